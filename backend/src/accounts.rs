@@ -0,0 +1,313 @@
+use actix_web::{http::StatusCode, ResponseError};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+use crate::config::AuthSettings;
+use crate::utils::now_secs;
+
+/// Name of the cookie holding the login token
+pub const SESSION_COOKIE: &str = "chess_session";
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidUsername,
+    InvalidPassword,
+    UsernameTaken,
+    InvalidCredentials,
+    Io(io::Error),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::InvalidUsername => {
+                write!(f, "usernames must be 3 to 20 letters, digits, `_` or `-`")
+            }
+            AccountError::InvalidPassword => {
+                write!(f, "passwords must be at least 8 characters long")
+            }
+            AccountError::UsernameTaken => write!(f, "this username is already taken"),
+            AccountError::InvalidCredentials => write!(f, "invalid username or password"),
+            AccountError::Io(e) => write!(f, "unable to access the account store: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl From<io::Error> for AccountError {
+    fn from(value: io::Error) -> Self {
+        AccountError::Io(value)
+    }
+}
+
+impl ResponseError for AccountError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AccountError::InvalidUsername | AccountError::InvalidPassword => {
+                StatusCode::BAD_REQUEST
+            }
+            AccountError::UsernameTaken => StatusCode::CONFLICT,
+            AccountError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            AccountError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub username: String,
+    /// Argon2 hash in the PHC string format
+    password_hash: String,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+}
+
+struct LoginToken {
+    username: String,
+    expires_at: SystemTime,
+}
+
+/// Registered players, persisted as a JSON file, and the tokens of everyone currently logged in.
+///
+/// Tokens only live in memory, a restart logs everybody out.
+pub struct Accounts {
+    path: PathBuf,
+    session_ttl: Duration,
+    accounts: RwLock<HashMap<String, Account>>,
+    tokens: RwLock<HashMap<String, LoginToken>>,
+}
+
+impl Accounts {
+    /// Open the account store at the configured path, a missing file yields an empty store
+    pub fn open(settings: &AuthSettings) -> io::Result<Self> {
+        let path = PathBuf::from(&settings.accounts_path);
+        let accounts: HashMap<String, Account> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        info!(accounts = accounts.len(), "loaded account store");
+
+        Ok(Self {
+            path,
+            session_ttl: Duration::from_secs(settings.session_ttl_secs),
+            accounts: RwLock::new(accounts),
+            tokens: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        self.session_ttl
+    }
+
+    pub fn get(&self, username: &str) -> Option<Account> {
+        self.accounts.read().unwrap().get(username).cloned()
+    }
+
+    /// Whether the name belongs to an account, ignoring case so that nobody can pass for
+    /// a registered player
+    pub fn is_registered(&self, name: &str) -> bool {
+        is_taken(&self.accounts.read().unwrap(), name)
+    }
+
+    /// Create a new account, the password hash is computed here so call it off the async runtime
+    pub fn register(&self, username: &str, password: &str) -> Result<Account, AccountError> {
+        validate_username(username)?;
+        if password.chars().count() < 8 {
+            return Err(AccountError::InvalidPassword);
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| AccountError::Io(io::Error::other(e.to_string())))?
+            .to_string();
+
+        let mut accounts = self.accounts.write().unwrap();
+        if is_taken(&accounts, username) {
+            return Err(AccountError::UsernameTaken);
+        }
+
+        let account = Account {
+            username: username.to_owned(),
+            password_hash,
+            created_at: now_secs(),
+        };
+        // the account only exists once it is on disk, a failed save leaves the name free
+        let mut updated = accounts.clone();
+        updated.insert(username.to_owned(), account.clone());
+        save(&self.path, &updated)?;
+        *accounts = updated;
+
+        info!(username, "registered account");
+
+        Ok(account)
+    }
+
+    /// Check the credentials and hand out a token that identifies the user until it expires
+    pub fn login(&self, username: &str, password: &str) -> Result<String, AccountError> {
+        let account = self.get(username).ok_or(AccountError::InvalidCredentials)?;
+
+        let hash = PasswordHash::new(&account.password_hash)
+            .map_err(|_| AccountError::InvalidCredentials)?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| AccountError::InvalidCredentials)?;
+
+        let token = nanoid::nanoid!(32);
+        let mut tokens = self.tokens.write().unwrap();
+        let now = SystemTime::now();
+        tokens.retain(|_, t| t.expires_at > now);
+        tokens.insert(
+            token.clone(),
+            LoginToken {
+                username: account.username,
+                expires_at: now + self.session_ttl,
+            },
+        );
+
+        Ok(token)
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.tokens.write().unwrap().remove(token);
+    }
+
+    /// The username a still valid token belongs to
+    pub fn authenticate(&self, token: &str) -> Option<String> {
+        self.tokens
+            .read()
+            .unwrap()
+            .get(token)
+            .filter(|t| t.expires_at > SystemTime::now())
+            .map(|t| t.username.clone())
+    }
+}
+
+fn is_taken(accounts: &HashMap<String, Account>, name: &str) -> bool {
+    accounts
+        .keys()
+        .any(|username| username.eq_ignore_ascii_case(name))
+}
+
+fn validate_username(username: &str) -> Result<(), AccountError> {
+    let valid_len = (3..=20).contains(&username.len());
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid_len && valid_chars {
+        Ok(())
+    } else {
+        Err(AccountError::InvalidUsername)
+    }
+}
+
+/// Write the store to a temporary file first so a crash never leaves a half written file behind
+fn save(path: &Path, accounts: &HashMap<String, Account>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string_pretty(accounts)?)?;
+    fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> AuthSettings {
+        let dir = std::env::temp_dir().join(format!("accounts-{}", nanoid::nanoid!(10)));
+        AuthSettings {
+            accounts_path: dir.join("accounts.json").to_string_lossy().into_owned(),
+            session_ttl_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn registered_accounts_log_in() {
+        let settings = settings();
+        let accounts = Accounts::open(&settings).unwrap();
+        accounts.register("alice", "correct horse").unwrap();
+
+        // only the argon2 hash of the password is kept
+        let stored = fs::read_to_string(&settings.accounts_path).unwrap();
+        assert!(!stored.contains("correct horse"));
+        assert!(accounts
+            .get("alice")
+            .unwrap()
+            .password_hash
+            .starts_with("$argon2"));
+
+        let token = accounts.login("alice", "correct horse").unwrap();
+        assert_eq!(accounts.authenticate(&token).as_deref(), Some("alice"));
+        accounts.logout(&token);
+        assert_eq!(accounts.authenticate(&token), None);
+
+        // the account outlives a restart
+        let reopened = Accounts::open(&settings).unwrap();
+        assert!(reopened.login("alice", "correct horse").is_ok());
+    }
+
+    #[test]
+    fn wrong_credentials_are_refused() {
+        let accounts = Accounts::open(&settings()).unwrap();
+        accounts.register("alice", "correct horse").unwrap();
+
+        assert!(matches!(
+            accounts.login("alice", "battery staple"),
+            Err(AccountError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            accounts.login("bob", "correct horse"),
+            Err(AccountError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn usernames_are_taken_ignoring_case() {
+        let accounts = Accounts::open(&settings()).unwrap();
+        accounts.register("Alice", "correct horse").unwrap();
+
+        assert!(accounts.is_registered("aLiCe"));
+        for username in ["Alice", "alice", "ALICE"] {
+            assert!(matches!(
+                accounts.register(username, "battery staple"),
+                Err(AccountError::UsernameTaken)
+            ));
+        }
+        assert!(accounts.get("alice").is_none());
+    }
+
+    #[test]
+    fn invalid_registrations_are_refused() {
+        let accounts = Accounts::open(&settings()).unwrap();
+
+        for username in ["al", "a".repeat(21).as_str(), "alice smith", "alice!"] {
+            assert!(matches!(
+                accounts.register(username, "correct horse"),
+                Err(AccountError::InvalidUsername)
+            ));
+        }
+        assert!(matches!(
+            accounts.register("alice", "short"),
+            Err(AccountError::InvalidPassword)
+        ));
+        assert!(!accounts.is_registered("alice"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Reviews a finished game with the engine: every position is evaluated, each move is judged
+//! by how many centipawns it gave away, and both players get an accuracy score.
+
+use serde::*;
+use std::io;
+
+use crate::chess::{Move, Outcome, Position};
+use crate::engine::{Score, SearchLimit, UciEngine};
+use crate::types::Color;
+
+/// Evaluations are capped so a missed mate doesn't swamp the averages
+const MAX_CENTIPAWNS: i32 = 1000;
+const INACCURACY_LOSS: u32 = 50;
+const MISTAKE_LOSS: u32 = 100;
+const BLUNDER_LOSS: u32 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn from_loss(centipawn_loss: u32) -> Option<Self> {
+        match centipawn_loss {
+            loss if loss >= BLUNDER_LOSS => Some(Judgement::Blunder),
+            loss if loss >= MISTAKE_LOSS => Some(Judgement::Mistake),
+            loss if loss >= INACCURACY_LOSS => Some(Judgement::Inaccuracy),
+            _ => None,
+        }
+    }
+
+    /// Numeric annotation glyph used by PGN: `?!`, `?` and `??`
+    pub fn nag(&self) -> u8 {
+        match self {
+            Judgement::Inaccuracy => 6,
+            Judgement::Mistake => 2,
+            Judgement::Blunder => 4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "Inaccuracy",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnotatedMove {
+    pub san: String,
+    /// Evaluation of the position after the move, from white's point of view
+    pub eval: Score,
+    /// The engine's choice, in SAN, when it differs from the move played
+    pub best_move: Option<String>,
+    pub centipawn_loss: u32,
+    pub judgement: Option<Judgement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameAnalysis {
+    pub depth: u32,
+    /// From 0 to 100, None when the player didn't move
+    pub white_accuracy: Option<f64>,
+    pub black_accuracy: Option<f64>,
+    pub moves: Vec<AnnotatedMove>,
+}
+
+/// An evaluated position, from white's point of view
+struct Evaluation {
+    score: Score,
+    centipawns: i32,
+    best_move: Option<Move>,
+}
+
+/// Chances of winning, from 0 to 100, for the side the centipawns are counted for
+fn win_percent(centipawns: i32) -> f64 {
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * f64::from(centipawns)).exp()) - 1.0)
+}
+
+/// How well a move kept the mover's winning chances, from 0 to 100
+fn move_accuracy(win_before: f64, win_after: f64) -> f64 {
+    if win_after >= win_before {
+        return 100.0;
+    }
+
+    (103.1668 * (-0.04354 * (win_before - win_after)).exp() - 3.1669).clamp(0.0, 100.0)
+}
+
+async fn evaluate(
+    engine: &mut UciEngine,
+    position: &Position,
+    depth: u32,
+) -> io::Result<Evaluation> {
+    let white_to_move = position.side_to_move() == Color::White;
+
+    // the engine has nothing to say about positions without moves
+    match position.outcome() {
+        Some(Outcome::Checkmate) => {
+            return Ok(Evaluation {
+                score: Score::Mate(0),
+                centipawns: match white_to_move {
+                    true => -MAX_CENTIPAWNS,
+                    false => MAX_CENTIPAWNS,
+                },
+                best_move: None,
+            })
+        }
+        Some(Outcome::Stalemate) => {
+            return Ok(Evaluation {
+                score: Score::Cp(0),
+                centipawns: 0,
+                best_move: None,
+            })
+        }
+        _ => {}
+    }
+
+    let analysis = engine
+        .analyse(&position.to_fen(), SearchLimit::Depth(depth))
+        .await?;
+    // the engine scores for the side to move
+    let score = match (analysis.score.unwrap_or(Score::Cp(0)), white_to_move) {
+        (score, true) => score,
+        (Score::Cp(cp), false) => Score::Cp(-cp),
+        (Score::Mate(moves), false) => Score::Mate(-moves),
+    };
+    let centipawns = match score {
+        Score::Cp(cp) => cp.clamp(-MAX_CENTIPAWNS, MAX_CENTIPAWNS),
+        Score::Mate(moves) if moves > 0 => MAX_CENTIPAWNS,
+        Score::Mate(_) => -MAX_CENTIPAWNS,
+    };
+
+    Ok(Evaluation {
+        score,
+        centipawns,
+        best_move: analysis.best_move.as_deref().and_then(Move::from_uci),
+    })
+}
+
+/// Evaluate every position of a game given as UCI moves from the starting position
+pub async fn analyse_game(
+    engine: &mut UciEngine,
+    moves: &[String],
+    depth: u32,
+) -> io::Result<GameAnalysis> {
+    let mut position = Position::default();
+    let mut before = evaluate(engine, &position, depth).await?;
+    let mut annotated = Vec::with_capacity(moves.len());
+    let mut accuracies: [Vec<f64>; 2] = [Vec::new(), Vec::new()];
+
+    for (ply, uci) in moves.iter().enumerate() {
+        let mv = Move::from_uci(uci)
+            .filter(|mv| position.is_legal(*mv))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("move {} ({uci}) is illegal", ply + 1),
+                )
+            })?;
+        let mover = position.side_to_move();
+        let san = position.san(mv);
+        let best_move = before
+            .best_move
+            .filter(|best| *best != mv && position.is_legal(*best))
+            .map(|best| position.san(best));
+
+        let next = position.play(mv);
+        let after = evaluate(engine, &next, depth).await?;
+
+        // both evaluations from the mover's point of view
+        let sign = match mover {
+            Color::White => 1,
+            _ => -1,
+        };
+        let cp_before = before.centipawns * sign;
+        // playing the engine's move can't lose anything, whatever the next search says
+        let cp_after = match best_move {
+            Some(_) => after.centipawns * sign,
+            None => cp_before,
+        };
+        let centipawn_loss = (cp_before - cp_after).max(0) as u32;
+        accuracies[usize::from(mover == Color::Black)]
+            .push(move_accuracy(win_percent(cp_before), win_percent(cp_after)));
+
+        annotated.push(AnnotatedMove {
+            san,
+            eval: after.score,
+            best_move,
+            centipawn_loss,
+            judgement: Judgement::from_loss(centipawn_loss),
+        });
+
+        position = next;
+        before = after;
+    }
+
+    let average = |values: &[f64]| {
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+
+    Ok(GameAnalysis {
+        depth,
+        white_accuracy: average(&accuracies[0]),
+        black_accuracy: average(&accuracies[1]),
+        moves: annotated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn judgements() {
+        assert_eq!(Judgement::from_loss(0), None);
+        assert_eq!(Judgement::from_loss(49), None);
+        assert_eq!(Judgement::from_loss(50), Some(Judgement::Inaccuracy));
+        assert_eq!(Judgement::from_loss(99), Some(Judgement::Inaccuracy));
+        assert_eq!(Judgement::from_loss(100), Some(Judgement::Mistake));
+        assert_eq!(Judgement::from_loss(299), Some(Judgement::Mistake));
+        assert_eq!(Judgement::from_loss(300), Some(Judgement::Blunder));
+        assert_eq!(Judgement::from_loss(2000), Some(Judgement::Blunder));
+    }
+
+    #[test]
+    fn accuracy() {
+        assert_eq!(win_percent(0), 50.0);
+        assert!(win_percent(300) > 70.0 && win_percent(-300) < 30.0);
+
+        assert_eq!(move_accuracy(60.0, 60.0), 100.0);
+        assert_eq!(move_accuracy(40.0, 60.0), 100.0);
+        assert!(move_accuracy(60.0, 50.0) > move_accuracy(60.0, 30.0));
+        assert_eq!(move_accuracy(100.0, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,287 @@
+use serde::*;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tracing::info;
+
+use crate::analysis::GameAnalysis;
+use crate::chess::VariantKind;
+use crate::config::ArchiveSettings;
+use crate::openings::Opening;
+use crate::rating::{Pool, RatingChanges};
+use crate::types::{Color, GameResult, TimeControl, Visibility};
+
+/// A finished game as it is kept in the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub id: String,
+    pub white_name: String,
+    pub black_name: String,
+    /// Accounts of the players, None for guests
+    pub white_user: Option<String>,
+    pub black_user: Option<String>,
+    pub result: GameResult,
+    pub rated: bool,
+    pub time_control: Option<TimeControl>,
+    /// Games archived before variants existed were all standard chess
+    #[serde(default)]
+    pub variant: VariantKind,
+    /// Games archived before games could be hidden were all public
+    #[serde(default)]
+    pub visibility: Visibility,
+    pub rating_changes: Option<RatingChanges>,
+    /// Seconds since the unix epoch
+    pub ended_at: u64,
+    /// In UCI notation
+    #[serde(default)]
+    pub moves: Vec<String>,
+    /// When each move was played, in milliseconds since the unix epoch. Games archived
+    /// before moves were timed have none.
+    #[serde(default)]
+    pub move_times: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening: Option<Opening>,
+    /// Set once the game was reviewed by the engine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<GameAnalysis>,
+    /// Set once the game was searched for puzzles
+    #[serde(default)]
+    pub mined: bool,
+}
+
+impl GameRecord {
+    pub fn pool(&self) -> Option<Pool> {
+        self.time_control.as_ref().map(Pool::for_time_control)
+    }
+
+    /// Whether the game can be shown to the account, or to anyone without one. Private
+    /// games are only shown to their players.
+    pub fn visible_to(&self, username: Option<&str>) -> bool {
+        self.visibility != Visibility::Private
+            || username.is_some_and(|username| self.color_of(username).is_some())
+    }
+
+    /// The color the account played, None if it didn't play in this game
+    pub fn color_of(&self, username: &str) -> Option<Color> {
+        if self.white_user.as_deref() == Some(username) {
+            Some(Color::White)
+        } else if self.black_user.as_deref() == Some(username) {
+            Some(Color::Black)
+        } else {
+            None
+        }
+    }
+
+    /// The game from the point of view of the account, None if it didn't play in this game
+    pub fn outcome_for(&self, username: &str) -> Option<Outcome> {
+        let color = self.color_of(username)?;
+
+        Some(match (self.result, color) {
+            (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => {
+                Outcome::Win
+            }
+            (GameResult::WhiteWins, _) | (GameResult::BlackWins, _) => Outcome::Loss,
+            (GameResult::Draw, _) => Outcome::Draw,
+            (GameResult::Aborted, _) => Outcome::Aborted,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+    Aborted,
+}
+
+/// The player's unbroken run of identical results, counting back from their latest game
+#[derive(Debug, Clone, Serialize)]
+pub struct Streak {
+    pub outcome: Outcome,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerStats {
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    pub streak: Option<Streak>,
+}
+
+impl PlayerStats {
+    /// Tally the account's results, `records` must be ordered from oldest to newest
+    pub fn from_records<'a>(
+        username: &str,
+        records: impl DoubleEndedIterator<Item = &'a GameRecord>,
+    ) -> Self {
+        let mut stats = PlayerStats::default();
+        let mut streak_running = true;
+
+        // aborted games don't count towards anything
+        let outcomes = records
+            .rev()
+            .filter_map(|record| record.outcome_for(username))
+            .filter(|outcome| *outcome != Outcome::Aborted);
+
+        for outcome in outcomes {
+            stats.games += 1;
+            match outcome {
+                Outcome::Win => stats.wins += 1,
+                Outcome::Draw => stats.draws += 1,
+                Outcome::Loss => stats.losses += 1,
+                Outcome::Aborted => {}
+            }
+
+            match &mut stats.streak {
+                None => stats.streak = Some(Streak { outcome, length: 1 }),
+                Some(streak) if streak_running && streak.outcome == outcome => streak.length += 1,
+                Some(_) => streak_running = false,
+            }
+        }
+
+        stats
+    }
+}
+
+/// Every finished game, kept in memory and appended to a JSON lines file
+pub struct GameArchive {
+    path: PathBuf,
+    records: RwLock<Vec<GameRecord>>,
+}
+
+impl GameArchive {
+    /// Open the archive at the configured path, a missing file yields an empty archive
+    pub fn open(settings: &ArchiveSettings) -> io::Result<Self> {
+        let path = PathBuf::from(&settings.games_path);
+        let records = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<GameRecord>, _>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        info!(games = records.len(), "loaded game archive");
+
+        Ok(Self {
+            path,
+            records: RwLock::new(records),
+        })
+    }
+
+    pub fn record(&self, record: GameRecord) -> io::Result<()> {
+        let mut records = self.records.write().unwrap();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+
+        records.push(record);
+
+        Ok(())
+    }
+
+    /// Store the engine review of a game unless a deeper one is already kept, returns
+    /// false if the game isn't archived
+    pub fn attach_analysis(&self, id: &str, analysis: GameAnalysis) -> io::Result<bool> {
+        self.update(id, |record| {
+            if record
+                .analysis
+                .as_ref()
+                .is_none_or(|kept| kept.depth < analysis.depth)
+            {
+                record.analysis = Some(analysis);
+            }
+        })
+    }
+
+    /// Remember that the game was searched for puzzles, returns false if the game isn't
+    /// archived
+    pub fn mark_mined(&self, id: &str) -> io::Result<bool> {
+        self.update(id, |record| record.mined = true)
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut GameRecord)) -> io::Result<bool> {
+        let mut records = self.records.write().unwrap();
+        let Some(record) = records.iter_mut().find(|record| record.id == id) else {
+            return Ok(false);
+        };
+        change(record);
+
+        // the whole file is rewritten, through a temporary file so a crash can't truncate it
+        let mut contents = String::new();
+        for record in records.iter() {
+            contents.push_str(&serde_json::to_string(record)?);
+            contents.push('\n');
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(true)
+    }
+
+    /// The latest standard games that weren't searched for puzzles yet, newest first
+    pub fn unmined_games(&self, limit: usize) -> Vec<GameRecord> {
+        self.records
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| {
+                !record.mined
+                    && record.variant == VariantKind::Standard
+                    && record.visible_to(None)
+                    && !record.moves.is_empty()
+            })
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<GameRecord> {
+        self.records
+            .read()
+            .unwrap()
+            .iter()
+            .find(|record| record.id == id)
+            .cloned()
+    }
+
+    /// Results of the account, optionally only for the games rated in `pool`
+    pub fn stats(&self, username: &str, pool: Option<Pool>) -> PlayerStats {
+        let records = self.records.read().unwrap();
+
+        PlayerStats::from_records(
+            username,
+            records
+                .iter()
+                .filter(|record| pool.is_none() || (record.rated && record.pool() == pool)),
+        )
+    }
+
+    /// The account's latest games that anyone may see, newest first
+    pub fn recent_games(&self, username: &str, limit: usize) -> Vec<GameRecord> {
+        self.records
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| record.color_of(username).is_some() && record.visible_to(None))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
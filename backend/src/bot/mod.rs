@@ -0,0 +1,251 @@
+//! A computer opponent living inside the server.
+//!
+//! The bot is seated like any other player: it gets a session whose address points to a
+//! [`BotActor`] instead of a websocket, receives the same messages a client would and
+//! answers with regular [`MakeMove`] messages.
+
+use actix::prelude::*;
+use rand::seq::SliceRandom;
+use serde::*;
+use std::io;
+use std::time::Duration;
+use tracing::{debug, info_span, warn, Span};
+
+use crate::chess::{square_name, Move, Position};
+use crate::config::BotSettings;
+use crate::engine::{EnginePool, SearchLimit};
+use crate::types::{ChessMove, Color};
+use crate::websocket::{
+    messages::{ClientMessage, Disconnect, MakeMove, Type},
+    server::WsChessServer,
+    servers::{in_memory::Player, WsServer},
+    session::{Close, Message},
+};
+
+pub mod eval;
+pub mod search;
+
+pub const MIN_SKILL: u8 = 1;
+pub const MAX_SKILL: u8 = 8;
+/// How far below the best move, in centipawns, each missing skill level lets the bot play
+const MARGIN_PER_LEVEL: i32 = 40;
+
+fn default_skill() -> u8 {
+    MAX_SKILL / 2
+}
+
+/// Sent along with `CreateGame` to play against the computer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BotOptions {
+    /// From 1 to 8, higher levels search deeper and stick to the best moves
+    #[serde(default = "default_skill")]
+    pub skill: u8,
+    /// Let the configured UCI engine choose the moves instead of the built-in search
+    #[serde(default)]
+    pub engine: bool,
+}
+
+impl BotOptions {
+    pub fn skill(&self) -> u8 {
+        self.skill.clamp(MIN_SKILL, MAX_SKILL)
+    }
+
+    /// The name the bot's session goes by
+    pub fn name(&self) -> String {
+        match self.engine {
+            true => "Computer (engine)".to_owned(),
+            false => format!("Computer (level {})", self.skill()),
+        }
+    }
+}
+
+pub struct BotActor<T: WsServer> {
+    /// The id of the bot's session
+    pub id: String,
+    server_addr: Addr<WsChessServer<T>>,
+    skill: u8,
+    settings: BotSettings,
+    /// Plays with the built-in search when None
+    engines: Option<EnginePool>,
+    /// Set once the opponent is known
+    color: Color,
+    position: Position,
+    span: Span,
+}
+
+impl<T: WsServer> BotActor<T> {
+    pub fn new(
+        id: String,
+        server_addr: Addr<WsChessServer<T>>,
+        options: BotOptions,
+        settings: BotSettings,
+        engines: Option<EnginePool>,
+    ) -> Self {
+        Self {
+            span: info_span!("bot", session_id = %id, skill = options.skill()),
+            id,
+            server_addr,
+            skill: options.skill(),
+            settings,
+            engines,
+            color: Color::None,
+            position: Position::default(),
+        }
+    }
+
+    fn think(&mut self, ctx: &mut Context<Self>) {
+        if self.position.side_to_move() != self.color || self.position.outcome().is_some() {
+            return;
+        }
+
+        match self.engines.clone() {
+            Some(engines) => self.ask_engine(engines, ctx),
+            None => self.search(ctx),
+        }
+    }
+
+    /// Let a UCI engine from the pool pick the move, falling back to the built-in search
+    /// if every engine is busy or it can't
+    fn ask_engine(&mut self, engines: EnginePool, ctx: &mut Context<Self>) {
+        let fen = self.position.to_fen();
+        let settings = engines.settings();
+        let skill_level = Some(settings.skill_level);
+        let limit = SearchLimit::MoveTime(Duration::from_millis(settings.move_time_ms));
+
+        let analysis = async move {
+            let mut engine = engines.acquire(skill_level).await?;
+            engine.analyse(&fen, limit).await
+        };
+        ctx.spawn(analysis.into_actor(self).map(|res, bot, ctx| {
+            let _span = bot.span.clone().entered();
+            let best_move = match res {
+                Ok(analysis) => analysis
+                    .best_move
+                    .as_deref()
+                    .and_then(Move::from_uci)
+                    .filter(|mv| bot.position.is_legal(*mv)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    debug!("every engine is busy, using the built-in search");
+                    None
+                }
+                Err(e) => {
+                    warn!(error = %e, "engine failed, using the built-in search");
+                    None
+                }
+            };
+            match best_move {
+                Some(mv) => bot.play(mv),
+                None => bot.search(ctx),
+            }
+        }));
+    }
+
+    /// Search for a move off the actor's thread, then play it
+    fn search(&mut self, ctx: &mut Context<Self>) {
+        let position = self.position.clone();
+        let depth = self.skill.min(self.settings.max_depth);
+        let time_limit = Duration::from_millis(self.settings.move_time_ms);
+        let margin = i32::from(MAX_SKILL - self.skill) * MARGIN_PER_LEVEL;
+
+        let search = tokio::task::spawn_blocking(move || {
+            search::search(&position, depth, time_limit, margin)
+        });
+        ctx.spawn(search.into_actor(self).map(|res, bot, _| {
+            let _span = bot.span.clone().entered();
+            match res {
+                Ok(Some(result)) => {
+                    debug!(
+                        depth = result.depth,
+                        nodes = result.nodes,
+                        best = ?result.best(),
+                        "search done"
+                    );
+                    if let Some((mv, _)) = result.candidates.choose(&mut rand::thread_rng()) {
+                        bot.play(*mv);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!(error = %e, "bot search failed"),
+            }
+        }));
+    }
+
+    fn play(&mut self, mv: Move) {
+        debug!(%mv, "playing move");
+        self.position = self.position.play(mv);
+
+        self.server_addr.do_send(MakeMove {
+            from: square_name(mv.from),
+            to: square_name(mv.to),
+            promotion_piece: mv.promotion.map(|kind| kind.name().to_owned()),
+            drop_piece: None,
+            game_id: String::new(),
+            player_id: self.id.clone(),
+        });
+    }
+
+    fn opponent_moved(&mut self, chess_move: ChessMove, ctx: &mut Context<Self>) {
+        let mv = Move::from_parts(
+            &chess_move.from,
+            &chess_move.to,
+            chess_move.promotion_piece.as_deref(),
+        );
+        match mv {
+            Some(mv)
+                if self.position.side_to_move() != self.color && self.position.is_legal(mv) =>
+            {
+                self.position = self.position.play(mv);
+                self.think(ctx);
+            }
+            _ => warn!(?chess_move, "ignoring illegal move from the opponent"),
+        }
+    }
+}
+
+impl<T: WsServer> Actor for BotActor<T> {
+    type Context = Context<Self>;
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        let _span = self.span.enter();
+        debug!("bot leaving");
+
+        self.server_addr.do_send(Disconnect::new(self.id.clone()));
+        Running::Stop
+    }
+}
+
+impl<T: WsServer> Handler<Message> for BotActor<T> {
+    type Result = ();
+
+    /// React to what the server would send to a client
+    fn handle(&mut self, msg: Message, ctx: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        let Ok(msg) = serde_json::from_str::<ClientMessage>(&msg.0) else {
+            return;
+        };
+
+        match msg.m_type {
+            Type::OpponentJoined => {
+                if let Ok(opponent) = serde_json::from_value::<Player>(msg.payload) {
+                    self.color = opponent.color.opposite();
+                    self.think(ctx);
+                }
+            }
+            Type::MakeMove => {
+                if let Ok(chess_move) = serde_json::from_value::<ChessMove>(msg.payload) {
+                    self.opponent_moved(chess_move, ctx);
+                }
+            }
+            Type::GameOver => ctx.stop(),
+            _ => {}
+        }
+    }
+}
+
+impl<T: WsServer> Handler<Close> for BotActor<T> {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
@@ -0,0 +1,259 @@
+use std::time::{Duration, Instant};
+
+use super::eval::{evaluate, piece_value};
+use crate::chess::{Move, Position};
+
+/// Score of being checkmated at the root, mates further away score a bit less
+pub const MATE: i32 = 100_000;
+const INFINITY: i32 = MATE + 1;
+/// How many nodes are searched between two looks at the clock
+const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+/// The outcome of the deepest iteration that finished in time
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub depth: u8,
+    /// Root moves scoring within the requested margin of the best one, best first
+    pub candidates: Vec<(Move, i32)>,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    pub fn best(&self) -> Option<(Move, i32)> {
+        self.candidates.first().copied()
+    }
+}
+
+struct Searcher {
+    deadline: Instant,
+    nodes: u64,
+    /// The first iteration always completes so there is a move to play
+    can_abort: bool,
+    aborted: bool,
+}
+
+/// Search the position with iterative deepening until `max_depth` is reached or the time is up.
+///
+/// Every root move scoring at most `margin` centipawns below the best move is scored exactly,
+/// which lets weaker levels pick any of them instead of always playing the best move.
+/// Returns None when there is no legal move.
+pub fn search(
+    position: &Position,
+    max_depth: u8,
+    time_limit: Duration,
+    margin: i32,
+) -> Option<SearchResult> {
+    let mut root_moves = position.legal_moves();
+    if root_moves.is_empty() {
+        return None;
+    }
+    order_moves(position, &mut root_moves);
+
+    let mut searcher = Searcher {
+        deadline: Instant::now() + time_limit,
+        nodes: 0,
+        can_abort: false,
+        aborted: false,
+    };
+    let mut result = None;
+
+    for depth in 1..=max_depth.max(1) {
+        let mut scored = Vec::with_capacity(root_moves.len());
+        let mut best = -INFINITY;
+        searcher.can_abort = depth > 1;
+
+        for &mv in &root_moves {
+            let alpha = best.saturating_sub(margin).max(-INFINITY);
+            let score = -searcher.negamax(&position.play(mv), depth - 1, 1, -INFINITY, -alpha);
+            if searcher.aborted {
+                break;
+            }
+            best = best.max(score);
+            // a score at or below alpha is only an upper bound
+            scored.push((mv, score, score > alpha));
+        }
+        if searcher.aborted {
+            break;
+        }
+
+        scored.sort_by_key(|(_, score, _)| std::cmp::Reverse(*score));
+        // the next iteration starts with the best moves of this one
+        root_moves = scored.iter().map(|(mv, _, _)| *mv).collect();
+        result = Some(SearchResult {
+            depth,
+            candidates: scored
+                .into_iter()
+                .filter(|(_, score, exact)| *exact && *score >= best - margin)
+                .map(|(mv, score, _)| (mv, score))
+                .collect(),
+            nodes: searcher.nodes,
+        });
+
+        // no point looking deeper once a forced mate is found
+        if best.abs() >= MATE - i32::from(max_depth) || searcher.out_of_time() {
+            break;
+        }
+    }
+
+    result
+}
+
+impl Searcher {
+    fn out_of_time(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    fn tick(&mut self) {
+        self.nodes += 1;
+        if self.can_abort && self.nodes.is_multiple_of(CLOCK_CHECK_INTERVAL) && self.out_of_time() {
+            self.aborted = true;
+        }
+    }
+
+    fn negamax(
+        &mut self,
+        position: &Position,
+        depth: u8,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.tick();
+        if self.aborted {
+            return 0;
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return match position.in_check() {
+                true => -MATE + ply,
+                false => 0,
+            };
+        }
+        if position.halfmove_clock() >= 100 || position.insufficient_material() {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(position, alpha, beta);
+        }
+
+        order_moves(position, &mut moves);
+        for mv in moves {
+            let score = -self.negamax(&position.play(mv), depth - 1, ply + 1, -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    /// Only look at captures and promotions so the evaluation isn't taken in the middle of an exchange
+    fn quiescence(&mut self, position: &Position, mut alpha: i32, beta: i32) -> i32 {
+        self.tick();
+        if self.aborted {
+            return 0;
+        }
+
+        let stand_pat = evaluate(position);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves: Vec<Move> = position
+            .legal_moves()
+            .into_iter()
+            .filter(|&mv| position.is_capture(mv) || mv.promotion.is_some())
+            .collect();
+        order_moves(position, &mut moves);
+
+        for mv in moves {
+            let score = -self.quiescence(&position.play(mv), -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+}
+
+/// Try the most valuable victims taken by the least valuable attackers first
+fn order_moves(position: &Position, moves: &mut [Move]) {
+    moves.sort_by_cached_key(|&mv| {
+        let victim = match position.piece_at(mv.to) {
+            Some(piece) => piece_value(piece.kind),
+            None if position.is_capture(mv) => piece_value(crate::chess::PieceKind::Pawn),
+            None => 0,
+        };
+        let attacker = position
+            .piece_at(mv.from)
+            .map(|piece| piece_value(piece.kind))
+            .unwrap_or_default();
+        let promotion = mv.promotion.map(piece_value).unwrap_or_default();
+
+        match victim + promotion {
+            0 => 0,
+            gain => -(gain * 10 - attacker / 10),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best(fen: &str, depth: u8) -> (Move, i32) {
+        let position = Position::from_fen(fen).unwrap();
+        search(&position, depth, Duration::from_secs(60), 0)
+            .and_then(|result| result.best())
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let (mv, score) = best("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+
+        assert_eq!(mv.uci(), "a1a8");
+        assert_eq!(score, MATE - 1);
+    }
+
+    #[test]
+    fn finds_mate_in_two() {
+        // either rook has to cut the king off before the other one can mate
+        let (mv, score) = best("k7/8/8/8/8/8/6R1/4K2R w - - 0 1", 4);
+
+        assert!(["g2g7", "h1h7"].contains(&mv.uci().as_str()));
+        assert_eq!(score, MATE - 3);
+    }
+
+    #[test]
+    fn defends_against_mate_in_one() {
+        // black has to give the king some air or cover the back rank
+        let (mv, _) = best("6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1", 3);
+        let after = Position::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1")
+            .unwrap()
+            .play(mv);
+
+        assert!(after
+            .legal_moves()
+            .into_iter()
+            .all(|reply| after.play(reply).outcome().is_none()));
+    }
+
+    #[test]
+    fn no_move_without_legal_moves() {
+        let stalemate = Position::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+
+        assert!(search(&stalemate, 3, Duration::from_secs(1), 0).is_none());
+    }
+}
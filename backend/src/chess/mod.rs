@@ -0,0 +1,279 @@
+//! Chess rules on the server: positions, FEN, legal move generation and SAN
+//!
+//! Squares are numbered from 0 (a1) to 63 (h8), rank by rank.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use crate::types::Color;
+
+mod movegen;
+mod position;
+mod san;
+pub mod variants;
+
+pub use position::{FenError, Position, STARTING_FEN};
+pub use variants::{Board, CheckDirection, Kriegspiel, Variant, VariantKind, VariantState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl PieceKind {
+    /// Promotion pieces are sent either as a letter or as the piece's name
+    pub fn from_promotion(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "n" | "knight" => Some(PieceKind::Knight),
+            "b" | "bishop" => Some(PieceKind::Bishop),
+            "r" | "rook" => Some(PieceKind::Rook),
+            "q" | "queen" => Some(PieceKind::Queen),
+            _ => None,
+        }
+    }
+
+    /// The name the client uses for promotion pieces
+    pub fn name(&self) -> &'static str {
+        match self {
+            PieceKind::Pawn => "pawn",
+            PieceKind::Knight => "knight",
+            PieceKind::Bishop => "bishop",
+            PieceKind::Rook => "rook",
+            PieceKind::Queen => "queen",
+            PieceKind::King => "king",
+        }
+    }
+
+    /// Piece names as sent by clients, for promotions and drops alike
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "p" | "pawn" => Some(PieceKind::Pawn),
+            _ => Self::from_promotion(name),
+        }
+    }
+
+    /// Lowercase letter as used by FEN and UCI
+    pub fn letter(&self) -> char {
+        match self {
+            PieceKind::Pawn => 'p',
+            PieceKind::Knight => 'n',
+            PieceKind::Bishop => 'b',
+            PieceKind::Rook => 'r',
+            PieceKind::Queen => 'q',
+            PieceKind::King => 'k',
+        }
+    }
+
+    fn from_letter(letter: char) -> Option<Self> {
+        match letter.to_ascii_lowercase() {
+            'p' => Some(PieceKind::Pawn),
+            'n' => Some(PieceKind::Knight),
+            'b' => Some(PieceKind::Bishop),
+            'r' => Some(PieceKind::Rook),
+            'q' => Some(PieceKind::Queen),
+            'k' => Some(PieceKind::King),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub color: Color,
+    pub kind: PieceKind,
+}
+
+impl Piece {
+    pub fn new(color: Color, kind: PieceKind) -> Self {
+        Self { color, kind }
+    }
+
+    /// FEN letter, uppercase for white
+    pub fn fen_letter(&self) -> char {
+        match self.color {
+            Color::White => self.kind.letter().to_ascii_uppercase(),
+            _ => self.kind.letter(),
+        }
+    }
+}
+
+pub fn file_of(square: u8) -> u8 {
+    square % 8
+}
+
+pub fn rank_of(square: u8) -> u8 {
+    square / 8
+}
+
+/// Parse a square name such as `e4`
+pub fn parse_square(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let file = chars.next()?;
+    let rank = chars.next()?;
+    if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+
+    Some((rank as u8 - b'1') * 8 + (file as u8 - b'a'))
+}
+
+pub fn square_name(square: u8) -> String {
+    format!(
+        "{}{}",
+        (b'a' + file_of(square)) as char,
+        (b'1' + rank_of(square)) as char
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<PieceKind>,
+    /// The piece put on the board by a crazyhouse drop, `from` is the same as `to` then
+    pub drop: Option<PieceKind>,
+}
+
+impl Move {
+    pub fn new(from: u8, to: u8, promotion: Option<PieceKind>) -> Self {
+        Self {
+            from,
+            to,
+            promotion,
+            drop: None,
+        }
+    }
+
+    /// Put a piece from the pocket on the square
+    pub fn drop(kind: PieceKind, to: u8) -> Self {
+        Self {
+            from: to,
+            to,
+            promotion: None,
+            drop: Some(kind),
+        }
+    }
+
+    /// Build a move from the squares and promotion piece sent by a client
+    pub fn from_parts(from: &str, to: &str, promotion: Option<&str>) -> Option<Self> {
+        let promotion = match promotion {
+            Some(name) if !name.is_empty() => Some(PieceKind::from_promotion(name)?),
+            _ => None,
+        };
+
+        Some(Self::new(parse_square(from)?, parse_square(to)?, promotion))
+    }
+
+    /// Build a move from what a client sent, a drop when it names the dropped piece
+    pub fn from_client(
+        from: &str,
+        to: &str,
+        promotion: Option<&str>,
+        drop: Option<&str>,
+    ) -> Option<Self> {
+        match drop {
+            Some(piece) => Self::drop_from_parts(piece, to),
+            None => Self::from_parts(from, to, promotion),
+        }
+    }
+
+    fn drop_from_parts(piece: &str, to: &str) -> Option<Self> {
+        Some(Self::drop(PieceKind::from_name(piece)?, parse_square(to)?))
+    }
+
+    /// Parse long algebraic notation as used by UCI, e.g. `e7e8q` or the drop `N@f3`
+    pub fn from_uci(uci: &str) -> Option<Self> {
+        if !(4..=5).contains(&uci.len()) || !uci.is_ascii() {
+            return None;
+        }
+        if let Some(square) = uci.strip_prefix(|c: char| c.is_ascii_alphabetic()) {
+            if let Some(square) = square.strip_prefix('@') {
+                return Self::drop_from_parts(&uci[0..1], square);
+            }
+        }
+
+        Self::from_parts(&uci[0..2], &uci[2..4], uci.get(4..))
+    }
+
+    pub fn uci(&self) -> String {
+        if let Some(kind) = self.drop {
+            return format!(
+                "{}@{}",
+                kind.letter().to_ascii_uppercase(),
+                square_name(self.to)
+            );
+        }
+
+        let mut uci = format!("{}{}", square_name(self.from), square_name(self.to));
+        if let Some(promotion) = self.promotion {
+            uci.push(promotion.letter());
+        }
+        uci
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.uci())
+    }
+}
+
+/// Moves are stored in UCI notation
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.uci())
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uci = String::deserialize(deserializer)?;
+        Self::from_uci(&uci).ok_or_else(|| de::Error::custom(format!("invalid move: {uci}")))
+    }
+}
+
+/// Why a position has no more moves to play, or can't lead to a win anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The side to move is checkmated
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    /// Fifty moves by each side without a capture or pawn move
+    FiftyMoves,
+    /// Three-check: the side to move has been checked for the third time
+    ThreeChecks,
+    /// King of the Hill: the opponent's king reached one of the four center squares
+    KingOfTheHill,
+    /// Atomic: the side to move's king was blown up
+    KingExploded,
+    /// Horde: every white piece was captured
+    HordeDestroyed,
+    /// Racing Kings: a king reached the eighth rank and the other can't follow
+    RaceWon,
+    /// Racing Kings: both kings reached the eighth rank
+    RaceTied,
+    /// Fog of War: the side to move's king was captured
+    KingCaptured,
+}
+
+impl Outcome {
+    /// Whether somebody won, every other outcome is a draw
+    pub fn is_decisive(&self) -> bool {
+        matches!(
+            self,
+            Outcome::Checkmate
+                | Outcome::ThreeChecks
+                | Outcome::KingOfTheHill
+                | Outcome::KingExploded
+                | Outcome::HordeDestroyed
+                | Outcome::RaceWon
+                | Outcome::KingCaptured
+        )
+    }
+}
@@ -0,0 +1,307 @@
+use super::position::{BLACK_KINGSIDE, BLACK_QUEENSIDE, WHITE_KINGSIDE, WHITE_QUEENSIDE};
+use super::{file_of, rank_of, Move, Piece, PieceKind, Position};
+use crate::types::Color;
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const PROMOTIONS: [PieceKind; 4] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+];
+
+/// The square reached by moving `df` files and `dr` ranks, if it's on the board
+fn offset(square: u8, df: i8, dr: i8) -> Option<u8> {
+    let file = file_of(square) as i8 + df;
+    let rank = rank_of(square) as i8 + dr;
+    if !(0..8).contains(&file) || !(0..8).contains(&rank) {
+        return None;
+    }
+
+    Some((rank * 8 + file) as u8)
+}
+
+fn pawn_direction(color: Color) -> i8 {
+    match color {
+        Color::White => 1,
+        _ => -1,
+    }
+}
+
+impl Position {
+    /// Whether any piece of `by` attacks the square
+    pub fn is_attacked(&self, square: u8, by: Color) -> bool {
+        let holds = |sq: Option<u8>, kinds: &[PieceKind]| {
+            sq.and_then(|sq| self.piece_at(sq))
+                .is_some_and(|p| p.color == by && kinds.contains(&p.kind))
+        };
+
+        // a pawn attacks the square if it sits diagonally behind it from its own point of view
+        let back = -pawn_direction(by);
+        if holds(offset(square, -1, back), &[PieceKind::Pawn])
+            || holds(offset(square, 1, back), &[PieceKind::Pawn])
+        {
+            return true;
+        }
+
+        if KNIGHT_STEPS
+            .iter()
+            .any(|&(df, dr)| holds(offset(square, df, dr), &[PieceKind::Knight]))
+        {
+            return true;
+        }
+        if KING_STEPS
+            .iter()
+            .any(|&(df, dr)| holds(offset(square, df, dr), &[PieceKind::King]))
+        {
+            return true;
+        }
+
+        let sliders = [
+            (ROOK_DIRECTIONS, [PieceKind::Rook, PieceKind::Queen]),
+            (BISHOP_DIRECTIONS, [PieceKind::Bishop, PieceKind::Queen]),
+        ];
+        for (directions, kinds) in sliders {
+            for (df, dr) in directions {
+                let mut current = square;
+                while let Some(next) = offset(current, df, dr) {
+                    if let Some(piece) = self.piece_at(next) {
+                        if piece.color == by && kinds.contains(&piece.kind) {
+                            return true;
+                        }
+                        break;
+                    }
+                    current = next;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Every move of the side to move that doesn't leave its own king in check
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let color = self.side_to_move;
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|&mv| {
+                let next = self.play(mv);
+                next.king_square(color)
+                    .is_some_and(|king| !next.is_attacked(king, color.opposite()))
+            })
+            .collect()
+    }
+
+    /// Moves following the piece movement rules, without checking king safety
+    pub(super) fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let color = self.side_to_move;
+        let mut moves = Vec::with_capacity(48);
+
+        for (from, piece) in self.pieces().filter(|(_, p)| p.color == color) {
+            match piece.kind {
+                PieceKind::Pawn => self.pawn_moves(from, color, &mut moves),
+                PieceKind::Knight => self.step_moves(from, color, &KNIGHT_STEPS, &mut moves),
+                PieceKind::Bishop => self.slide_moves(from, color, &BISHOP_DIRECTIONS, &mut moves),
+                PieceKind::Rook => self.slide_moves(from, color, &ROOK_DIRECTIONS, &mut moves),
+                PieceKind::Queen => {
+                    self.slide_moves(from, color, &ROOK_DIRECTIONS, &mut moves);
+                    self.slide_moves(from, color, &BISHOP_DIRECTIONS, &mut moves);
+                }
+                PieceKind::King => {
+                    self.step_moves(from, color, &KING_STEPS, &mut moves);
+                    self.castling_moves(from, color, &mut moves);
+                }
+            }
+        }
+
+        moves
+    }
+
+    fn pawn_moves(&self, from: u8, color: Color, moves: &mut Vec<Move>) {
+        let direction = pawn_direction(color);
+        let (start_rank, last_rank) = match color {
+            Color::White => (1, 7),
+            _ => (6, 0),
+        };
+        let mut push = |to: u8| match rank_of(to) == last_rank {
+            true => moves.extend(
+                PROMOTIONS
+                    .iter()
+                    .map(|&kind| Move::new(from, to, Some(kind))),
+            ),
+            false => moves.push(Move::new(from, to, None)),
+        };
+
+        if let Some(one) = offset(from, 0, direction).filter(|&sq| self.piece_at(sq).is_none()) {
+            push(one);
+            if rank_of(from) == start_rank {
+                if let Some(two) =
+                    offset(from, 0, 2 * direction).filter(|&sq| self.piece_at(sq).is_none())
+                {
+                    push(two);
+                }
+            }
+        }
+
+        for df in [-1, 1] {
+            let Some(to) = offset(from, df, direction) else {
+                continue;
+            };
+            let captures = self
+                .piece_at(to)
+                .is_some_and(|target| target.color != color);
+            if captures || self.en_passant == Some(to) {
+                push(to);
+            }
+        }
+    }
+
+    fn step_moves(&self, from: u8, color: Color, steps: &[(i8, i8)], moves: &mut Vec<Move>) {
+        for &(df, dr) in steps {
+            if let Some(to) = offset(from, df, dr) {
+                if self.piece_at(to).is_none_or(|target| target.color != color) {
+                    moves.push(Move::new(from, to, None));
+                }
+            }
+        }
+    }
+
+    fn slide_moves(&self, from: u8, color: Color, directions: &[(i8, i8)], moves: &mut Vec<Move>) {
+        for &(df, dr) in directions {
+            let mut current = from;
+            while let Some(to) = offset(current, df, dr) {
+                match self.piece_at(to) {
+                    None => moves.push(Move::new(from, to, None)),
+                    Some(target) => {
+                        if target.color != color {
+                            moves.push(Move::new(from, to, None));
+                        }
+                        break;
+                    }
+                }
+                current = to;
+            }
+        }
+    }
+
+    fn castling_moves(&self, from: u8, color: Color, moves: &mut Vec<Move>) {
+        let (home, kingside, queenside) = match color {
+            Color::White => (4, WHITE_KINGSIDE, WHITE_QUEENSIDE),
+            _ => (60, BLACK_KINGSIDE, BLACK_QUEENSIDE),
+        };
+        if from != home || self.is_attacked(home, color.opposite()) {
+            return;
+        }
+
+        let rook = Some(Piece::new(color, PieceKind::Rook));
+        let empty = |squares: &[u8]| squares.iter().all(|&sq| self.piece_at(sq).is_none());
+        let safe = |squares: &[u8]| {
+            squares
+                .iter()
+                .all(|&sq| !self.is_attacked(sq, color.opposite()))
+        };
+
+        if self.castling & kingside != 0
+            && self.piece_at(home + 3) == rook
+            && empty(&[home + 1, home + 2])
+            && safe(&[home + 1, home + 2])
+        {
+            moves.push(Move::new(home, home + 2, None));
+        }
+        if self.castling & queenside != 0
+            && self.piece_at(home - 4) == rook
+            && empty(&[home - 1, home - 2, home - 3])
+            && safe(&[home - 1, home - 2])
+        {
+            moves.push(Move::new(home, home - 2, None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::STARTING_FEN;
+
+    /// Count the leaf nodes of the move tree down to the depth
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        position
+            .legal_moves()
+            .into_iter()
+            .map(|mv| perft(&position.play(mv), depth - 1))
+            .sum()
+    }
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let position = Position::from_fen(fen).unwrap();
+        for (depth, &nodes) in (1..).zip(expected) {
+            assert_eq!(perft(&position, depth), nodes, "{fen} at depth {depth}");
+        }
+    }
+
+    #[test]
+    fn perft_starting_position() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_perft(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2_039, 97_862],
+        );
+    }
+
+    #[test]
+    fn perft_en_passant_and_pins() {
+        assert_perft(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2_812, 43_238],
+        );
+    }
+
+    #[test]
+    fn perft_promotions_and_castling() {
+        assert_perft(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9_467],
+        );
+        assert_perft(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1_486, 62_379],
+        );
+    }
+
+    #[test]
+    fn perft_middlegame() {
+        assert_perft(
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            &[46, 2_079, 89_890],
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+use super::{file_of, parse_square, rank_of, square_name, Move, Outcome, Piece, PieceKind};
+use crate::types::Color;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+pub(super) const WHITE_KINGSIDE: u8 = 1;
+pub(super) const WHITE_QUEENSIDE: u8 = 2;
+pub(super) const BLACK_KINGSIDE: u8 = 4;
+pub(super) const BLACK_QUEENSIDE: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FenError(String);
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid FEN: {}", self.0)
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub(super) board: [Option<Piece>; 64],
+    pub(super) side_to_move: Color,
+    /// Bit set of the castling rights still available
+    pub(super) castling: u8,
+    /// The square a pawn skipped over with its double step on the last move
+    pub(super) en_passant: Option<u8>,
+    pub(super) halfmove_clock: u32,
+    pub(super) fullmove_number: u32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::from_fen(STARTING_FEN).unwrap()
+    }
+}
+
+/// Positions are stored as FEN
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_fen())
+    }
+}
+
+/// Stored positions were written by the server, so they are trusted to lack a king when
+/// the variant has none, as the horde does
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fen = String::deserialize(deserializer)?;
+        Self::parse_fen(&fen).map_err(de::Error::custom)
+    }
+}
+
+/// The castling rights lost when a piece leaves or lands on the square
+pub(super) fn castling_rights_of(square: u8) -> u8 {
+    match square {
+        0 => WHITE_QUEENSIDE,
+        4 => WHITE_KINGSIDE | WHITE_QUEENSIDE,
+        7 => WHITE_KINGSIDE,
+        56 => BLACK_QUEENSIDE,
+        60 => BLACK_KINGSIDE | BLACK_QUEENSIDE,
+        63 => BLACK_KINGSIDE,
+        _ => 0,
+    }
+}
+
+impl Position {
+    /// Parse a FEN, rejecting positions that can't come up in a game: each side needs
+    /// exactly one king, pawns can't stand on the first or last rank and the side that
+    /// just moved can't be left in check
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let position = Self::parse_fen(fen)?;
+        for color in [Color::White, Color::Black] {
+            let kings = position
+                .pieces()
+                .filter(|(_, piece)| *piece == Piece::new(color, PieceKind::King))
+                .count();
+            if kings != 1 {
+                return Err(FenError(format!("{color:?} has {kings} kings")));
+            }
+        }
+        if position.pieces().any(|(square, piece)| {
+            piece.kind == PieceKind::Pawn && matches!(rank_of(square), 0 | 7)
+        }) {
+            return Err(FenError("a pawn is on the first or last rank".into()));
+        }
+        let waiting = position.side_to_move.opposite();
+        if position.is_attacked(
+            position.king_square(waiting).unwrap(),
+            position.side_to_move,
+        ) {
+            return Err(FenError(format!(
+                "{waiting:?} is in check but it isn't their move"
+            )));
+        }
+
+        Ok(position)
+    }
+
+    /// Parse a FEN without requiring both kings on the board
+    pub(super) fn parse_fen(fen: &str) -> Result<Self, FenError> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or_else(|| FenError("empty".into()))?;
+
+        let mut board = [None; 64];
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError("expected 8 ranks".into()));
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let rank_index = 7 - i as u8;
+            let mut file = 0u8;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file = Some(empty)
+                        .filter(|empty| (1..=8).contains(empty))
+                        .and_then(|empty| file.checked_add(empty as u8))
+                        .ok_or_else(|| FenError(format!("invalid empty square count '{c}'")))?;
+                    continue;
+                }
+                let kind = PieceKind::from_letter(c)
+                    .ok_or_else(|| FenError(format!("unknown piece '{c}'")))?;
+                let color = match c.is_ascii_uppercase() {
+                    true => Color::White,
+                    false => Color::Black,
+                };
+                if file > 7 {
+                    return Err(FenError(format!("rank {} is too long", rank_index + 1)));
+                }
+                board[(rank_index * 8 + file) as usize] = Some(Piece::new(color, kind));
+                file += 1;
+            }
+            if file != 8 {
+                return Err(FenError(format!(
+                    "rank {} has {file} files",
+                    rank_index + 1
+                )));
+            }
+        }
+
+        let side_to_move = match fields.next().unwrap_or("w") {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(FenError(format!("unknown side to move '{other}'"))),
+        };
+
+        let mut castling = 0;
+        for c in fields.next().unwrap_or("-").chars() {
+            castling |= match c {
+                'K' => WHITE_KINGSIDE,
+                'Q' => WHITE_QUEENSIDE,
+                'k' => BLACK_KINGSIDE,
+                'q' => BLACK_QUEENSIDE,
+                '-' => 0,
+                other => return Err(FenError(format!("unknown castling right '{other}'"))),
+            };
+        }
+
+        let en_passant = match fields.next().unwrap_or("-") {
+            "-" => None,
+            square => Some(
+                parse_square(square)
+                    .ok_or_else(|| FenError(format!("invalid en passant square '{square}'")))?,
+            ),
+        };
+
+        let halfmove_clock = fields.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        let fullmove_number = fields.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+
+        Ok(Self {
+            board,
+            side_to_move,
+            castling,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+        })
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.board[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            placement.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        placement.push(piece.fen_letter());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                placement.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                placement.push('/');
+            }
+        }
+
+        let mut castling: String = [
+            (WHITE_KINGSIDE, 'K'),
+            (WHITE_QUEENSIDE, 'Q'),
+            (BLACK_KINGSIDE, 'k'),
+            (BLACK_QUEENSIDE, 'q'),
+        ]
+        .iter()
+        .filter(|(right, _)| self.castling & right != 0)
+        .map(|(_, c)| *c)
+        .collect();
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            match self.side_to_move {
+                Color::Black => 'b',
+                _ => 'w',
+            },
+            castling,
+            self.en_passant
+                .map(square_name)
+                .unwrap_or_else(|| "-".to_string()),
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+
+    pub fn piece_at(&self, square: u8) -> Option<Piece> {
+        self.board[square as usize]
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.side_to_move
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    /// Half moves since the last capture or pawn move
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn king_square(&self, color: Color) -> Option<u8> {
+        (0..64).find(|&sq| self.board[sq as usize] == Some(Piece::new(color, PieceKind::King)))
+    }
+
+    /// Every occupied square with its piece
+    pub fn pieces(&self) -> impl Iterator<Item = (u8, Piece)> + '_ {
+        (0..64u8).filter_map(|sq| Some((sq, self.board[sq as usize]?)))
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        self.legal_moves().contains(&mv)
+    }
+
+    pub fn is_capture(&self, mv: Move) -> bool {
+        self.piece_at(mv.to).is_some() || self.is_en_passant(mv)
+    }
+
+    pub(super) fn is_en_passant(&self, mv: Move) -> bool {
+        self.en_passant == Some(mv.to)
+            && self.piece_at(mv.from).map(|p| p.kind) == Some(PieceKind::Pawn)
+            && file_of(mv.from) != file_of(mv.to)
+    }
+
+    /// The position after playing a move, which has to be legal
+    pub fn play(&self, mv: Move) -> Position {
+        let mut next = self.clone();
+        let piece = self.piece_at(mv.from).expect("no piece on the from square");
+        let color = piece.color;
+
+        let capture = self.is_capture(mv);
+        if self.is_en_passant(mv) {
+            let captured = match color {
+                Color::White => mv.to - 8,
+                _ => mv.to + 8,
+            };
+            next.board[captured as usize] = None;
+        }
+
+        next.board[mv.from as usize] = None;
+        next.board[mv.to as usize] = Some(match mv.promotion {
+            Some(kind) => Piece::new(color, kind),
+            None => piece,
+        });
+
+        // castling moves the rook along with the king
+        if piece.kind == PieceKind::King && file_of(mv.from).abs_diff(file_of(mv.to)) == 2 {
+            let rank = rank_of(mv.from) * 8;
+            let (rook_from, rook_to) = match file_of(mv.to) {
+                6 => (rank + 7, rank + 5),
+                _ => (rank, rank + 3),
+            };
+            next.board[rook_to as usize] = next.board[rook_from as usize].take();
+        }
+
+        // moving the king or a rook, or capturing a rook, loses the matching rights
+        for square in [mv.from, mv.to] {
+            next.castling &= !castling_rights_of(square);
+        }
+
+        next.en_passant = match piece.kind {
+            PieceKind::Pawn if rank_of(mv.from).abs_diff(rank_of(mv.to)) == 2 => {
+                Some((mv.from + mv.to) / 2)
+            }
+            _ => None,
+        };
+        next.halfmove_clock = match piece.kind == PieceKind::Pawn || capture {
+            true => 0,
+            false => self.halfmove_clock + 1,
+        };
+        if color == Color::Black {
+            next.fullmove_number += 1;
+        }
+        next.side_to_move = color.opposite();
+
+        next
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.king_square(self.side_to_move)
+            .is_some_and(|king| self.is_attacked(king, self.side_to_move.opposite()))
+    }
+
+    /// How the game ended in this position, None if it goes on
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.legal_moves().is_empty() {
+            return Some(match self.in_check() {
+                true => Outcome::Checkmate,
+                false => Outcome::Stalemate,
+            });
+        }
+        if self.insufficient_material() {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        if self.halfmove_clock >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+
+        None
+    }
+
+    /// Neither side can possibly checkmate: bare kings, or a single minor piece left
+    pub fn insufficient_material(&self) -> bool {
+        let mut minors = 0;
+        for (_, piece) in self.pieces() {
+            match piece.kind {
+                PieceKind::King => {}
+                PieceKind::Knight | PieceKind::Bishop => minors += 1,
+                _ => return false,
+            }
+        }
+
+        minors <= 1
+    }
+
+    /// Identifies the position for repetitions, ignoring the move counters. The en passant
+    /// square only counts while a pawn can actually capture there, so move orders that
+    /// transpose into each other get the same key.
+    pub fn repetition_key(&self) -> String {
+        let can_capture_en_passant = self.en_passant.is_some()
+            && self.legal_moves().into_iter().any(|mv| {
+                self.is_en_passant(mv)
+                    && self
+                        .piece_at(mv.from)
+                        .is_some_and(|piece| piece.kind == PieceKind::Pawn)
+            });
+        let fen = match can_capture_en_passant {
+            true => self.to_fen(),
+            false => Position {
+                en_passant: None,
+                ..self.clone()
+            }
+            .to_fen(),
+        };
+
+        fen.rsplitn(3, ' ').nth(2).unwrap_or(&fen).to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_empty_square_counts() {
+        for fen in [
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/0/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/88888888888888888888888888888888/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            "rnbqkbnr/pppppppp/8/54/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ] {
+            assert!(Position::from_fen(fen).is_err(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_positions() {
+        for fen in [
+            // no black king
+            "8/8/8/8/8/8/8/4K3 w - - 0 1",
+            // two white kings
+            "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+            // a pawn on the last rank
+            "3Pk3/8/8/8/8/8/8/4K3 w - - 0 1",
+            // white to move with black in check
+            "4k3/8/8/8/8/8/8/K3R3 w - - 0 1",
+            "4k3/8/8/8/Q7/8/8/4K3 w - - 0 1",
+        ] {
+            assert!(Position::from_fen(fen).is_err(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn outcomes() {
+        let mate = Position::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        let stalemate = Position::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        let bare_kings = Position::from_fen("k7/8/1K6/8/8/8/8/8 w - - 0 1").unwrap();
+        let fifty_moves = Position::from_fen("k7/8/1K6/8/8/8/8/7R w - - 100 80").unwrap();
+
+        assert_eq!(mate.outcome(), Some(Outcome::Checkmate));
+        assert_eq!(stalemate.outcome(), Some(Outcome::Stalemate));
+        assert_eq!(bare_kings.outcome(), Some(Outcome::InsufficientMaterial));
+        assert_eq!(fifty_moves.outcome(), Some(Outcome::FiftyMoves));
+    }
+}
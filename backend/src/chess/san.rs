@@ -0,0 +1,188 @@
+use super::{file_of, rank_of, square_name, Move, Outcome, PieceKind, Position};
+
+impl Position {
+    /// Standard algebraic notation of a legal move, e.g. `Nbd2`, `exd5`, `O-O` or `e8=Q#`
+    pub fn san(&self, mv: Move) -> String {
+        let mut san = self.san_without_check(mv);
+
+        let next = self.play(mv);
+        if next.in_check() {
+            san.push(match next.outcome() {
+                Some(Outcome::Checkmate) => '#',
+                _ => '+',
+            });
+        }
+
+        san
+    }
+
+    /// The notation without the check or mate mark, which variants decide on themselves
+    pub(super) fn san_without_check(&self, mv: Move) -> String {
+        if let Some(kind) = mv.drop {
+            return format!(
+                "{}@{}",
+                kind.letter().to_ascii_uppercase(),
+                square_name(mv.to)
+            );
+        }
+
+        let piece = self.piece_at(mv.from).expect("no piece on the from square");
+        let mut san = String::new();
+
+        if piece.kind == PieceKind::King && file_of(mv.from).abs_diff(file_of(mv.to)) == 2 {
+            san.push_str(match file_of(mv.to) {
+                6 => "O-O",
+                _ => "O-O-O",
+            });
+        } else {
+            let capture = self.is_capture(mv);
+            match piece.kind {
+                PieceKind::Pawn => {
+                    if capture {
+                        san.push((b'a' + file_of(mv.from)) as char);
+                    }
+                }
+                kind => {
+                    san.push(kind.letter().to_ascii_uppercase());
+                    san.push_str(&self.disambiguation(mv, kind));
+                }
+            }
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to));
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(promotion.letter().to_ascii_uppercase());
+            }
+        }
+
+        san
+    }
+
+    /// Find the legal move written in standard algebraic notation, check marks and
+    /// annotations such as `!?` are optional
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let strip = |san: &str| san.trim_end_matches(['+', '#', '!', '?']).to_owned();
+        let san = strip(san);
+
+        self.legal_moves()
+            .into_iter()
+            .find(|mv| strip(&self.san(*mv)) == san)
+    }
+
+    /// The file, rank or square needed to tell the moving piece apart from its twins
+    fn disambiguation(&self, mv: Move, kind: PieceKind) -> String {
+        let twins: Vec<u8> = self
+            .legal_moves()
+            .into_iter()
+            .filter(|other| {
+                other.to == mv.to
+                    && other.from != mv.from
+                    && self.piece_at(other.from).map(|p| p.kind) == Some(kind)
+            })
+            .map(|other| other.from)
+            .collect();
+
+        if twins.is_empty() {
+            String::new()
+        } else if twins.iter().all(|&sq| file_of(sq) != file_of(mv.from)) {
+            ((b'a' + file_of(mv.from)) as char).to_string()
+        } else if twins.iter().all(|&sq| rank_of(sq) != rank_of(mv.from)) {
+            ((b'1' + rank_of(mv.from)) as char).to_string()
+        } else {
+            square_name(mv.from)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let position = Position::from_fen(fen).unwrap();
+        let mv = Move::from_uci(uci).unwrap();
+        assert!(position.is_legal(mv), "{uci} in {fen}");
+
+        position.san(mv)
+    }
+
+    #[test]
+    fn pieces_and_pawns() {
+        let start = Position::default().to_fen();
+        assert_eq!(san(&start, "e2e4"), "e4");
+        assert_eq!(san(&start, "g1f3"), "Nf3");
+
+        let fen = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2";
+        assert_eq!(san(fen, "e4d5"), "exd5");
+        // en passant
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(san(fen, "e5f6"), "exf6");
+    }
+
+    #[test]
+    fn disambiguation() {
+        // knights on b1 and f3 can both reach d2
+        let fen = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1d2"), "Nbd2");
+        assert_eq!(san(fen, "f3d2"), "Nfd2");
+
+        // rooks on a1 and a5 share a file
+        let fen = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a3"), "R1a3");
+        assert_eq!(san(fen, "a5a3"), "R5a3");
+
+        // queens on a1, a5 and e1 all reach e5, the one on a1 shares a file with one
+        // twin and a rank with the other
+        let fen = "6k1/8/8/Q7/8/8/8/Q3Q2K w - - 0 1";
+        assert_eq!(san(fen, "a1e5"), "Qa1e5");
+        assert_eq!(san(fen, "a5e5"), "Q5e5");
+        assert_eq!(san(fen, "e1e5"), "Qee5");
+
+        // a pinned twin doesn't count
+        let fen = "4k3/8/8/4N3/8/8/8/r1N1K3 w - - 0 1";
+        assert_eq!(san(fen, "e5d3"), "Nd3");
+    }
+
+    #[test]
+    fn checks_and_mates() {
+        let fen = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a8"), "Ra8+");
+
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        assert_eq!(san(fen, "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+        // castling into a check on the enemy king
+        let fen = "5k2/8/8/8/8/8/8/4K2R w K - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O+");
+    }
+
+    #[test]
+    fn promotions() {
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a7a8q"), "a8=Q");
+        assert_eq!(san(fen, "a7a8n"), "a8=N");
+        assert_eq!(san(fen, "a7b8q"), "axb8=Q+");
+        assert_eq!(san(fen, "a7b8n"), "axb8=N");
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let position = Position::from_fen("r3k2r/8/8/8/8/5N2/P7/RN2K2R w KQkq - 0 1").unwrap();
+        for mv in position.legal_moves() {
+            assert_eq!(position.parse_san(&position.san(mv)), Some(mv));
+        }
+        assert_eq!(
+            position.parse_san("Nbd2!?"),
+            Move::from_uci("b1d2").filter(|mv| position.is_legal(*mv))
+        );
+        assert_eq!(position.parse_san("Nd2"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use super::{neighbours, Board, Variant};
+use crate::chess::position::castling_rights_of;
+use crate::chess::{Move, Outcome, PieceKind, Position};
+use crate::types::Color;
+
+/// Captures blow up the capturing piece along with every piece around the captured one
+/// except pawns, blowing up the opponent's king wins
+pub struct Atomic;
+
+impl Atomic {
+    /// The position after a move, explosion included
+    fn explode(position: &Position, mv: Move) -> Position {
+        let capture = position.is_capture(mv);
+        let mut next = position.play(mv);
+        if !capture {
+            return next;
+        }
+
+        let blast = std::iter::once(mv.to).chain(neighbours(mv.to).filter(|&sq| {
+            next.piece_at(sq)
+                .is_some_and(|piece| piece.kind != PieceKind::Pawn)
+        }));
+        for square in blast.collect::<Vec<_>>() {
+            next.board[square as usize] = None;
+            next.castling &= !castling_rights_of(square);
+        }
+
+        next
+    }
+
+    /// A king next to the enemy king can't be checked, capturing it would blow up the
+    /// capturer's own king
+    fn king_attacked(position: &Position, color: Color) -> bool {
+        let Some(king) = position.king_square(color) else {
+            return false;
+        };
+        let kings_touch = position
+            .king_square(color.opposite())
+            .is_some_and(|enemy| neighbours(king).any(|sq| sq == enemy));
+
+        !kings_touch && position.is_attacked(king, color.opposite())
+    }
+}
+
+impl Variant for Atomic {
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        let position = &board.position;
+        let color = position.side_to_move();
+
+        position
+            .pseudo_legal_moves()
+            .into_iter()
+            // kings can't capture, they would blow themselves up
+            .filter(|&mv| {
+                !(position.piece_at(mv.from).map(|p| p.kind) == Some(PieceKind::King)
+                    && position.is_capture(mv))
+            })
+            .filter(|&mv| {
+                let next = Self::explode(position, mv);
+                match (next.king_square(color), next.king_square(color.opposite())) {
+                    (None, _) => false,
+                    (Some(_), None) => true,
+                    (Some(_), Some(_)) => !Self::king_attacked(&next, color),
+                }
+            })
+            .collect()
+    }
+
+    fn play(&self, board: &Board, mv: Move) -> Board {
+        Board {
+            position: Self::explode(&board.position, mv),
+            variant_state: board.variant_state.clone(),
+        }
+    }
+
+    fn in_check(&self, board: &Board) -> bool {
+        Self::king_attacked(&board.position, board.position.side_to_move())
+    }
+
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let position = &board.position;
+        if position.king_square(position.side_to_move()).is_none() {
+            return Some(Outcome::KingExploded);
+        }
+        if self.legal_moves(board).is_empty() {
+            return Some(match self.in_check(board) {
+                true => Outcome::Checkmate,
+                false => Outcome::Stalemate,
+            });
+        }
+        // a lone minor piece can still blow up a king next to the enemy's pieces
+        if position.pieces().count() == 2 {
+            return Some(Outcome::InsufficientMaterial);
+        }
+        if position.halfmove_clock() >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::from_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    #[test]
+    fn captures_explode() {
+        let board = board("4k3/8/2n1p3/3p4/4B3/8/8/4K3 w - - 0 1");
+
+        // the knight next to the captured pawn goes too, the pawn on e6 survives
+        let next = Atomic.play(&board, mv("e4d5"));
+        assert_eq!(next.position.to_fen(), "4k3/8/4p3/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn blowing_up_the_king_wins() {
+        let board = board("3qk3/8/8/8/8/8/8/3RK3 w - - 0 1");
+
+        let next = Atomic.play(&board, mv("d1d8"));
+        assert_eq!(next.position.king_square(Color::Black), None);
+        assert_eq!(Atomic.outcome(&next), Some(Outcome::KingExploded));
+        assert_eq!(
+            Atomic.winner(&next, Outcome::KingExploded),
+            Some(Color::White)
+        );
+    }
+
+    #[test]
+    fn kings_never_capture() {
+        let board = board("4k3/8/8/8/8/8/3p4/4K3 w - - 0 1");
+
+        assert!(Atomic.in_check(&board));
+        assert!(!Atomic.is_legal(&board, mv("e1d2")));
+        assert!(Atomic.is_legal(&board, mv("e1d1")));
+    }
+
+    #[test]
+    fn touching_kings_are_not_in_check() {
+        // standard FENs can't have the kings touch
+        let board = Board {
+            position: Position::parse_fen("8/8/8/8/8/8/3kq3/4K3 w - - 0 1").unwrap(),
+            ..Board::default()
+        };
+
+        assert!(!Atomic.in_check(&board));
+        assert!(Atomic.is_legal(&board, mv("e1d1")));
+        assert!(!Atomic.is_legal(&board, mv("e1f1")));
+    }
+}
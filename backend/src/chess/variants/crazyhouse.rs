@@ -0,0 +1,280 @@
+use super::{side, Board, Variant};
+use crate::chess::{rank_of, Move, Outcome, Piece, PieceKind, Position};
+use crate::types::Color;
+
+/// Captured pieces change sides and go to the capturer's pocket, from where they can be
+/// dropped back on any empty square instead of moving
+pub struct Crazyhouse;
+
+/// Crazyhouse on two linked boards: captured pieces go to the pocket of the capturer's
+/// partner on the other board, which the server hands over
+pub struct Bughouse;
+
+fn bit(square: u8) -> u64 {
+    1 << square
+}
+
+impl Crazyhouse {
+    /// Every drop that doesn't leave the dropping side's king in check
+    fn drops(board: &Board) -> Vec<Move> {
+        let position = &board.position;
+        let color = position.side_to_move();
+        let mut drops = Vec::new();
+
+        for kind in board.variant_state.pockets[side(color)].kinds() {
+            for to in 0..64 {
+                // pawns can't be dropped where they could never have stood
+                if position.piece_at(to).is_some()
+                    || (kind == PieceKind::Pawn && matches!(rank_of(to), 0 | 7))
+                {
+                    continue;
+                }
+
+                let next = Self::place(position, kind, to);
+                if next
+                    .king_square(color)
+                    .is_some_and(|king| !next.is_attacked(king, color.opposite()))
+                {
+                    drops.push(Move::drop(kind, to));
+                }
+            }
+        }
+
+        drops
+    }
+
+    /// The position after dropping a piece of the side to move
+    fn place(position: &Position, kind: PieceKind, to: u8) -> Position {
+        let color = position.side_to_move;
+        let mut next = position.clone();
+
+        next.board[to as usize] = Some(Piece::new(color, kind));
+        next.en_passant = None;
+        next.halfmove_clock = match kind {
+            PieceKind::Pawn => 0,
+            _ => position.halfmove_clock + 1,
+        };
+        if color == Color::Black {
+            next.fullmove_number += 1;
+        }
+        next.side_to_move = color.opposite();
+
+        next
+    }
+
+    /// Play a move, the captured piece goes to the mover's pocket unless it is handed
+    /// over to someone else
+    fn play_keeping_captures(board: &Board, mv: Move, keep_captures: bool) -> Board {
+        let position = &board.position;
+        let mover = side(position.side_to_move());
+        let mut variant_state = board.variant_state.clone();
+
+        if let Some(kind) = mv.drop {
+            variant_state.pockets[mover].take(kind);
+            return Board {
+                position: Self::place(position, kind, mv.to),
+                variant_state,
+            };
+        }
+
+        if let Some(kind) = Crazyhouse.captured(board, mv).filter(|_| keep_captures) {
+            variant_state.pockets[mover].add(kind);
+        }
+
+        let moved_promoted = variant_state.promoted & bit(mv.from) != 0;
+        variant_state.promoted &= !(bit(mv.from) | bit(mv.to));
+        if moved_promoted || mv.promotion.is_some() {
+            variant_state.promoted |= bit(mv.to);
+        }
+
+        Board {
+            position: position.play(mv),
+            variant_state,
+        }
+    }
+}
+
+impl Variant for Crazyhouse {
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        let mut moves = board.position.legal_moves();
+        moves.extend(Self::drops(board));
+        moves
+    }
+
+    fn play(&self, board: &Board, mv: Move) -> Board {
+        Self::play_keeping_captures(board, mv, true)
+    }
+
+    /// Promoted pieces turn back into pawns when they are captured
+    fn captured(&self, board: &Board, mv: Move) -> Option<PieceKind> {
+        let position = &board.position;
+        match position.is_en_passant(mv) {
+            true => Some(PieceKind::Pawn),
+            false => position.piece_at(mv.to).map(|piece| {
+                match board.variant_state.promoted & bit(mv.to) != 0 {
+                    true => PieceKind::Pawn,
+                    false => piece.kind,
+                }
+            }),
+        }
+    }
+
+    /// Captured material always comes back, so only mate and stalemate end the game
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        if !self.legal_moves(board).is_empty() {
+            return None;
+        }
+
+        Some(match board.position.in_check() {
+            true => Outcome::Checkmate,
+            false => Outcome::Stalemate,
+        })
+    }
+
+    /// Promoted pieces are followed by `~` and the pockets come in brackets after the
+    /// placement, e.g. `.../RNBQKB1R[Qp] w KQkq - 0 1`
+    fn fen(&self, board: &Board) -> String {
+        let fen = board.position.to_fen();
+        let (placement, rest) = fen.split_once(' ').unwrap_or((&fen, ""));
+
+        let mut marked = String::with_capacity(placement.len() + 12);
+        let (mut rank, mut file) = (7u8, 0u8);
+        for c in placement.chars() {
+            marked.push(c);
+            match c {
+                '/' => {
+                    rank = rank.saturating_sub(1);
+                    file = 0;
+                }
+                _ => match c.to_digit(10) {
+                    Some(empty) => file += empty as u8,
+                    None => {
+                        if board.variant_state.promoted & bit(rank * 8 + file) != 0 {
+                            marked.push('~');
+                        }
+                        file += 1;
+                    }
+                },
+            }
+        }
+
+        let mut pockets = String::new();
+        for color in [Color::White, Color::Black] {
+            let pocket = board.variant_state.pockets[side(color)];
+            for kind in pocket.kinds() {
+                let letter = Piece::new(color, kind).fen_letter();
+                pockets.extend(std::iter::repeat_n(letter, pocket.count(kind).into()));
+            }
+        }
+
+        format!("{marked}[{pockets}] {rest}")
+    }
+}
+
+impl Variant for Bughouse {
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        Crazyhouse.legal_moves(board)
+    }
+
+    fn play(&self, board: &Board, mv: Move) -> Board {
+        Crazyhouse::play_keeping_captures(board, mv, false)
+    }
+
+    fn captured(&self, board: &Board, mv: Move) -> Option<PieceKind> {
+        Crazyhouse.captured(board, mv)
+    }
+
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        Crazyhouse.outcome(board)
+    }
+
+    fn fen(&self, board: &Board) -> String {
+        Crazyhouse.fen(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::parse_square;
+    use crate::chess::variants::Pocket;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::from_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    #[test]
+    fn drops() {
+        let mut board = board("4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        board
+            .variant_state
+            .add_to_pocket(Color::White, PieceKind::Knight);
+        board
+            .variant_state
+            .add_to_pocket(Color::White, PieceKind::Pawn);
+
+        assert!(Crazyhouse.is_legal(&board, mv("N@f3")));
+        assert!(Crazyhouse.is_legal(&board, mv("P@a7")));
+        assert!(!Crazyhouse.is_legal(&board, mv("P@a8")));
+        assert!(!Crazyhouse.is_legal(&board, mv("P@a1")));
+        assert!(!Crazyhouse.is_legal(&board, mv("B@f3")));
+
+        let next = Crazyhouse.play(&board, mv("N@f3"));
+        let knight = Piece::new(Color::White, PieceKind::Knight);
+        assert_eq!(
+            next.position.piece_at(parse_square("f3").unwrap()),
+            Some(knight)
+        );
+        assert_eq!(next.variant_state.pockets[0].count(PieceKind::Knight), 0);
+        assert_eq!(Crazyhouse.fen(&next), "4k3/8/8/8/8/5N2/8/4K3[P] b - - 1 1");
+    }
+
+    #[test]
+    fn drops_have_to_get_out_of_check() {
+        let mut board = board("4r1k1/8/8/8/8/8/8/4K3 w - - 0 1");
+        board
+            .variant_state
+            .add_to_pocket(Color::White, PieceKind::Knight);
+
+        assert!(Crazyhouse.is_legal(&board, mv("N@e4")));
+        assert!(!Crazyhouse.is_legal(&board, mv("N@a3")));
+    }
+
+    #[test]
+    fn captured_promoted_pieces_go_back_as_pawns() {
+        let board = board("1r2k3/P2n4/8/8/8/8/8/4K3 w - - 0 1");
+
+        let promoted = Crazyhouse.play(&board, mv("a7b8q"));
+        assert_eq!(promoted.variant_state.pockets[0].count(PieceKind::Rook), 1);
+        assert_eq!(
+            Crazyhouse.fen(&promoted),
+            "1Q~2k3/3n4/8/8/8/8/8/4K3[R] b - - 0 1"
+        );
+
+        assert_eq!(
+            Crazyhouse.captured(&promoted, mv("d7b8")),
+            Some(PieceKind::Pawn)
+        );
+        let recaptured = Crazyhouse.play(&promoted, mv("d7b8"));
+        let pocket = recaptured.variant_state.pockets[1];
+        assert_eq!(pocket.count(PieceKind::Pawn), 1);
+        assert_eq!(pocket.count(PieceKind::Queen), 0);
+        assert_eq!(recaptured.variant_state.promoted, 0);
+    }
+
+    #[test]
+    fn bughouse_hands_captures_over() {
+        let board = board("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1");
+
+        let next = Bughouse.play(&board, mv("e4d5"));
+        assert_eq!(Bughouse.captured(&board, mv("e4d5")), Some(PieceKind::Pawn));
+        assert_eq!(next.variant_state.pockets, [Pocket::default(); 2]);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{Board, Variant};
+use crate::chess::{Move, Outcome};
+use crate::types::Color;
+
+/// Each player only sees the squares their pieces can move to. There is no check, kings
+/// can walk into danger and capturing the king wins.
+pub struct FogOfWar;
+
+impl Variant for FogOfWar {
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        board.position.pseudo_legal_moves()
+    }
+
+    fn in_check(&self, _board: &Board) -> bool {
+        false
+    }
+
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let position = &board.position;
+        if position.king_square(position.side_to_move()).is_none() {
+            return Some(Outcome::KingCaptured);
+        }
+        if self.legal_moves(board).is_empty() {
+            return Some(Outcome::Stalemate);
+        }
+        if position.halfmove_clock() >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+
+        None
+    }
+
+    /// The viewer's own squares and every square one of their pieces could move to if
+    /// it was their turn, pawns only see diagonally where they could capture
+    fn visible_squares(&self, board: &Board, viewer: Color) -> Option<u64> {
+        let mut turn = board.position.clone();
+        if turn.side_to_move != viewer {
+            turn.side_to_move = viewer;
+            // the en passant square belongs to the viewer's own pawn then
+            turn.en_passant = None;
+        }
+
+        let own = board
+            .position
+            .pieces()
+            .filter(|(_, piece)| piece.color == viewer)
+            .fold(0, |squares, (square, _)| squares | 1 << square);
+        let reachable = turn
+            .pseudo_legal_moves()
+            .into_iter()
+            .fold(0, |squares, mv| squares | 1 << mv.to);
+
+        Some(own | reachable)
+    }
+
+    fn hides_information(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &str) -> Board {
+        moves
+            .split_whitespace()
+            .fold(Board::default(), |board, uci| {
+                FogOfWar.play(&board, Move::from_uci(uci).unwrap())
+            })
+    }
+
+    #[test]
+    fn players_only_see_where_they_could_move() {
+        let board = play("e2e4 d7d5");
+
+        // each side's pawn can take the other's, the rest of the enemy army is hidden
+        assert_eq!(
+            FogOfWar.fen_for(&board, Color::White),
+            "8/8/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQ - 0 2"
+        );
+        assert_eq!(
+            FogOfWar.fen_for(&board, Color::Black),
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/8/8 w kq d6 0 2"
+        );
+    }
+
+    #[test]
+    fn capturing_the_king_wins() {
+        let board = play("e2e4 f7f6 d1h5 e8f7 h5f7");
+
+        assert_eq!(FogOfWar.outcome(&board), Some(Outcome::KingCaptured));
+        assert_eq!(
+            FogOfWar.winner(&board, Outcome::KingCaptured),
+            Some(Color::White)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use super::{Board, Variant};
+use crate::chess::{rank_of, Move, Outcome, PieceKind, Position};
+use crate::types::Color;
+
+/// White has no king but 36 pawns, black wins by capturing all of them
+const STARTING_FEN: &str =
+    "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1";
+
+/// A horde of white pawns against a full black army
+pub struct Horde;
+
+impl Variant for Horde {
+    fn starting_board(&self) -> Board {
+        Board {
+            position: Position::parse_fen(STARTING_FEN).expect("valid horde FEN"),
+            ..Board::default()
+        }
+    }
+
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        let position = &board.position;
+        let color = position.side_to_move();
+        let mut moves = position.pseudo_legal_moves();
+
+        // pawns on the first rank can step two squares, as if they were on the second
+        if color == Color::White {
+            let empty = |square: u8| position.piece_at(square).is_none();
+            moves.extend(
+                position
+                    .pieces()
+                    .filter(|(square, piece)| {
+                        piece.color == color
+                            && piece.kind == PieceKind::Pawn
+                            && rank_of(*square) == 0
+                    })
+                    .filter(|&(square, _)| empty(square + 8) && empty(square + 16))
+                    .map(|(square, _)| Move::new(square, square + 16, None)),
+            );
+        }
+
+        // the horde has no king to keep safe
+        if position.king_square(color).is_none() {
+            return moves;
+        }
+        moves
+            .into_iter()
+            .filter(|&mv| {
+                let next = position.play(mv);
+                next.king_square(color)
+                    .is_some_and(|king| !next.is_attacked(king, color.opposite()))
+            })
+            .collect()
+    }
+
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let position = &board.position;
+        if !position
+            .pieces()
+            .any(|(_, piece)| piece.color == Color::White)
+        {
+            return Some(Outcome::HordeDestroyed);
+        }
+        if self.legal_moves(board).is_empty() {
+            return Some(match position.in_check() {
+                true => Outcome::Checkmate,
+                false => Outcome::Stalemate,
+            });
+        }
+        if position.halfmove_clock() >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::parse_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    #[test]
+    fn starting_board() {
+        let board = Horde.starting_board();
+
+        assert_eq!(board.position.to_fen(), STARTING_FEN);
+        assert_eq!(board.position.king_square(Color::White), None);
+        assert!(Horde.is_legal(&board, mv("b5b6")));
+        assert_eq!(Horde.outcome(&board), None);
+    }
+
+    #[test]
+    fn first_rank_pawns_step_two_squares() {
+        let board = board("4k3/8/8/8/8/8/8/P7 w - - 0 1");
+
+        assert!(Horde.is_legal(&board, mv("a1a3")));
+        assert!(Horde.is_legal(&board, mv("a1a2")));
+    }
+
+    #[test]
+    fn capturing_the_last_pawn_wins() {
+        let board = board("4k3/8/8/8/8/8/3r4/3P4 b - - 0 1");
+
+        let next = Horde.play(&board, mv("d2d1"));
+        assert_eq!(Horde.outcome(&next), Some(Outcome::HordeDestroyed));
+        assert_eq!(
+            Horde.winner(&next, Outcome::HordeDestroyed),
+            Some(Color::Black)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use super::{Board, Variant};
+use crate::chess::{Outcome, Piece, PieceKind};
+
+/// d4, e4, d5 and e5
+const CENTER: [u8; 4] = [27, 28, 35, 36];
+
+/// Standard chess, except that bringing the king to the center wins
+pub struct KingOfTheHill;
+
+impl Variant for KingOfTheHill {
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let king = Piece::new(board.position.side_to_move().opposite(), PieceKind::King);
+        if CENTER
+            .iter()
+            .any(|&square| board.position.piece_at(square) == Some(king))
+        {
+            return Some(Outcome::KingOfTheHill);
+        }
+
+        // a bare king can still walk to the center
+        match board.position.outcome() {
+            Some(Outcome::InsufficientMaterial) => None,
+            outcome => outcome,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{Move, Position};
+    use crate::types::Color;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::from_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    #[test]
+    fn reaching_the_center_wins() {
+        let board = board("4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        assert_eq!(KingOfTheHill.outcome(&board), None);
+
+        let next = KingOfTheHill.play(&board, Move::from_uci("e3e4").unwrap());
+        assert_eq!(KingOfTheHill.outcome(&next), Some(Outcome::KingOfTheHill));
+        assert_eq!(
+            KingOfTheHill.winner(&next, Outcome::KingOfTheHill),
+            Some(Color::White)
+        );
+
+        let beside = KingOfTheHill.play(&board, Move::from_uci("e3f4").unwrap());
+        assert_eq!(KingOfTheHill.outcome(&beside), None);
+    }
+}
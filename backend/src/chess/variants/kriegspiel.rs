@@ -0,0 +1,168 @@
+use serde::Serialize;
+
+use super::{Board, Variant};
+use crate::chess::{file_of, rank_of, PieceKind, Position};
+use crate::types::Color;
+
+/// Standard chess where each player only sees their own pieces, a referee tells both
+/// players whether moves were legal, what was captured and where checks come from
+pub struct Kriegspiel;
+
+/// Where a check comes from, as the referee announces it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckDirection {
+    Rank,
+    File,
+    /// The longer of the two diagonals through the king
+    LongDiagonal,
+    ShortDiagonal,
+    Knight,
+}
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+impl Kriegspiel {
+    /// Every direction the side to move is checked from, two for a double check
+    pub fn checks(position: &Position) -> Vec<CheckDirection> {
+        let color = position.side_to_move();
+        let Some(king) = position.king_square(color) else {
+            return Vec::new();
+        };
+        let (file, rank) = (file_of(king) as i8, rank_of(king) as i8);
+        let enemy = |f: i8, r: i8, kinds: &[PieceKind]| {
+            ((0..8).contains(&f) && (0..8).contains(&r))
+                .then(|| position.piece_at((r * 8 + f) as u8))
+                .flatten()
+                .is_some_and(|piece| piece.color != color && kinds.contains(&piece.kind))
+        };
+
+        // the main diagonal runs a1-h8 wise, the other one a8-h1 wise
+        let main_length = 8 - (file - rank).abs();
+        let anti_length = 8 - (file + rank - 7).abs();
+        let diagonal = |main: bool| match main == (main_length > anti_length) {
+            true => CheckDirection::LongDiagonal,
+            false => CheckDirection::ShortDiagonal,
+        };
+
+        let mut checks = Vec::new();
+        let rays = [
+            ((1, 0), CheckDirection::Rank),
+            ((-1, 0), CheckDirection::Rank),
+            ((0, 1), CheckDirection::File),
+            ((0, -1), CheckDirection::File),
+            ((1, 1), diagonal(true)),
+            ((-1, -1), diagonal(true)),
+            ((1, -1), diagonal(false)),
+            ((-1, 1), diagonal(false)),
+        ];
+        for ((df, dr), direction) in rays {
+            let sliders: &[PieceKind] = match df == 0 || dr == 0 {
+                true => &[PieceKind::Rook, PieceKind::Queen],
+                false => &[PieceKind::Bishop, PieceKind::Queen],
+            };
+            let (mut f, mut r) = (file + df, rank + dr);
+            while (0..8).contains(&f) && (0..8).contains(&r) {
+                if position.piece_at((r * 8 + f) as u8).is_some() {
+                    // pawns only check from the square diagonally in front of the king
+                    let pawn_checks = df != 0
+                        && dr != 0
+                        && (f, r) == (file + df, rank + dr)
+                        && dr == Self::forward(color)
+                        && enemy(f, r, &[PieceKind::Pawn]);
+                    if enemy(f, r, sliders) || pawn_checks {
+                        checks.push(direction);
+                    }
+                    break;
+                }
+                (f, r) = (f + df, r + dr);
+            }
+        }
+
+        if KNIGHT_STEPS
+            .iter()
+            .any(|&(df, dr)| enemy(file + df, rank + dr, &[PieceKind::Knight]))
+        {
+            checks.push(CheckDirection::Knight);
+        }
+
+        checks
+    }
+
+    /// The rank direction the king of the color faces enemy pawns from
+    fn forward(color: Color) -> i8 {
+        match color {
+            Color::White => 1,
+            _ => -1,
+        }
+    }
+}
+
+impl Variant for Kriegspiel {
+    /// Only the squares of the viewer's own pieces
+    fn visible_squares(&self, board: &Board, viewer: Color) -> Option<u64> {
+        Some(
+            board
+                .position
+                .pieces()
+                .filter(|(_, piece)| piece.color == viewer)
+                .fold(0, |squares, (square, _)| squares | 1 << square),
+        )
+    }
+
+    fn hides_information(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::Move;
+
+    fn play(moves: &str) -> Board {
+        moves
+            .split_whitespace()
+            .fold(Board::default(), |board, uci| {
+                Kriegspiel.play(&board, Move::from_uci(uci).unwrap())
+            })
+    }
+
+    #[test]
+    fn players_only_see_their_own_pieces() {
+        let board = play("e2e4 d7d5");
+
+        assert_eq!(
+            Kriegspiel.fen_for(&board, Color::White),
+            "8/8/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQ - 0 2"
+        );
+        assert_eq!(
+            Kriegspiel.fen_for(&board, Color::Black),
+            "rnbqkbnr/ppp1pppp/8/3p4/8/8/8/8 w kq - 0 2"
+        );
+    }
+
+    #[test]
+    fn check_directions() {
+        let board = play("e2e4 f7f6 d1h5");
+        assert_eq!(
+            Kriegspiel::checks(&board.position),
+            [CheckDirection::ShortDiagonal]
+        );
+
+        let board = play("e2e4 d7d6 g1f3 e8d7 f3e5");
+        assert_eq!(
+            Kriegspiel::checks(&board.position),
+            [CheckDirection::Knight]
+        );
+    }
+}
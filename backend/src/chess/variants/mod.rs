@@ -0,0 +1,277 @@
+//! Rules that set chess variants apart from standard chess
+//!
+//! Every variant plays on a [`Position`] and keeps whatever else it needs, such as
+//! crazyhouse pockets or three-check counters, in a [`VariantState`] next to it.
+
+use serde::{Deserialize, Serialize};
+
+use super::position::{BLACK_KINGSIDE, BLACK_QUEENSIDE, WHITE_KINGSIDE, WHITE_QUEENSIDE};
+use super::{file_of, rank_of, Move, Outcome, PieceKind, Position};
+use crate::types::Color;
+
+mod atomic;
+mod crazyhouse;
+mod fog_of_war;
+mod horde;
+mod king_of_the_hill;
+mod kriegspiel;
+mod racing_kings;
+mod three_check;
+
+pub use atomic::Atomic;
+pub use crazyhouse::{Bughouse, Crazyhouse};
+pub use fog_of_war::FogOfWar;
+pub use horde::Horde;
+pub use king_of_the_hill::KingOfTheHill;
+pub use kriegspiel::{CheckDirection, Kriegspiel};
+pub use racing_kings::RacingKings;
+pub use three_check::ThreeCheck;
+
+/// The variant a game is played in, chosen when the game is created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VariantKind {
+    #[default]
+    Standard,
+    Crazyhouse,
+    ThreeCheck,
+    KingOfTheHill,
+    Atomic,
+    Horde,
+    RacingKings,
+    /// Only played in four player matches, where the server links two boards
+    Bughouse,
+    Kriegspiel,
+    FogOfWar,
+}
+
+impl VariantKind {
+    pub fn rules(self) -> &'static dyn Variant {
+        match self {
+            VariantKind::Standard => &Standard,
+            VariantKind::Crazyhouse => &Crazyhouse,
+            VariantKind::ThreeCheck => &ThreeCheck,
+            VariantKind::KingOfTheHill => &KingOfTheHill,
+            VariantKind::Atomic => &Atomic,
+            VariantKind::Horde => &Horde,
+            VariantKind::RacingKings => &RacingKings,
+            VariantKind::Bughouse => &Bughouse,
+            VariantKind::Kriegspiel => &Kriegspiel,
+            VariantKind::FogOfWar => &FogOfWar,
+        }
+    }
+
+    /// The name used by the PGN `Variant` tag
+    pub fn name(self) -> &'static str {
+        match self {
+            VariantKind::Standard => "Standard",
+            VariantKind::Crazyhouse => "Crazyhouse",
+            VariantKind::ThreeCheck => "Three-check",
+            VariantKind::KingOfTheHill => "King of the Hill",
+            VariantKind::Atomic => "Atomic",
+            VariantKind::Horde => "Horde",
+            VariantKind::RacingKings => "Racing Kings",
+            VariantKind::Bughouse => "Bughouse",
+            VariantKind::Kriegspiel => "Kriegspiel",
+            VariantKind::FogOfWar => "Fog of War",
+        }
+    }
+}
+
+/// Pieces in hand, counted by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Pocket([u8; 5]);
+
+impl Pocket {
+    /// Kings are never captured, so they never end up in a pocket
+    const KINDS: [PieceKind; 5] = [
+        PieceKind::Queen,
+        PieceKind::Rook,
+        PieceKind::Bishop,
+        PieceKind::Knight,
+        PieceKind::Pawn,
+    ];
+
+    fn index(kind: PieceKind) -> usize {
+        match kind {
+            PieceKind::Queen => 0,
+            PieceKind::Rook => 1,
+            PieceKind::Bishop => 2,
+            PieceKind::Knight => 3,
+            _ => 4,
+        }
+    }
+
+    pub fn count(&self, kind: PieceKind) -> u8 {
+        self.0[Self::index(kind)]
+    }
+
+    fn add(&mut self, kind: PieceKind) {
+        self.0[Self::index(kind)] += 1;
+    }
+
+    fn take(&mut self, kind: PieceKind) {
+        let count = &mut self.0[Self::index(kind)];
+        *count = count.saturating_sub(1);
+    }
+
+    /// Every kind with at least one piece in hand, the most valuable first
+    fn kinds(&self) -> impl Iterator<Item = PieceKind> + '_ {
+        Self::KINDS.into_iter().filter(|&kind| self.count(kind) > 0)
+    }
+}
+
+/// What variants keep track of besides the position
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VariantState {
+    /// Crazyhouse pieces in hand, white's first
+    #[serde(default)]
+    pub pockets: [Pocket; 2],
+    /// Crazyhouse squares holding promoted pieces, which go back to a pocket as pawns
+    #[serde(default)]
+    pub promoted: u64,
+    /// Three-check checks given, white's first
+    #[serde(default)]
+    pub checks: [u8; 2],
+}
+
+impl VariantState {
+    /// Put a piece in the side's pocket, bughouse hands over the partner's captures this way
+    pub fn add_to_pocket(&mut self, color: Color, kind: PieceKind) {
+        self.pockets[side(color)].add(kind);
+    }
+}
+
+/// Index of the side in the per-color arrays of [`VariantState`]
+fn side(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        _ => 1,
+    }
+}
+
+/// The squares around the square
+fn neighbours(square: u8) -> impl Iterator<Item = u8> {
+    let (file, rank) = (file_of(square) as i8, rank_of(square) as i8);
+    (-1..=1)
+        .flat_map(move |df| (-1..=1).map(move |dr| (file + df, rank + dr)))
+        .filter(move |&(f, r)| (f, r) != (file, rank) && (0..8).contains(&f) && (0..8).contains(&r))
+        .map(|(f, r)| (r * 8 + f) as u8)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Board {
+    #[serde(default)]
+    pub position: Position,
+    #[serde(default)]
+    pub variant_state: VariantState,
+}
+
+/// The rules of a variant, the defaults are those of standard chess
+pub trait Variant: Sync {
+    fn starting_board(&self) -> Board {
+        Board::default()
+    }
+
+    /// Every move the side to move can play
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        board.position.legal_moves()
+    }
+
+    /// The kind of piece the move captures, if any
+    fn captured(&self, board: &Board, mv: Move) -> Option<PieceKind> {
+        match board.position.is_en_passant(mv) {
+            true => Some(PieceKind::Pawn),
+            false => board.position.piece_at(mv.to).map(|piece| piece.kind),
+        }
+    }
+
+    fn is_legal(&self, board: &Board, mv: Move) -> bool {
+        self.legal_moves(board).contains(&mv)
+    }
+
+    /// The board after playing a move, which has to be legal
+    fn play(&self, board: &Board, mv: Move) -> Board {
+        Board {
+            position: board.position.play(mv),
+            variant_state: board.variant_state.clone(),
+        }
+    }
+
+    fn in_check(&self, board: &Board) -> bool {
+        board.position.in_check()
+    }
+
+    /// How the game ended on this board, None if it goes on
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        board.position.outcome()
+    }
+
+    /// Who won the game that ended with the outcome, None for draws
+    fn winner(&self, board: &Board, outcome: Outcome) -> Option<Color> {
+        // decisive outcomes are usually a loss for the side that can't move on
+        outcome
+            .is_decisive()
+            .then(|| board.position.side_to_move().opposite())
+    }
+
+    /// FEN of the board, extended with whatever the variant keeps next to the position
+    fn fen(&self, board: &Board) -> String {
+        board.position.to_fen()
+    }
+
+    /// The squares the viewer can see as a bit set, None when the whole board is visible
+    fn visible_squares(&self, _board: &Board, _viewer: Color) -> Option<u64> {
+        None
+    }
+
+    /// What the viewer is allowed to see of the board. Pieces on hidden squares are left
+    /// out, so are the opponent's castling rights, an en passant square the viewer can't
+    /// see and the halfmove clock, which would give the opponent's pawn moves away.
+    fn fen_for(&self, board: &Board, viewer: Color) -> String {
+        let Some(visible) = self.visible_squares(board, viewer) else {
+            return self.fen(board);
+        };
+
+        let mut view = board.position.clone();
+        for (square, piece) in view.board.iter_mut().enumerate() {
+            if piece.is_some_and(|piece| piece.color != viewer) && visible & 1 << square == 0 {
+                *piece = None;
+            }
+        }
+        view.castling &= match viewer {
+            Color::White => WHITE_KINGSIDE | WHITE_QUEENSIDE,
+            _ => BLACK_KINGSIDE | BLACK_QUEENSIDE,
+        };
+        view.en_passant = view.en_passant.filter(|&square| visible & 1 << square != 0);
+        view.halfmove_clock = 0;
+
+        view.to_fen()
+    }
+
+    /// Whether players only see part of the board, the opponent's moves are kept from
+    /// them too until the game is over
+    fn hides_information(&self) -> bool {
+        false
+    }
+
+    /// Standard algebraic notation of a legal move, with check and mate marks
+    /// following the variant's rules
+    fn san(&self, board: &Board, mv: Move) -> String {
+        let mut san = board.position.san_without_check(mv);
+
+        let next = self.play(board, mv);
+        if self.in_check(&next) {
+            san.push(match self.outcome(&next) {
+                Some(Outcome::Checkmate) => '#',
+                _ => '+',
+            });
+        }
+
+        san
+    }
+}
+
+pub struct Standard;
+
+impl Variant for Standard {}
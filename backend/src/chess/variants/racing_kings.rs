@@ -0,0 +1,142 @@
+use super::{Board, Variant};
+use crate::chess::{rank_of, Move, Outcome, PieceKind, Position};
+use crate::types::Color;
+
+const STARTING_FEN: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+/// Both kings race to the eighth rank, giving check is not allowed
+pub struct RacingKings;
+
+impl RacingKings {
+    fn on_goal(position: &Position, color: Color) -> bool {
+        position
+            .king_square(color)
+            .is_some_and(|king| rank_of(king) == 7)
+    }
+}
+
+impl Variant for RacingKings {
+    fn starting_board(&self) -> Board {
+        Board {
+            position: Position::from_fen(STARTING_FEN).expect("valid racing kings FEN"),
+            ..Board::default()
+        }
+    }
+
+    /// Moves that leave neither king in check
+    fn legal_moves(&self, board: &Board) -> Vec<Move> {
+        let position = &board.position;
+        position
+            .pseudo_legal_moves()
+            .into_iter()
+            .filter(|&mv| {
+                let next = position.play(mv);
+                [Color::White, Color::Black].into_iter().all(|color| {
+                    next.king_square(color)
+                        .is_some_and(|king| !next.is_attacked(king, color.opposite()))
+                })
+            })
+            .collect()
+    }
+
+    fn in_check(&self, _: &Board) -> bool {
+        false
+    }
+
+    /// White moves first, so when its king gets there black has one move left to tie
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let position = &board.position;
+        match (
+            Self::on_goal(position, Color::White),
+            Self::on_goal(position, Color::Black),
+        ) {
+            (true, true) => return Some(Outcome::RaceTied),
+            (false, true) => return Some(Outcome::RaceWon),
+            (true, false) => {
+                let black_can_follow = position.side_to_move() == Color::Black
+                    && self.legal_moves(board).into_iter().any(|mv| {
+                        position.piece_at(mv.from).map(|p| p.kind) == Some(PieceKind::King)
+                            && rank_of(mv.to) == 7
+                    });
+                if !black_can_follow {
+                    return Some(Outcome::RaceWon);
+                }
+            }
+            (false, false) => {}
+        }
+
+        if self.legal_moves(board).is_empty() {
+            return Some(Outcome::Stalemate);
+        }
+        if position.halfmove_clock() >= 100 {
+            return Some(Outcome::FiftyMoves);
+        }
+
+        None
+    }
+
+    fn winner(&self, board: &Board, outcome: Outcome) -> Option<Color> {
+        match outcome {
+            Outcome::RaceWon => [Color::White, Color::Black]
+                .into_iter()
+                .find(|&color| Self::on_goal(&board.position, color)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::from_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    #[test]
+    fn checks_are_not_allowed() {
+        let board = board("8/8/8/8/8/k7/7R/7K w - - 0 1");
+
+        assert!(!RacingKings.is_legal(&board, mv("h2h3")));
+        assert!(RacingKings.is_legal(&board, mv("h2h4")));
+    }
+
+    #[test]
+    fn black_can_still_tie() {
+        let board = board("K7/7k/8/8/8/8/8/8 b - - 0 1");
+        assert_eq!(RacingKings.outcome(&board), None);
+
+        let next = RacingKings.play(&board, mv("h7h8"));
+        assert_eq!(RacingKings.outcome(&next), Some(Outcome::RaceTied));
+        assert_eq!(RacingKings.winner(&next, Outcome::RaceTied), None);
+    }
+
+    #[test]
+    fn white_wins_when_black_cannot_follow() {
+        let board = board("K7/8/7k/8/8/8/8/8 b - - 0 1");
+
+        assert_eq!(RacingKings.outcome(&board), Some(Outcome::RaceWon));
+        assert_eq!(
+            RacingKings.winner(&board, Outcome::RaceWon),
+            Some(Color::White)
+        );
+    }
+
+    #[test]
+    fn black_reaching_the_goal_wins_at_once() {
+        let board = board("7k/8/K7/8/8/8/8/8 w - - 0 1");
+
+        assert_eq!(RacingKings.outcome(&board), Some(Outcome::RaceWon));
+        assert_eq!(
+            RacingKings.winner(&board, Outcome::RaceWon),
+            Some(Color::Black)
+        );
+    }
+}
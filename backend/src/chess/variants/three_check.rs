@@ -0,0 +1,97 @@
+use super::{side, Board, Variant};
+use crate::chess::{Move, Outcome};
+
+/// Checks a side has to give to win
+const CHECKS_TO_WIN: u8 = 3;
+
+/// Standard chess, except that checking the opponent for the third time wins
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn play(&self, board: &Board, mv: Move) -> Board {
+        let position = board.position.play(mv);
+        let mut variant_state = board.variant_state.clone();
+        if position.in_check() {
+            variant_state.checks[side(board.position.side_to_move())] += 1;
+        }
+
+        Board {
+            position,
+            variant_state,
+        }
+    }
+
+    fn outcome(&self, board: &Board) -> Option<Outcome> {
+        let opponent = board.position.side_to_move().opposite();
+        if board.variant_state.checks[side(opponent)] >= CHECKS_TO_WIN {
+            return Some(Outcome::ThreeChecks);
+        }
+
+        // any piece can still give checks, only bare kings are a dead draw
+        match board.position.outcome() {
+            Some(Outcome::InsufficientMaterial) if board.position.pieces().count() > 2 => None,
+            outcome => outcome,
+        }
+    }
+
+    /// The checks each side still has to give go between the en passant square and the
+    /// halfmove clock, white's first, e.g. `3+2`
+    fn fen(&self, board: &Board) -> String {
+        let fen = board.position.to_fen();
+        let fields: Vec<&str> = fen.split(' ').collect();
+        let [white, black] = board
+            .variant_state
+            .checks
+            .map(|checks| CHECKS_TO_WIN.saturating_sub(checks));
+
+        format!(
+            "{} {white}+{black} {}",
+            fields[..4].join(" "),
+            fields[4..].join(" ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::Position;
+    use crate::types::Color;
+
+    fn board(fen: &str) -> Board {
+        Board {
+            position: Position::from_fen(fen).unwrap(),
+            ..Board::default()
+        }
+    }
+
+    #[test]
+    fn third_check_wins() {
+        let mut board = board("4k3/8/8/8/8/8/8/4K2Q w - - 0 1");
+        assert_eq!(ThreeCheck.fen(&board), "4k3/8/8/8/8/8/8/4K2Q w - - 3+3 0 1");
+
+        for (i, uci) in ["h1h5", "e8d8", "h5h8", "d8d7", "h8h7"].iter().enumerate() {
+            assert_eq!(ThreeCheck.outcome(&board), None, "before move {i}");
+            board = ThreeCheck.play(&board, Move::from_uci(uci).unwrap());
+        }
+
+        assert_eq!(board.variant_state.checks, [3, 0]);
+        assert_eq!(ThreeCheck.fen(&board), "8/3k3Q/8/8/8/8/8/4K3 b - - 0+3 5 3");
+        assert_eq!(ThreeCheck.outcome(&board), Some(Outcome::ThreeChecks));
+        assert_eq!(
+            ThreeCheck.winner(&board, Outcome::ThreeChecks),
+            Some(Color::White)
+        );
+    }
+
+    #[test]
+    fn a_lone_minor_piece_can_still_check() {
+        let board = board("4k3/8/8/8/8/8/8/4KN2 w - - 0 1");
+
+        assert_eq!(
+            board.position.outcome(),
+            Some(Outcome::InsufficientMaterial)
+        );
+        assert_eq!(ThreeCheck.outcome(&board), None);
+    }
+}
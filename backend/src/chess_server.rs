@@ -1,14 +1,45 @@
 use actix::{Actor, Addr};
 use actix_files::NamedFile;
-use actix_web::Responder;
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{Payload, ServerHandle};
+use actix_web::error::{
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorServiceUnavailable, ErrorUnauthorized,
+};
+use actix_web::http::header;
+use actix_web::{delete, post, FromRequest, Responder};
 use actix_web::{dev::Server, get, web, App, Error, HttpRequest, HttpResponse, HttpServer, Result};
 use actix_web_actors::ws;
-use std::path::PathBuf;
+use futures_util::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
 
-use crate::config::Settings;
+use crate::accounts::{Accounts, SESSION_COOKIE};
+use crate::analysis;
+use crate::archive::GameArchive;
+use crate::chess::{Position, VariantKind};
+use crate::config::{AdminSettings, Settings, ShutdownSettings};
+use crate::engine::{EnginePool, PooledEngine, SearchLimit};
+use crate::pgn;
+use crate::players::{self, Profile};
+use crate::puzzles::PuzzleStore;
+use crate::rating::{Pool, RatingStore};
+use crate::tactics::{self, MinedPuzzle};
+use crate::types::{ChessMove, GameResult};
+use crate::utils::constant_time_eq;
 use crate::websocket::{
-    server::WsChessServer, servers::in_memory::InMemoryServer, session::SessionActor,
+    messages::{
+        ActiveGames, Announcement, BeginShutdown, GetGame, GetInvitation, GetStoredGame,
+        KickSession, ListCorrespondenceGames, ListGames, ListOpenGames, ListSessions, SaveSnapshot,
+        SubmitMove, TerminateGame,
+    },
+    server::WsChessServer,
+    servers::in_memory::InMemoryServer,
+    session::SessionActor,
 };
 
 pub struct ChessServer {
@@ -21,30 +52,159 @@ impl ChessServer {
     }
 
     pub fn build(&self) -> Result<Server, Error> {
-        let in_memory_state = InMemoryServer::default();
-        let websocket_server = WsChessServer::new(in_memory_state).start();
+        let snapshot_path = Path::new(&self.config.shutdown.snapshot_path);
+        let in_memory_state = InMemoryServer::restore(snapshot_path).unwrap_or_else(|e| {
+            error!(error = %e, "unable to restore game snapshot, starting empty");
+            InMemoryServer::default()
+        });
+        let ratings = Arc::new(RatingStore::open(&self.config.ratings)?);
+        let archive = Arc::new(GameArchive::open(&self.config.archive)?);
+        let puzzles = Arc::new(PuzzleStore::open(&self.config.puzzles)?);
+        let accounts = Arc::new(Accounts::open(&self.config.auth)?);
+        let engines = EnginePool::new(self.config.engine.clone());
+        let websocket_server = WsChessServer::new(
+            in_memory_state,
+            &self.config,
+            ratings.clone(),
+            archive.clone(),
+            puzzles.clone(),
+            accounts.clone(),
+            engines.clone(),
+        )
+        .start();
+        let shutdown_server = websocket_server.clone();
+        let shutdown_puzzles = puzzles.clone();
 
         let host = self.config.app.host.as_str();
         let port = self.config.app.port;
 
         let player_count_limit: Arc<Mutex<u8>> = Arc::new(Mutex::new(0));
+        let admin_settings = self.config.admin.clone();
 
         let server = HttpServer::new(move || {
             App::new()
                 .app_data(web::Data::new(websocket_server.clone()))
                 .app_data(web::Data::new(player_count_limit.clone()))
+                .app_data(web::Data::new(admin_settings.clone()))
+                .app_data(web::Data::new(engines.clone()))
+                .app_data(web::Data::from(accounts.clone()))
+                .app_data(web::Data::from(ratings.clone()))
+                .app_data(web::Data::from(archive.clone()))
+                .app_data(web::Data::from(puzzles.clone()))
+                .service(
+                    web::scope("/admin")
+                        .service(admin_list_sessions)
+                        .service(admin_kick_session)
+                        .service(admin_list_games)
+                        .service(admin_get_game)
+                        .service(admin_terminate_game)
+                        .service(admin_announce)
+                        .service(admin_mine_puzzles),
+                )
+                .service(register)
+                .service(login)
+                .service(logout)
+                .service(me)
+                .service(player_profile)
+                .service(leaderboard)
+                .service(analyse_position)
+                .service(game_pgn)
+                .service(game_position)
+                .service(analyse_game)
+                .service(correspondence_games)
+                .service(submit_move)
+                .service(lobby)
+                .service(join_invitation)
                 .service(index)
                 .service(file)
                 .service(websocket)
                 .service(health_check)
         })
+        // Signals are handled by `shutdown_on_signal` so games get a chance to wrap up
+        .disable_signals()
         .bind((host, port))?
         .run();
 
+        actix_web::rt::spawn(shutdown_on_signal(
+            shutdown_server,
+            server.handle(),
+            shutdown_puzzles,
+            self.config.shutdown.clone(),
+        ));
+
         Ok(server)
     }
 }
 
+/// Wait for SIGTERM/SIGINT, then tell every session the server is going away, give the
+/// running games until the grace period ends, snapshot what is left, write the puzzle
+/// ratings not written yet and stop the HTTP server.
+async fn shutdown_on_signal(
+    ws_server: Addr<WsChessServer<InMemoryServer>>,
+    server: ServerHandle,
+    puzzles: Arc<PuzzleStore>,
+    settings: ShutdownSettings,
+) {
+    wait_for_signal().await;
+    info!("shutdown signal received");
+
+    let grace_period = Duration::from_secs(settings.grace_period_secs);
+    let deadline = Instant::now() + grace_period;
+
+    if let Err(e) = ws_server
+        .send(BeginShutdown {
+            message: "The server is restarting for maintenance".to_string(),
+            deadline_secs: settings.grace_period_secs,
+        })
+        .await
+    {
+        error!(error = %e, "unable to notify sessions of the shutdown");
+    }
+
+    while Instant::now() < deadline {
+        match ws_server.send(ActiveGames).await {
+            Ok(0) | Err(_) => break,
+            Ok(active) => info!(active, "waiting for games to finish"),
+        }
+        actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    match ws_server
+        .send(SaveSnapshot {
+            path: PathBuf::from(&settings.snapshot_path),
+        })
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!(error = %e, "unable to write game snapshot"),
+        Err(e) => error!(error = %e, "unable to reach websocket server for snapshot"),
+    }
+    if let Err(e) = puzzles.flush() {
+        error!(error = %e, "unable to save puzzles");
+    }
+
+    server.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("unable to listen for ctrl-c");
+}
+
 #[get("/health_check")]
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().finish()
@@ -55,6 +215,38 @@ async fn index() -> impl Responder {
     NamedFile::open_async("./dist/index.html").await.unwrap()
 }
 
+/// The SPA with the invitation preloaded as `window.INVITATION`, the client joins the
+/// game with the token from there
+#[get("/join/{token}")]
+async fn join_invitation(
+    token: web::Path<String>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let token = token.into_inner();
+    let game = ws_server
+        .send(GetInvitation {
+            token: token.clone(),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("this invitation is no longer valid"))?;
+
+    let invitation = serde_json::json!({ "token": token, "game": game })
+        .to_string()
+        // the game name is chosen by players, keep it from closing the script tag
+        .replace('<', "\\u003c");
+    let html = tokio::fs::read_to_string("./dist/index.html")
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let page = html.replacen(
+        "</head>",
+        &format!("<script>window.INVITATION = {invitation};</script></head>"),
+        1,
+    );
+
+    Ok(HttpResponse::Ok().content_type("text/html").body(page))
+}
+
 #[get("/assets/{filename:.*}")]
 async fn file(req: HttpRequest) -> Result<NamedFile> {
     let path: PathBuf = req.match_info().query("filename").parse().unwrap();
@@ -69,13 +261,651 @@ async fn websocket(
     req: HttpRequest,
     stream: web::Payload,
     ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+    accounts: web::Data<Accounts>,
 ) -> Result<HttpResponse, Error> {
+    // Guests are still welcome, an invalid or expired token just connects as a guest
+    let user = login_token(&req)
+        .or_else(|| query_token(&req))
+        .and_then(|token| accounts.authenticate(&token));
+
     // let mut player_count = player_count_limit.lock().unwrap();
 
     // if *player_count < 3 {
     //     *player_count += 1;
-    return ws::start(SessionActor::new(ws_server.get_ref().clone()), &req, stream);
+    ws::start(
+        SessionActor::new(ws_server.get_ref().clone(), user),
+        &req,
+        stream,
+    )
     // } else {
     //     return Err(ErrorBadRequest("player limit reached"));
     // }
 }
+
+/// Only lets a request through when it carries `Authorization: Bearer <admin token>`
+pub struct AdminAuth;
+
+impl FromRequest for AdminAuth {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let expected = req
+            .app_data::<web::Data<AdminSettings>>()
+            .map(|settings| settings.token.as_str())
+            .filter(|token| !token.is_empty());
+        let provided = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        ready(match (expected, provided) {
+            (Some(expected), Some(provided)) if constant_time_eq(expected, provided) => {
+                Ok(AdminAuth)
+            }
+            _ => Err(ErrorUnauthorized("invalid admin token")),
+        })
+    }
+}
+
+#[get("/sessions")]
+async fn admin_list_sessions(
+    _: AdminAuth,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let sessions = ws_server
+        .send(ListSessions)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[derive(Deserialize)]
+struct KickParams {
+    reason: Option<String>,
+}
+
+#[delete("/sessions/{id}")]
+async fn admin_kick_session(
+    _: AdminAuth,
+    id: web::Path<String>,
+    params: web::Query<KickParams>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let kicked = ws_server
+        .send(KickSession {
+            id: id.into_inner(),
+            reason: params
+                .into_inner()
+                .reason
+                .unwrap_or_else(|| "Disconnected by an administrator".to_string()),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !kicked {
+        return Err(ErrorNotFound("session not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/games")]
+async fn admin_list_games(
+    _: AdminAuth,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let games = ws_server
+        .send(ListGames)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(games))
+}
+
+#[get("/games/{id}")]
+async fn admin_get_game(
+    _: AdminAuth,
+    id: web::Path<String>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let game = ws_server
+        .send(GetGame {
+            id: id.into_inner(),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("game not found"))?;
+
+    Ok(HttpResponse::Ok().json(game))
+}
+
+#[derive(Deserialize)]
+struct TerminateParams {
+    result: GameResult,
+}
+
+#[post("/games/{id}/terminate")]
+async fn admin_terminate_game(
+    _: AdminAuth,
+    id: web::Path<String>,
+    params: web::Json<TerminateParams>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let terminated = ws_server
+        .send(TerminateGame {
+            id: id.into_inner(),
+            result: params.result,
+        })
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !terminated {
+        return Err(ErrorNotFound("game not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/announcements")]
+async fn admin_announce(
+    _: AdminAuth,
+    announcement: web::Json<Announcement>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    ws_server
+        .send(announcement.into_inner())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Most games searched for puzzles in one request
+const MAX_MINED_GAMES: usize = 50;
+
+#[derive(Deserialize)]
+struct MiningParams {
+    game_id: Option<String>,
+    /// How many of the latest games not searched yet to go through, without a game ID
+    limit: Option<usize>,
+    depth: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct MiningReport {
+    games: usize,
+    /// The puzzles added to the collection
+    puzzles: Vec<MinedPuzzle>,
+}
+
+/// Search archived games for puzzles and add them to the collection. Games without an
+/// engine review are reviewed first, and the review is kept with the game.
+#[post("/puzzles/mine")]
+async fn admin_mine_puzzles(
+    _: AdminAuth,
+    params: web::Query<MiningParams>,
+    archive: web::Data<GameArchive>,
+    puzzles: web::Data<PuzzleStore>,
+    engines: web::Data<EnginePool>,
+) -> Result<HttpResponse, Error> {
+    let params = params.into_inner();
+    let records = match &params.game_id {
+        Some(id) => {
+            let record = archive
+                .get(id)
+                .ok_or_else(|| ErrorNotFound("game not found"))?;
+            if record.variant != VariantKind::Standard {
+                return Err(ErrorBadRequest("only standard chess games are searched"));
+            }
+            vec![record]
+        }
+        None => archive.unmined_games(params.limit.unwrap_or(10).min(MAX_MINED_GAMES)),
+    };
+    let depth = params
+        .depth
+        .unwrap_or(engines.settings().analysis_depth)
+        .clamp(1, MAX_ANALYSIS_DEPTH);
+
+    let mut engine = pooled_engine(&engines).await?;
+    let mut report = MiningReport {
+        games: records.len(),
+        puzzles: Vec::new(),
+    };
+    for record in &records {
+        let analysis = match &record.analysis {
+            Some(analysis) => analysis.clone(),
+            None => {
+                let analysis = analysis::analyse_game(&mut engine, &record.moves, depth)
+                    .await
+                    .map_err(ErrorInternalServerError)?;
+                archive
+                    .attach_analysis(&record.id, analysis.clone())
+                    .map_err(ErrorInternalServerError)?;
+                analysis
+            }
+        };
+
+        let mined = tactics::mine_game(&mut engine, record, &analysis, depth)
+            .await
+            .map_err(ErrorInternalServerError)?;
+        for mined in mined {
+            // mining a game again finds the puzzles it already gave
+            if puzzles
+                .insert(mined.puzzle.clone())
+                .map_err(ErrorInternalServerError)?
+            {
+                report.puzzles.push(mined);
+            }
+        }
+        archive
+            .mark_mined(&record.id)
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    info!(
+        games = report.games,
+        puzzles = report.puzzles.len(),
+        depth,
+        "mined archived games for puzzles"
+    );
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+/// The login token from the session cookie
+fn login_token(req: &HttpRequest) -> Option<String> {
+    req.cookie(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_owned())
+}
+
+/// The login token from the `token` query parameter, only accepted for the websocket
+/// handshake of clients that can't send cookies along with it
+fn query_token(req: &HttpRequest) -> Option<String> {
+    web::Query::<TokenQuery>::from_query(req.query_string())
+        .ok()
+        .map(|query| query.into_inner().token)
+}
+
+#[post("/register")]
+async fn register(
+    credentials: web::Json<Credentials>,
+    accounts: web::Data<Accounts>,
+) -> Result<HttpResponse, Error> {
+    let credentials = credentials.into_inner();
+
+    // Hashing the password is expensive, keep it off the async workers
+    let account =
+        web::block(move || accounts.register(&credentials.username, &credentials.password))
+            .await??;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({ "username": account.username })))
+}
+
+#[post("/login")]
+async fn login(
+    credentials: web::Json<Credentials>,
+    accounts: web::Data<Accounts>,
+) -> Result<HttpResponse, Error> {
+    let credentials = credentials.into_inner();
+    let username = credentials.username.clone();
+    let max_age = time::Duration::seconds(accounts.session_ttl().as_secs() as i64);
+
+    let token =
+        web::block(move || accounts.login(&credentials.username, &credentials.password)).await??;
+
+    let cookie = Cookie::build(SESSION_COOKIE, token.clone())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(max_age)
+        .finish();
+
+    Ok(HttpResponse::Ok()
+        .cookie(cookie)
+        .json(serde_json::json!({ "username": username, "token": token })))
+}
+
+#[post("/logout")]
+async fn logout(req: HttpRequest, accounts: web::Data<Accounts>) -> impl Responder {
+    if let Some(token) = login_token(&req) {
+        accounts.logout(&token);
+    }
+
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+
+    HttpResponse::NoContent().cookie(cookie).finish()
+}
+
+#[get("/me")]
+async fn me(req: HttpRequest, accounts: web::Data<Accounts>) -> Result<HttpResponse, Error> {
+    let username = login_token(&req)
+        .and_then(|token| accounts.authenticate(&token))
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "username": username })))
+}
+
+/// Public games waiting for an opponent
+#[get("/lobby")]
+async fn lobby(
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let games = ws_server
+        .send(ListOpenGames)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(games))
+}
+
+#[get("/correspondence")]
+async fn correspondence_games(
+    req: HttpRequest,
+    accounts: web::Data<Accounts>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let username = login_token(&req)
+        .and_then(|token| accounts.authenticate(&token))
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    let games = ws_server
+        .send(ListCorrespondenceGames { user: username })
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(games))
+}
+
+/// Play a move in a correspondence game without opening a websocket
+#[post("/games/{id}/moves")]
+async fn submit_move(
+    req: HttpRequest,
+    id: web::Path<String>,
+    chess_move: web::Json<ChessMove>,
+    accounts: web::Data<Accounts>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let username = login_token(&req)
+        .and_then(|token| accounts.authenticate(&token))
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    let mut chess_move = chess_move.into_inner();
+    chess_move.premove = false;
+    let played = ws_server
+        .send(SubmitMove {
+            game_id: id.into_inner(),
+            user: username,
+            chess_move,
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(ErrorBadRequest)?;
+
+    Ok(HttpResponse::Ok().json(played))
+}
+
+#[get("/players/{name}")]
+async fn player_profile(
+    name: web::Path<String>,
+    accounts: web::Data<Accounts>,
+    ratings: web::Data<RatingStore>,
+    archive: web::Data<GameArchive>,
+) -> Result<HttpResponse, Error> {
+    let account = accounts
+        .get(&name)
+        .ok_or_else(|| ErrorNotFound("player not found"))?;
+
+    Ok(HttpResponse::Ok().json(Profile::new(account, &ratings, &archive)))
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    pool: Pool,
+    limit: Option<usize>,
+}
+
+#[get("/leaderboard")]
+async fn leaderboard(
+    query: web::Query<LeaderboardQuery>,
+    ratings: web::Data<RatingStore>,
+    archive: web::Data<GameArchive>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(50).min(200);
+
+    HttpResponse::Ok().json(players::leaderboard(query.pool, limit, &ratings, &archive))
+}
+
+/// Keeps a single request from tying up the engine for minutes
+const MAX_ANALYSIS_DEPTH: u32 = 30;
+
+#[derive(Deserialize)]
+struct AnalysisRequest {
+    fen: String,
+    depth: Option<u32>,
+}
+
+/// An engine from the shared pool, turning the request away while they are all busy
+async fn pooled_engine(engines: &EnginePool) -> Result<PooledEngine, Error> {
+    if engines.settings().path.is_empty() {
+        return Err(ErrorServiceUnavailable(
+            "no engine is available on this server",
+        ));
+    }
+
+    engines.acquire(None).await.map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock => {
+            ErrorServiceUnavailable("every engine is busy, try again in a moment")
+        }
+        _ => ErrorInternalServerError(e),
+    })
+}
+
+#[post("/analysis")]
+async fn analyse_position(
+    req: HttpRequest,
+    request: web::Json<AnalysisRequest>,
+    accounts: web::Data<Accounts>,
+    engines: web::Data<EnginePool>,
+) -> Result<HttpResponse, Error> {
+    login_token(&req)
+        .and_then(|token| accounts.authenticate(&token))
+        .ok_or_else(|| ErrorUnauthorized("not logged in"))?;
+
+    let request = request.into_inner();
+    // the engine only gets positions that could come up in a game, in a FEN written by
+    // the server itself
+    let fen = Position::from_fen(&request.fen)
+        .map_err(ErrorBadRequest)?
+        .to_fen();
+    let depth = request
+        .depth
+        .unwrap_or(engines.settings().analysis_depth)
+        .clamp(1, MAX_ANALYSIS_DEPTH);
+
+    let mut engine = pooled_engine(&engines).await?;
+    let analysis = engine
+        .analyse(&fen, SearchLimit::Depth(depth))
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(analysis))
+}
+
+/// Private games are only exported for their players
+#[get("/games/{id}/pgn")]
+async fn game_pgn(
+    req: HttpRequest,
+    id: web::Path<String>,
+    accounts: web::Data<Accounts>,
+    archive: web::Data<GameArchive>,
+) -> Result<HttpResponse, Error> {
+    let user = login_token(&req).and_then(|token| accounts.authenticate(&token));
+    let record = archive
+        .get(&id)
+        .filter(|record| record.visible_to(user.as_deref()))
+        .ok_or_else(|| ErrorNotFound("game not found"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-chess-pgn")
+        .body(pgn::export(&record)))
+}
+
+#[derive(Deserialize)]
+struct PositionQuery {
+    ply: Option<usize>,
+}
+
+/// The position of a finished or ongoing game after `ply` moves, the latest one without
+#[get("/games/{id}/positions")]
+async fn game_position(
+    id: web::Path<String>,
+    query: web::Query<PositionQuery>,
+    ws_server: web::Data<Addr<WsChessServer<InMemoryServer>>>,
+) -> Result<HttpResponse, Error> {
+    let game = ws_server
+        .send(GetStoredGame {
+            id: id.into_inner(),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("game not found"))?;
+    let position = game
+        .position_at(query.ply.unwrap_or(game.moves.len()))
+        .map_err(ErrorBadRequest)?;
+
+    Ok(HttpResponse::Ok().json(position))
+}
+
+#[derive(Deserialize)]
+struct GameAnalysisQuery {
+    depth: Option<u32>,
+}
+
+/// Review an archived game with the engine and keep the result with the game, for
+/// logged in players or the admin.
+///
+/// A game already reviewed is only analysed again when one of its players or the admin
+/// asks for a deeper review than the one kept.
+#[post("/games/{id}/analysis")]
+async fn analyse_game(
+    req: HttpRequest,
+    id: web::Path<String>,
+    query: web::Query<GameAnalysisQuery>,
+    admin: Option<AdminAuth>,
+    accounts: web::Data<Accounts>,
+    archive: web::Data<GameArchive>,
+    engines: web::Data<EnginePool>,
+) -> Result<HttpResponse, Error> {
+    let user = login_token(&req).and_then(|token| accounts.authenticate(&token));
+    if admin.is_none() && user.is_none() {
+        return Err(ErrorUnauthorized("not logged in"));
+    }
+
+    let record = archive
+        .get(&id)
+        .filter(|record| admin.is_some() || record.visible_to(user.as_deref()))
+        .ok_or_else(|| ErrorNotFound("game not found"))?;
+    let depth = query
+        .depth
+        .unwrap_or(engines.settings().analysis_depth)
+        .clamp(1, MAX_ANALYSIS_DEPTH);
+    if let Some(analysis) = &record.analysis {
+        if query.depth.is_none() || depth <= analysis.depth {
+            return Ok(HttpResponse::Ok().json(analysis));
+        }
+        let played = user
+            .as_deref()
+            .is_some_and(|user| record.color_of(user).is_some());
+        if admin.is_none() && !played {
+            return Err(ErrorForbidden(
+                "only the players can have the game reviewed again",
+            ));
+        }
+    }
+    if record.moves.is_empty() {
+        return Err(ErrorBadRequest("the game has no moves"));
+    }
+    if record.variant != VariantKind::Standard {
+        return Err(ErrorBadRequest("only standard chess games can be reviewed"));
+    }
+
+    let mut engine = pooled_engine(&engines).await?;
+    let analysis = analysis::analyse_game(&mut engine, &record.moves, depth)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    info!(game_id = %record.id, depth, "game analysed");
+    archive
+        .attach_analysis(&record.id, analysis.clone())
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(analysis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::server::tests::test_server;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    /// The status of listing the sessions with the authorization header, if any
+    async fn list_sessions(token: &str, authorization: Option<&str>) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_server().start()))
+                .app_data(web::Data::new(AdminSettings {
+                    token: token.to_owned(),
+                }))
+                .service(web::scope("/admin").service(admin_list_sessions)),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri("/admin/sessions");
+        if let Some(authorization) = authorization {
+            req = req.insert_header((header::AUTHORIZATION, authorization));
+        }
+        test::call_service(&app, req.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_need_the_token() {
+        assert_eq!(
+            list_sessions("secret", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_sessions("secret", Some("Bearer wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_sessions("secret", Some("secret")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            list_sessions("secret", Some("Bearer secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn admin_routes_are_closed_without_a_token() {
+        assert_eq!(
+            list_sessions("", Some("Bearer ")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -5,6 +5,15 @@ use std::env;
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub app: AppSettings,
+    pub log: LogSettings,
+    pub shutdown: ShutdownSettings,
+    pub admin: AdminSettings,
+    pub auth: AuthSettings,
+    pub ratings: RatingSettings,
+    pub archive: ArchiveSettings,
+    pub bot: BotSettings,
+    pub engine: EngineSettings,
+    pub puzzles: PuzzleSettings,
 }
 
 #[derive(Deserialize, Debug)]
@@ -12,6 +21,97 @@ pub struct AppSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    /// Websocket sessions the server takes at once, further connections are ignored
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_sessions: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogSettings {
+    /// A `tracing` filter directive, e.g. `info` or `chess_backend=debug,actix_web=info`
+    pub level: String,
+    /// Emit logs as JSON lines instead of human readable text
+    pub json: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ShutdownSettings {
+    /// How long running games are given to finish once a shutdown signal is received
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub grace_period_secs: u64,
+    /// Where the correspondence games are written on shutdown and restored from on the
+    /// next startup. Games on the clock aren't kept, the grace period is all they get.
+    pub snapshot_path: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AdminSettings {
+    /// Bearer token required by the `/admin` routes, they are disabled while it is empty
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthSettings {
+    /// JSON file holding the registered accounts
+    pub accounts_path: String,
+    /// How long a login stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub session_ttl_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RatingSettings {
+    /// JSON file holding every player's ratings
+    pub ratings_path: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArchiveSettings {
+    /// JSON lines file every finished game is appended to
+    pub games_path: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BotSettings {
+    /// Deepest search of the strongest level, every level below searches one ply less
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_depth: u8,
+    /// How long the bot may think about a move, it plays the deepest finished search after that
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub move_time_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EngineSettings {
+    /// Path to a UCI engine binary, the engine features are disabled while it is empty
+    pub path: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub threads: u32,
+    /// Size of the engine's hash table in megabytes
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub hash_mb: u32,
+    /// Passed to the engine's `Skill Level` option when it plays as an opponent
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub skill_level: u32,
+    /// How long the engine thinks about each move it plays
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub move_time_ms: u64,
+    /// Depth of the searches run for analysis requests that don't ask for one
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub analysis_depth: u32,
+    /// Most engine processes searching at once, for analysis requests and engine opponents
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_engines: usize,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PuzzleSettings {
+    /// JSON file holding the puzzle collection
+    pub puzzles_path: String,
+    /// JSON file holding the puzzles each account has attempted
+    pub attempts_path: String,
+    /// CSV file in the Lichess puzzle database layout imported on startup, skipped while empty
+    pub import_path: String,
 }
 
 pub enum Environment {
@@ -43,7 +143,7 @@ impl TryFrom<String> for Environment {
     }
 }
 
-pub fn get_config() -> Result<Settings, config::ConfigError>{
+pub fn get_config() -> Result<Settings, config::ConfigError> {
     let base_path = env::current_dir().expect("Failed to get current directory");
     let config_dir = base_path.join("configs");
 
@@ -63,7 +163,8 @@ pub fn get_config() -> Result<Settings, config::ConfigError>{
             config::Environment::with_prefix("APP")
                 .prefix_separator("_")
                 .separator("__"),
-        ).build()?;
+        )
+        .build()?;
 
     settings.try_deserialize::<Settings>()
 }
@@ -0,0 +1,536 @@
+//! Drives an external UCI engine, such as Stockfish, as a child process.
+//!
+//! The engine is spoken to over its stdin/stdout: `uci` and `isready` handshakes when it
+//! starts, then one `position` + `go` per search, reading `info` lines until `bestmove`.
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::config::EngineSettings;
+
+/// How long the engine may stay silent before it is considered stuck
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The `Skill Level` engines such as Stockfish play at full strength with
+const FULL_STRENGTH: u32 = 20;
+
+/// Evaluation reported by the engine, from the point of view of the side to move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Score {
+    /// Centipawns
+    Cp(i32),
+    /// Moves until mate, negative when the side to move is getting mated
+    Mate(i32),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Analysis {
+    /// In UCI notation, None when the side to move has no legal move
+    pub best_move: Option<String>,
+    pub score: Option<Score>,
+    pub depth: u32,
+    /// The line the engine expects, starting with the best move
+    pub pv: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SearchLimit {
+    Depth(u32),
+    MoveTime(Duration),
+}
+
+pub struct UciEngine {
+    /// Killed when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    /// How many lines the engine is set to report, its `MultiPV` option
+    lines: usize,
+    /// Its `Skill Level` option, None at full strength
+    skill_level: Option<u32>,
+    /// Shortened by the tests
+    response_timeout: Duration,
+    /// Set once talking to the engine failed or a search was left unfinished, what it
+    /// says next can't be trusted
+    failed: bool,
+    /// What the engine calls itself
+    pub name: Option<String>,
+}
+
+impl UciEngine {
+    /// Spawn the configured engine and wait until it is ready to search.
+    ///
+    /// The engine plays at full strength unless given a skill level.
+    pub async fn start(settings: &EngineSettings, skill_level: Option<u32>) -> io::Result<Self> {
+        if settings.path.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no UCI engine configured",
+            ));
+        }
+
+        let mut child = Command::new(&settings.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("engine stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("engine stdout is piped")).lines();
+
+        let mut engine = Self {
+            _child: child,
+            stdin,
+            stdout,
+            lines: 1,
+            skill_level: None,
+            response_timeout: RESPONSE_TIMEOUT,
+            failed: false,
+            name: None,
+        };
+
+        engine.send("uci").await?;
+        loop {
+            let line = engine.read_line().await?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = Some(name.trim().to_owned());
+            }
+            if line.trim() == "uciok" {
+                break;
+            }
+        }
+
+        engine
+            .set_option("Threads", &settings.threads.to_string())
+            .await?;
+        engine
+            .set_option("Hash", &settings.hash_mb.to_string())
+            .await?;
+        engine.set_skill_level(skill_level).await?;
+        engine.wait_ready().await?;
+
+        info!(engine = engine.name.as_deref(), path = %settings.path, "UCI engine started");
+        Ok(engine)
+    }
+
+    async fn send(&mut self, command: &str) -> io::Result<()> {
+        debug!(command, "to engine");
+        let sent = async {
+            self.stdin.write_all(command.as_bytes()).await?;
+            self.stdin.write_all(b"\n").await?;
+            self.stdin.flush().await
+        }
+        .await;
+        self.failed |= sent.is_err();
+
+        sent
+    }
+
+    async fn read_line(&mut self) -> io::Result<String> {
+        let line = match tokio::time::timeout(self.response_timeout, self.stdout.next_line()).await
+        {
+            Ok(Ok(Some(line))) => Ok(line),
+            Ok(Ok(None)) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the engine exited",
+            )),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the engine stopped answering",
+            )),
+        };
+        self.failed |= line.is_err();
+
+        line
+    }
+
+    async fn set_option(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.send(&format!("setoption name {name} value {value}"))
+            .await
+    }
+
+    /// Make the engine play at a skill level, or at full strength when None
+    pub async fn set_skill_level(&mut self, skill_level: Option<u32>) -> io::Result<()> {
+        if skill_level != self.skill_level {
+            let value = skill_level.unwrap_or(FULL_STRENGTH);
+            self.set_option("Skill Level", &value.to_string()).await?;
+            self.skill_level = skill_level;
+        }
+
+        Ok(())
+    }
+
+    async fn wait_ready(&mut self) -> io::Result<()> {
+        self.send("isready").await?;
+        while self.read_line().await?.trim() != "readyok" {}
+
+        Ok(())
+    }
+
+    /// Search the position, which has to be a valid FEN
+    pub async fn analyse(&mut self, fen: &str, limit: SearchLimit) -> io::Result<Analysis> {
+        let mut analyses = self.analyse_lines(fen, limit, 1).await?;
+
+        Ok(analyses.remove(0))
+    }
+
+    /// Search the position for its `lines` best moves, best first. The first analysis is
+    /// always there, the others only for moves the engine reported on.
+    pub async fn analyse_lines(
+        &mut self,
+        fen: &str,
+        limit: SearchLimit,
+        lines: usize,
+    ) -> io::Result<Vec<Analysis>> {
+        // stays set if the search is abandoned before its `bestmove`
+        let failed = std::mem::replace(&mut self.failed, true);
+        let lines = lines.max(1);
+        if lines != self.lines {
+            self.set_option("MultiPV", &lines.to_string()).await?;
+            self.lines = lines;
+        }
+
+        self.send(&format!("position fen {fen}")).await?;
+        self.send(&match limit {
+            SearchLimit::Depth(depth) => format!("go depth {depth}"),
+            SearchLimit::MoveTime(time) => format!("go movetime {}", time.as_millis()),
+        })
+        .await?;
+
+        let mut analyses = vec![
+            Analysis {
+                best_move: None,
+                score: None,
+                depth: 0,
+                pv: Vec::new(),
+            };
+            lines
+        ];
+        loop {
+            let line = self.read_line().await?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => parse_info(tokens.collect(), &mut analyses),
+                Some("bestmove") => {
+                    analyses[0].best_move = tokens
+                        .next()
+                        .filter(|mv| *mv != "(none)" && *mv != "0000")
+                        .map(str::to_owned);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        // the other lines start with their own move
+        for analysis in analyses.iter_mut().skip(1) {
+            analysis.best_move = analysis.pv.first().cloned();
+        }
+        let reported = 1 + analyses[1..]
+            .iter()
+            .take_while(|analysis| analysis.score.is_some())
+            .count();
+        analyses.truncate(reported);
+        self.failed = failed;
+
+        Ok(analyses)
+    }
+
+    /// Ask the engine to exit, it is killed if it doesn't
+    pub async fn quit(mut self) {
+        if let Err(e) = self.send("quit").await {
+            warn!(error = %e, "unable to stop the engine");
+        }
+    }
+}
+
+/// Keep the score and line of the latest `info` line carrying them, for the line it
+/// reports on when the engine reports several
+fn parse_info(tokens: Vec<&str>, analyses: &mut [Analysis]) {
+    let value_after = |key: &str| {
+        tokens
+            .iter()
+            .position(|t| *t == key)
+            .and_then(|i| tokens.get(i + 1))
+    };
+
+    let line = value_after("multipv")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(1);
+    let Some(analysis) = line.checked_sub(1).and_then(|i| analyses.get_mut(i)) else {
+        return;
+    };
+
+    let score = match value_after("score") {
+        Some(&"cp") => value_after("cp")
+            .and_then(|v| v.parse().ok())
+            .map(Score::Cp),
+        Some(&"mate") => value_after("mate")
+            .and_then(|v| v.parse().ok())
+            .map(Score::Mate),
+        _ => None,
+    };
+    let Some(score) = score else {
+        return;
+    };
+
+    analysis.score = Some(score);
+    if let Some(depth) = value_after("depth").and_then(|v| v.parse().ok()) {
+        analysis.depth = depth;
+    }
+    if let Some(i) = tokens.iter().position(|t| *t == "pv") {
+        analysis.pv = tokens[i + 1..].iter().map(|mv| mv.to_string()).collect();
+    }
+}
+
+/// Engine processes shared by the analysis endpoints and the engine opponents. At most
+/// `max_engines` of them search at once, and idle ones are kept for the next search.
+#[derive(Clone)]
+pub struct EnginePool {
+    settings: EngineSettings,
+    permits: Arc<Semaphore>,
+    idle: Arc<Mutex<Vec<UciEngine>>>,
+}
+
+impl EnginePool {
+    pub fn new(settings: EngineSettings) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(settings.max_engines.max(1))),
+            settings,
+            idle: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn settings(&self) -> &EngineSettings {
+        &self.settings
+    }
+
+    /// An engine nobody else is using, started if none is idle, set to play at the skill
+    /// level. Fails with `WouldBlock` while every engine is busy rather than queueing
+    /// searches up.
+    pub async fn acquire(&self, skill_level: Option<u32>) -> io::Result<PooledEngine> {
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "every engine is busy"))?;
+        let idle = self.idle.lock().unwrap().pop();
+        let mut engine = PooledEngine {
+            engine: match idle {
+                Some(engine) => Some(engine),
+                None => Some(UciEngine::start(&self.settings, skill_level).await?),
+            },
+            idle: self.idle.clone(),
+            _permit: permit,
+        };
+        engine.set_skill_level(skill_level).await?;
+
+        Ok(engine)
+    }
+}
+
+/// An engine taken from the pool, handed back once dropped unless it failed
+pub struct PooledEngine {
+    engine: Option<UciEngine>,
+    idle: Arc<Mutex<Vec<UciEngine>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledEngine {
+    type Target = UciEngine;
+
+    fn deref(&self) -> &Self::Target {
+        self.engine
+            .as_ref()
+            .expect("the engine is only taken when dropped")
+    }
+}
+
+impl DerefMut for PooledEngine {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.engine
+            .as_mut()
+            .expect("the engine is only taken when dropped")
+    }
+}
+
+impl Drop for PooledEngine {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take().filter(|engine| !engine.failed) {
+            self.idle.lock().unwrap().push(engine);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    /// Depth 1 searches normally, 2 finds no move, 3 hangs and 4 makes the engine exit,
+    /// see the script
+    fn mock_settings(max_engines: usize) -> EngineSettings {
+        EngineSettings {
+            path: concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/mock_uci_engine.sh"
+            )
+            .into(),
+            threads: 1,
+            hash_mb: 16,
+            skill_level: 20,
+            move_time_ms: 100,
+            analysis_depth: 1,
+            max_engines,
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let engine = UciEngine::start(&mock_settings(1), Some(5)).await.unwrap();
+
+        assert_eq!(engine.name.as_deref(), Some("Mock Engine 1.0"));
+        assert_eq!(engine.skill_level, Some(5));
+        assert!(!engine.failed);
+    }
+
+    #[test]
+    fn info_lines() {
+        let empty = Analysis {
+            best_move: None,
+            score: None,
+            depth: 0,
+            pv: Vec::new(),
+        };
+        let mut analyses = vec![empty.clone(), empty];
+        let info = |line: &str, analyses: &mut [Analysis]| {
+            parse_info(line.split_whitespace().skip(1).collect(), analyses)
+        };
+
+        info(
+            "info depth 5 seldepth 7 multipv 2 score mate -3 nodes 400 pv f2f3 e7e5 g2g4 d8h4",
+            &mut analyses,
+        );
+        // no score, or a line that wasn't asked for
+        info("info depth 6 currmove e2e4 currmovenumber 1", &mut analyses);
+        info("info depth 6 multipv 3 score cp 10 pv d2d4", &mut analyses);
+
+        assert_eq!(analyses[0].score, None);
+        assert_eq!(analyses[1].score, Some(Score::Mate(-3)));
+        assert_eq!(analyses[1].depth, 5);
+        assert_eq!(analyses[1].pv, ["f2f3", "e7e5", "g2g4", "d8h4"]);
+
+        // without multipv the first line is meant
+        info("info depth 7 score cp -20 pv e2e4 c7c5", &mut analyses);
+        assert_eq!(analyses[0].score, Some(Score::Cp(-20)));
+        assert_eq!(analyses[0].pv, ["e2e4", "c7c5"]);
+    }
+
+    #[tokio::test]
+    async fn analysis() {
+        let mut engine = UciEngine::start(&mock_settings(1), None).await.unwrap();
+
+        let lines = engine
+            .analyse_lines(FEN, SearchLimit::Depth(1), 2)
+            .await
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].best_move.as_deref(), Some("e2e4"));
+        assert_eq!(lines[0].score, Some(Score::Cp(35)));
+        assert_eq!(lines[0].depth, 5);
+        assert_eq!(lines[0].pv, ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(lines[1].best_move.as_deref(), Some("f2f3"));
+        assert_eq!(lines[1].score, Some(Score::Mate(-3)));
+
+        let best = engine.analyse(FEN, SearchLimit::Depth(1)).await.unwrap();
+        assert_eq!(best.best_move.as_deref(), Some("e2e4"));
+        assert_eq!(engine.lines, 1);
+        assert!(!engine.failed);
+    }
+
+    #[tokio::test]
+    async fn no_legal_move() {
+        let mut engine = UciEngine::start(&mock_settings(1), None).await.unwrap();
+
+        let analysis = engine.analyse(FEN, SearchLimit::Depth(2)).await.unwrap();
+        assert_eq!(analysis.best_move, None);
+        assert_eq!(analysis.score, Some(Score::Mate(0)));
+        assert!(!engine.failed);
+    }
+
+    #[tokio::test]
+    async fn engine_exiting_fails_it() {
+        let mut engine = UciEngine::start(&mock_settings(1), None).await.unwrap();
+
+        let e = engine
+            .analyse(FEN, SearchLimit::Depth(4))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(engine.failed);
+    }
+
+    #[tokio::test]
+    async fn silent_engine_fails_it() {
+        let mut engine = UciEngine::start(&mock_settings(1), None).await.unwrap();
+        engine.response_timeout = Duration::from_millis(50);
+
+        let e = engine
+            .analyse(FEN, SearchLimit::Depth(3))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(engine.failed);
+    }
+
+    #[tokio::test]
+    async fn abandoned_search_fails_it() {
+        let mut engine = UciEngine::start(&mock_settings(1), None).await.unwrap();
+
+        let search = engine.analyse(FEN, SearchLimit::Depth(3));
+        assert!(tokio::time::timeout(Duration::from_millis(50), search)
+            .await
+            .is_err());
+        assert!(engine.failed);
+    }
+
+    #[tokio::test]
+    async fn pool() {
+        let pool = EnginePool::new(mock_settings(1));
+        let idle = || pool.idle.lock().unwrap().len();
+
+        let mut engine = pool.acquire(None).await.unwrap();
+        let busy = pool.acquire(None).await.err().unwrap();
+        assert_eq!(busy.kind(), io::ErrorKind::WouldBlock);
+
+        engine.analyse(FEN, SearchLimit::Depth(1)).await.unwrap();
+        drop(engine);
+        assert_eq!(idle(), 1);
+
+        // the idle engine is handed out again, at the skill level asked for
+        let mut engine = pool.acquire(Some(3)).await.unwrap();
+        assert_eq!(idle(), 0);
+        assert_eq!(engine.skill_level, Some(3));
+
+        // and thrown away once it failed
+        assert!(engine.analyse(FEN, SearchLimit::Depth(4)).await.is_err());
+        drop(engine);
+        assert_eq!(idle(), 0);
+
+        let mut engine = pool.acquire(None).await.unwrap();
+        engine.response_timeout = Duration::from_millis(50);
+        assert!(engine.analyse(FEN, SearchLimit::Depth(3)).await.is_err());
+        drop(engine);
+        assert_eq!(idle(), 0);
+    }
+}
@@ -1,5 +1,20 @@
+pub mod accounts;
+pub mod analysis;
+pub mod archive;
+pub mod bot;
+pub mod chess;
 pub mod chess_server;
 pub mod config;
+pub mod engine;
+pub mod openings;
+pub mod pgn;
+pub mod players;
+pub mod puzzles;
+pub mod rating;
+pub mod replay;
+pub mod tactics;
+pub mod telemetry;
+pub mod tournament;
 pub mod utils;
 
 pub mod types;
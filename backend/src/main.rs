@@ -1,14 +1,16 @@
 use chess_backend::chess_server::ChessServer;
-use chess_backend::config;
+use chess_backend::{config, telemetry};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let config = config::get_config().expect("Failed to parse configs");
+    telemetry::init_subscriber(&config.log);
+
     let chess_server = ChessServer::new(config)
         .build()
         .expect("Unable to build chess server");
 
-    println!("Starting server...");
+    tracing::info!("Starting server...");
 
     // Start the server
     chess_server.await?;
@@ -0,0 +1,153 @@
+//! Names the opening of a game by looking its positions up in the bundled ECO table
+//!
+//! Lookups go by position rather than by move order, so transpositions into a known
+//! line are recognised too.
+
+use serde::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::chess::{Move, Position};
+
+/// Tab separated `eco`, `name` and `pgn` columns, one line per opening
+const TABLE: &str = include_str!("openings.tsv");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Opening {
+    pub eco: String,
+    pub name: String,
+}
+
+struct Openings {
+    by_position: HashMap<String, Opening>,
+    /// Length of the longest line, no later move can change the opening
+    max_plies: usize,
+}
+
+fn openings() -> &'static Openings {
+    static OPENINGS: OnceLock<Openings> = OnceLock::new();
+
+    OPENINGS.get_or_init(|| {
+        let mut by_position = HashMap::new();
+        let mut max_plies = 0;
+
+        for line in TABLE.lines().skip(1).filter(|line| !line.is_empty()) {
+            let mut columns = line.split('\t');
+            let (Some(eco), Some(name), Some(pgn)) =
+                (columns.next(), columns.next(), columns.next())
+            else {
+                panic!("malformed opening: {line}");
+            };
+
+            let mut position = Position::default();
+            let mut plies = 0;
+            // move numbers end with a dot, everything else is a move
+            for san in pgn.split_whitespace().filter(|token| !token.ends_with('.')) {
+                let mv = position
+                    .parse_san(san)
+                    .unwrap_or_else(|| panic!("illegal move {san} in opening {name}"));
+                position = position.play(mv);
+                plies += 1;
+            }
+
+            max_plies = max_plies.max(plies);
+            // the first name listed for a position wins
+            by_position
+                .entry(position.repetition_key())
+                .or_insert_with(|| Opening {
+                    eco: eco.to_owned(),
+                    name: name.to_owned(),
+                });
+        }
+
+        Openings {
+            by_position,
+            max_plies,
+        }
+    })
+}
+
+/// The most specific opening reached by a game given as UCI moves, None before any
+/// known position was reached
+pub fn classify(moves: &[String]) -> Option<Opening> {
+    let openings = openings();
+    let mut position = Position::default();
+    let mut opening = None;
+
+    for uci in moves.iter().take(openings.max_plies) {
+        let Some(mv) = Move::from_uci(uci).filter(|mv| position.is_legal(*mv)) else {
+            break;
+        };
+        position = position.play(mv);
+
+        if let Some(known) = openings.by_position.get(&position.repetition_key()) {
+            opening = Some(known);
+        }
+    }
+
+    opening.cloned()
+}
+
+/// Whether a move played at this ply could still change the opening
+pub fn in_book_range(ply: usize) -> bool {
+    ply < openings().max_plies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opening_after(moves: &str) -> Option<String> {
+        let moves: Vec<String> = moves.split_whitespace().map(str::to_owned).collect();
+        classify(&moves).map(|opening| format!("{} {}", opening.eco, opening.name))
+    }
+
+    #[test]
+    fn najdorf() {
+        let najdorf = Some("B90 Sicilian Defense: Najdorf Variation".to_owned());
+        assert_eq!(
+            opening_after("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6"),
+            najdorf
+        );
+        // ...a6 before ...Nf6
+        assert_eq!(
+            opening_after("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 a7a6 b1c3 g8f6"),
+            najdorf
+        );
+        // moves the table doesn't know keep the last name
+        assert_eq!(
+            opening_after("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 h2h3 e7e5"),
+            najdorf
+        );
+
+        // the deepest line reached wins over the Najdorf itself
+        assert_eq!(
+            opening_after("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 c1e3 e7e5"),
+            Some("B90 Sicilian Defense: Najdorf Variation, English Attack".to_owned())
+        );
+        assert_eq!(
+            opening_after("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 c1g5"),
+            Some("B94 Sicilian Defense: Najdorf Variation".to_owned())
+        );
+    }
+
+    #[test]
+    fn transposed_queens_gambit() {
+        let queens_knight =
+            Some("D31 Queen's Gambit Declined: Queen's Knight Variation".to_owned());
+        assert_eq!(opening_after("d2d4 d7d5 c2c4 e7e6 b1c3"), queens_knight);
+        // starting with the English, the last move a double push nobody can take
+        assert_eq!(opening_after("c2c4 e7e6 b1c3 d7d5 d2d4"), queens_knight);
+    }
+
+    #[test]
+    fn unknown_openings() {
+        assert_eq!(classify(&[]), None);
+        // an illegal move ends the lookup
+        assert_eq!(opening_after("e2e5 e7e5"), None);
+        assert_eq!(
+            opening_after("e2e4 c7c5 e1e3"),
+            Some("B20 Sicilian Defense".to_owned())
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Writes archived games as PGN, with the engine review as comments and NAGs when there is one
+
+use std::fmt::Write;
+
+use crate::archive::GameRecord;
+use crate::chess::{Move, VariantKind};
+use crate::engine::Score;
+
+/// Movetext lines are wrapped before reaching this width
+const LINE_WIDTH: usize = 80;
+
+/// The `YYYY.MM.DD` date of a unix timestamp
+fn date_tag(secs: u64) -> String {
+    // days to civil date, from Howard Hinnant's date algorithms
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+fn eval_comment(score: Score) -> String {
+    match score {
+        Score::Cp(cp) => format!("[%eval {:.2}]", f64::from(cp) / 100.0),
+        Score::Mate(moves) => format!("[%eval #{moves}]"),
+    }
+}
+
+/// Escape the quotes and backslashes of a tag value
+fn tag_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn export(record: &GameRecord) -> String {
+    let result = serde_json::to_value(record.result)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_owned))
+        .unwrap_or_else(|| "*".to_owned());
+
+    let mut tags = vec![
+        (
+            "Event",
+            match record.rated {
+                true => "Rated game".to_owned(),
+                false => "Casual game".to_owned(),
+            },
+        ),
+        ("Site", "?".to_owned()),
+        ("Date", date_tag(record.ended_at)),
+        ("Round", "-".to_owned()),
+        ("White", record.white_name.clone()),
+        ("Black", record.black_name.clone()),
+        ("Result", result.clone()),
+        (
+            "TimeControl",
+            match &record.time_control {
+                Some(tc) => format!("{}+{}", tc.initial_secs, tc.increment_secs),
+                None => "-".to_owned(),
+            },
+        ),
+    ];
+    let rules = record.variant.rules();
+    let start = rules.starting_board();
+    if record.variant != VariantKind::Standard {
+        tags.push(("Variant", record.variant.name().to_owned()));
+    }
+    if start != Default::default() {
+        tags.push(("SetUp", "1".to_owned()));
+        tags.push(("FEN", rules.fen(&start)));
+    }
+    if let Some(opening) = &record.opening {
+        tags.push(("ECO", opening.eco.clone()));
+        tags.push(("Opening", opening.name.clone()));
+    }
+    if let Some(analysis) = &record.analysis {
+        for (tag, accuracy) in [
+            ("WhiteAccuracy", analysis.white_accuracy),
+            ("BlackAccuracy", analysis.black_accuracy),
+        ] {
+            if let Some(accuracy) = accuracy {
+                tags.push((tag, format!("{accuracy:.1}")));
+            }
+        }
+    }
+
+    let mut pgn = String::new();
+    for (name, value) in &tags {
+        let _ = writeln!(pgn, "[{name} \"{}\"]", tag_value(value));
+    }
+    pgn.push('\n');
+
+    let annotations = record.analysis.as_ref().map(|analysis| &analysis.moves);
+    let mut tokens = Vec::new();
+    let mut board = start;
+    for (ply, uci) in record.moves.iter().enumerate() {
+        let Some(mv) = Move::from_uci(uci).filter(|mv| rules.is_legal(&board, *mv)) else {
+            break;
+        };
+
+        let fullmove_number = board.position.fullmove_number();
+        if ply % 2 == 0 {
+            tokens.push(format!("{fullmove_number}."));
+        } else if tokens.last().is_some_and(|t| t.ends_with('}')) {
+            // black's move needs its number again after a comment
+            tokens.push(format!("{fullmove_number}..."));
+        }
+        tokens.push(rules.san(&board, mv));
+
+        if let Some(annotated) = annotations.and_then(|moves| moves.get(ply)) {
+            let mut comment = eval_comment(annotated.eval);
+            if let Some(judgement) = annotated.judgement {
+                tokens.push(format!("${}", judgement.nag()));
+                let _ = write!(comment, " {}.", judgement.name());
+                if let Some(best) = &annotated.best_move {
+                    let _ = write!(comment, " {best} was best.");
+                }
+            }
+            tokens.push(format!("{{ {comment} }}"));
+        }
+
+        board = rules.play(&board, mv);
+    }
+    tokens.push(result);
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+
+    pgn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{AnnotatedMove, GameAnalysis, Judgement};
+    use crate::types::{GameResult, Visibility};
+
+    fn record(moves: &[&str]) -> GameRecord {
+        GameRecord {
+            id: "game".into(),
+            white_name: "Alice".into(),
+            black_name: "Bob \"the rook\"".into(),
+            white_user: None,
+            black_user: None,
+            result: GameResult::WhiteWins,
+            rated: false,
+            time_control: None,
+            variant: VariantKind::Standard,
+            visibility: Visibility::Public,
+            rating_changes: None,
+            ended_at: 1_700_000_000,
+            moves: moves.iter().map(|mv| mv.to_string()).collect(),
+            move_times: Vec::new(),
+            opening: None,
+            analysis: None,
+            mined: false,
+        }
+    }
+
+    fn annotated(
+        san: &str,
+        eval: Score,
+        judgement: Option<Judgement>,
+        best_move: Option<&str>,
+    ) -> AnnotatedMove {
+        AnnotatedMove {
+            san: san.into(),
+            eval,
+            best_move: best_move.map(str::to_owned),
+            centipawn_loss: 0,
+            judgement,
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date_tag(0), "1970.01.01");
+        assert_eq!(date_tag(951_782_400), "2000.02.29");
+        assert_eq!(date_tag(1_700_000_000), "2023.11.14");
+        // 2100 isn't a leap year
+        assert_eq!(date_tag(4_107_542_400 - 86_400), "2100.02.28");
+        assert_eq!(date_tag(4_107_542_400), "2100.03.01");
+    }
+
+    #[test]
+    fn annotated_export() {
+        let mut record = record(&["e2e4", "e7e5", "g1f3", "b8c6"]);
+        record.analysis = Some(GameAnalysis {
+            depth: 12,
+            white_accuracy: Some(90.0),
+            black_accuracy: Some(75.46),
+            moves: vec![
+                annotated("e4", Score::Cp(30), None, None),
+                annotated("e5", Score::Cp(80), Some(Judgement::Mistake), Some("Nf6")),
+                annotated("Nf3", Score::Cp(-350), Some(Judgement::Blunder), Some("d4")),
+                annotated("Nc6", Score::Mate(-3), Some(Judgement::Inaccuracy), None),
+            ],
+        });
+
+        assert_eq!(
+            export(&record),
+            [
+                "[Event \"Casual game\"]",
+                "[Site \"?\"]",
+                "[Date \"2023.11.14\"]",
+                "[Round \"-\"]",
+                "[White \"Alice\"]",
+                "[Black \"Bob \\\"the rook\\\"\"]",
+                "[Result \"1-0\"]",
+                "[TimeControl \"-\"]",
+                "[WhiteAccuracy \"90.0\"]",
+                "[BlackAccuracy \"75.5\"]",
+                "",
+                // exactly 80 columns
+                "1. e4 { [%eval 0.30] } 1... e5 $2 { [%eval 0.80] Mistake. Nf6 was best. } 2. Nf3",
+                "$4 { [%eval -3.50] Blunder. d4 was best. } 2... Nc6 $6",
+                "{ [%eval #-3] Inaccuracy. } 1-0",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn movetext_wraps_at_80_columns() {
+        // knights hopping back and forth for 30 moves
+        let hops = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        let moves: Vec<&str> = hops.iter().cycle().take(60).copied().collect();
+        let pgn = export(&record(&moves));
+
+        let movetext: Vec<&str> = pgn.split("\n\n").nth(1).unwrap().lines().collect();
+        assert!(movetext.len() > 1);
+        for (line, next) in movetext.iter().zip(&movetext[1..]) {
+            assert!(line.len() <= LINE_WIDTH, "{line}");
+            // nothing more would have fit
+            let next_token = next.split(' ').next().unwrap();
+            assert!(line.len() + 1 + next_token.len() > LINE_WIDTH, "{line}");
+        }
+        assert!(movetext[0].starts_with("1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3"));
+        assert!(movetext.last().unwrap().ends_with("30. Ng1 Ng8 1-0"));
+    }
+}
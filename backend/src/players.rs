@@ -0,0 +1,91 @@
+use serde::*;
+use std::collections::HashMap;
+
+use crate::accounts::Account;
+use crate::archive::{GameArchive, GameRecord, PlayerStats};
+use crate::rating::{Pool, Rating, RatingStore};
+
+/// Ratings with a deviation above this haven't settled yet and are shown with a `?`
+const PROVISIONAL_DEVIATION: f64 = 110.0;
+const RECENT_GAMES: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct PoolRating {
+    pub rating: i32,
+    pub deviation: i32,
+    pub provisional: bool,
+    #[serde(flatten)]
+    pub stats: PlayerStats,
+}
+
+impl PoolRating {
+    fn new(rating: &Rating, stats: PlayerStats) -> Self {
+        Self {
+            rating: rating.rating.round() as i32,
+            deviation: rating.deviation.round() as i32,
+            provisional: rating.deviation > PROVISIONAL_DEVIATION,
+            stats,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub username: String,
+    pub created_at: u64,
+    pub ratings: HashMap<Pool, PoolRating>,
+    /// Results over every game, rated or not
+    #[serde(flatten)]
+    pub stats: PlayerStats,
+    /// Newest first
+    pub recent_games: Vec<GameRecord>,
+}
+
+impl Profile {
+    pub fn new(account: Account, ratings: &RatingStore, archive: &GameArchive) -> Self {
+        let username = account.username;
+
+        Self {
+            ratings: ratings
+                .ratings_of(&username)
+                .iter()
+                .map(|(pool, rating)| {
+                    let stats = archive.stats(&username, Some(*pool));
+                    (*pool, PoolRating::new(rating, stats))
+                })
+                .collect(),
+            stats: archive.stats(&username, None),
+            recent_games: archive.recent_games(&username, RECENT_GAMES),
+            created_at: account.created_at,
+            username,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub username: String,
+    #[serde(flatten)]
+    pub rating: PoolRating,
+}
+
+/// The best rated players of a pool, highest rating first
+pub fn leaderboard(
+    pool: Pool,
+    limit: usize,
+    ratings: &RatingStore,
+    archive: &GameArchive,
+) -> Vec<LeaderboardEntry> {
+    ratings
+        .leaderboard(pool)
+        .into_iter()
+        .take(limit)
+        .enumerate()
+        .map(|(i, (username, rating))| LeaderboardEntry {
+            rank: i + 1,
+            rating: PoolRating::new(&rating, archive.stats(&username, Some(pool))),
+            username,
+        })
+        .collect()
+}
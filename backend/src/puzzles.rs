@@ -0,0 +1,494 @@
+//! Tactics puzzles: a position, the line that solves it and how hard it is
+//!
+//! Puzzles follow the layout of the Lichess puzzle database. The FEN is the position
+//! before the opponent's last move, which is the first move of the line, and the
+//! solver plays every other move after it.
+
+use rand::seq::SliceRandom;
+use serde::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::chess::{Move, Position};
+use crate::config::PuzzleSettings;
+use crate::rating::Rating;
+
+/// How many of the puzzles closest to the player's rating one is picked from
+const CANDIDATES: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Puzzle {
+    pub id: String,
+    /// The position before the opponent's move that sets the puzzle up
+    pub fen: String,
+    /// In UCI notation, starting with the opponent's move
+    pub moves: Vec<String>,
+    pub rating: Rating,
+    pub themes: Vec<String>,
+    /// How many rated attempts were made
+    #[serde(default)]
+    pub plays: u32,
+    /// The archived game the puzzle was found in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_id: Option<String>,
+}
+
+impl Puzzle {
+    /// The position once the first `plies` moves of the line were played
+    pub fn position_after(&self, plies: usize) -> Result<Position, String> {
+        let mut position = Position::from_fen(&self.fen).map_err(|e| e.to_string())?;
+        for uci in self.moves.iter().take(plies) {
+            let mv = Move::from_uci(uci)
+                .filter(|&mv| position.is_legal(mv))
+                .ok_or_else(|| format!("illegal move {uci}"))?;
+            position = position.play(mv);
+        }
+
+        Ok(position)
+    }
+
+    /// Check the FEN and that every move of the line is legal
+    fn validate(&self) -> Result<(), String> {
+        if self.moves.len() < 2 {
+            return Err("the line needs a setup move and at least one solution move".into());
+        }
+
+        self.position_after(self.moves.len()).map(|_| ())
+    }
+}
+
+/// The puzzle collection and the puzzles each account has attempted, persisted as
+/// JSON files. Attempts only change them in memory, [`PuzzleStore::flush`] writes them
+/// out in batches.
+pub struct PuzzleStore {
+    path: PathBuf,
+    attempts_path: PathBuf,
+    collection: Mutex<Collection>,
+    /// Held while writing, so an older flush never overwrites a newer one
+    writing: Mutex<()>,
+}
+
+#[derive(Default)]
+struct Collection {
+    puzzles: HashMap<String, Puzzle>,
+    /// Rating and ID of every puzzle, lowest rating first
+    by_rating: Vec<(f64, String)>,
+    /// IDs of the puzzles attempted, by username
+    attempts: HashMap<String, HashSet<String>>,
+    /// Whether the puzzles changed since they were last written
+    puzzles_changed: bool,
+    attempts_changed: bool,
+}
+
+impl Collection {
+    fn insert(&mut self, puzzle: Puzzle) {
+        let rating = puzzle.rating.rating;
+        let at = self.by_rating.partition_point(|(other, _)| *other < rating);
+        self.by_rating.insert(at, (rating, puzzle.id.clone()));
+        self.puzzles.insert(puzzle.id.clone(), puzzle);
+        self.puzzles_changed = true;
+    }
+
+    /// Move the puzzle to its place in the rating order once its rating changed
+    fn reindex(&mut self, id: &str, old_rating: f64) {
+        let Some(rating) = self.puzzles.get(id).map(|puzzle| puzzle.rating.rating) else {
+            return;
+        };
+        let from = self
+            .by_rating
+            .partition_point(|(other, _)| *other < old_rating);
+        if let Some(offset) = self.by_rating[from..]
+            .iter()
+            .position(|(_, other)| other == id)
+        {
+            self.by_rating.remove(from + offset);
+        }
+        let at = self.by_rating.partition_point(|(other, _)| *other < rating);
+        self.by_rating.insert(at, (rating, id.to_owned()));
+    }
+}
+
+impl PuzzleStore {
+    /// Open the collection at the configured path and import the configured CSV file
+    /// into it, a missing collection yields an empty store
+    pub fn open(settings: &PuzzleSettings) -> io::Result<Self> {
+        let path = PathBuf::from(&settings.puzzles_path);
+        let puzzles: HashMap<String, Puzzle> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        let attempts_path = PathBuf::from(&settings.attempts_path);
+        let attempts: HashMap<String, HashSet<String>> = match fs::read_to_string(&attempts_path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        info!(
+            puzzles = puzzles.len(),
+            players = attempts.len(),
+            "loaded puzzle collection"
+        );
+
+        let mut by_rating: Vec<(f64, String)> = puzzles
+            .values()
+            .map(|puzzle| (puzzle.rating.rating, puzzle.id.clone()))
+            .collect();
+        by_rating.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let store = Self {
+            path,
+            attempts_path,
+            collection: Mutex::new(Collection {
+                puzzles,
+                by_rating,
+                attempts,
+                ..Collection::default()
+            }),
+            writing: Mutex::new(()),
+        };
+        if !settings.import_path.is_empty() {
+            store.import_csv(Path::new(&settings.import_path))?;
+        }
+
+        Ok(store)
+    }
+
+    /// Add the puzzles of a CSV file in the Lichess puzzle database layout, columns are
+    /// found by their header. Puzzles already in the collection are skipped.
+    ///
+    /// Returns how many puzzles were added.
+    pub fn import_csv(&self, path: &Path) -> io::Result<usize> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header: Vec<&str> = lines.next().unwrap_or_default().split(',').collect();
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|column| *column == name)
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("no {name} column"))
+                })
+        };
+        let (id, fen, moves, rating) = (
+            column("PuzzleId")?,
+            column("FEN")?,
+            column("Moves")?,
+            column("Rating")?,
+        );
+        let (deviation, themes) = (column("RatingDeviation").ok(), column("Themes").ok());
+
+        let mut collection = self.collection.lock().unwrap();
+        let mut added = 0;
+        for (number, line) in lines.enumerate() {
+            let fields: Vec<&str> = line.split(',').collect();
+            let field = |index: usize| fields.get(index).copied().unwrap_or_default();
+            if collection.puzzles.contains_key(field(id)) {
+                continue;
+            }
+
+            let Ok(rating) = field(rating).parse::<f64>() else {
+                warn!(line = number + 2, "skipping puzzle without a rating");
+                continue;
+            };
+            let puzzle = Puzzle {
+                id: field(id).to_owned(),
+                fen: field(fen).to_owned(),
+                moves: field(moves).split_whitespace().map(str::to_owned).collect(),
+                rating: Rating {
+                    rating,
+                    deviation: deviation
+                        .and_then(|index| field(index).parse().ok())
+                        .unwrap_or(Rating::default().deviation),
+                    ..Rating::default()
+                },
+                themes: themes
+                    .map(|index| field(index).split_whitespace().map(str::to_owned).collect())
+                    .unwrap_or_default(),
+                plays: 0,
+                game_id: None,
+            };
+            if let Err(e) = puzzle.validate() {
+                warn!(line = number + 2, error = %e, "skipping invalid puzzle");
+                continue;
+            }
+
+            collection.insert(puzzle);
+            added += 1;
+        }
+        drop(collection);
+
+        self.flush()?;
+        info!(added, path = %path.display(), "imported puzzles");
+
+        Ok(added)
+    }
+
+    /// Add a puzzle to the collection, returns false if there already is one with its ID
+    pub fn insert(&self, puzzle: Puzzle) -> io::Result<bool> {
+        if let Err(e) = puzzle.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+
+        let mut collection = self.collection.lock().unwrap();
+        if collection.puzzles.contains_key(&puzzle.id) {
+            return Ok(false);
+        }
+        collection.insert(puzzle);
+        drop(collection);
+
+        self.flush()?;
+
+        Ok(true)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Puzzle> {
+        self.collection.lock().unwrap().puzzles.get(id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.collection.lock().unwrap().puzzles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A random puzzle among those rated closest to the player, leaving out the ones
+    /// the player has already seen and, for signed in players, every one their
+    /// account has attempted
+    pub fn pick(&self, rating: f64, seen: &[String], user: Option<&str>) -> Option<Puzzle> {
+        let collection = self.collection.lock().unwrap();
+        let attempted = user.and_then(|user| collection.attempts.get(user));
+        let available = |(_, id): &&(f64, String)| {
+            !seen.contains(id) && attempted.is_none_or(|attempted| !attempted.contains(id))
+        };
+
+        // walk away from the player's rating in both directions, closest first
+        let at = collection
+            .by_rating
+            .partition_point(|(other, _)| *other < rating);
+        let mut below = collection.by_rating[..at]
+            .iter()
+            .rev()
+            .filter(available)
+            .peekable();
+        let mut above = collection.by_rating[at..]
+            .iter()
+            .filter(available)
+            .peekable();
+        let mut candidates = Vec::with_capacity(CANDIDATES);
+        while candidates.len() < CANDIDATES {
+            let next = match (below.peek(), above.peek()) {
+                (Some(lower), Some(higher)) if rating - lower.0 <= higher.0 - rating => {
+                    below.next()
+                }
+                (_, Some(_)) => above.next(),
+                (Some(_), None) => below.next(),
+                (None, None) => break,
+            };
+            candidates.extend(next.map(|(_, id)| id));
+        }
+
+        candidates
+            .choose(&mut rand::thread_rng())
+            .and_then(|&id| collection.puzzles.get(id))
+            .cloned()
+    }
+
+    /// Remember that the account attempted the puzzle, only the first attempt is rated
+    ///
+    /// Returns false if the account attempted it before.
+    pub fn start_attempt(&self, id: &str, user: &str) -> bool {
+        let mut collection = self.collection.lock().unwrap();
+        let first = collection
+            .attempts
+            .entry(user.to_owned())
+            .or_default()
+            .insert(id.to_owned());
+        collection.attempts_changed |= first;
+
+        first
+    }
+
+    /// Update the puzzle's rating after a rated attempt, the puzzle wins when it
+    /// wasn't solved
+    ///
+    /// Returns the puzzle's new rating, None if there is no such puzzle.
+    pub fn record_attempt(&self, id: &str, player: &Rating, solved: bool) -> Option<Rating> {
+        let mut collection = self.collection.lock().unwrap();
+        let puzzle = collection.puzzles.get_mut(id)?;
+        let old_rating = puzzle.rating.rating;
+        puzzle.rating = puzzle.rating.update(player, if solved { 0.0 } else { 1.0 });
+        puzzle.plays += 1;
+        let rating = puzzle.rating;
+
+        collection.reindex(id, old_rating);
+        collection.puzzles_changed = true;
+
+        Some(rating)
+    }
+
+    /// Write whatever changed since the last flush, what fails to be written is tried
+    /// again on the next one
+    pub fn flush(&self) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        let (puzzles, attempts) = {
+            let mut collection = self.collection.lock().unwrap();
+            let puzzles =
+                std::mem::take(&mut collection.puzzles_changed).then(|| collection.puzzles.clone());
+            let attempts = std::mem::take(&mut collection.attempts_changed)
+                .then(|| collection.attempts.clone());
+            (puzzles, attempts)
+        };
+
+        let saved_puzzles = puzzles.map_or(Ok(()), |puzzles| save(&self.path, &puzzles));
+        let saved_attempts =
+            attempts.map_or(Ok(()), |attempts| save(&self.attempts_path, &attempts));
+
+        let mut collection = self.collection.lock().unwrap();
+        collection.puzzles_changed |= saved_puzzles.is_err();
+        collection.attempts_changed |= saved_attempts.is_err();
+
+        saved_puzzles.and(saved_attempts)
+    }
+}
+
+/// Write to a temporary file first so a crash never leaves a half written file behind
+fn save(path: &Path, contents: &impl Serialize) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string(contents)?)?;
+    fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black's rook pawn steps forward and white mates on the back rank
+    fn mate_in_one(id: &str) -> Puzzle {
+        Puzzle {
+            id: id.to_owned(),
+            fen: "6k1/p4ppp/8/8/8/8/5PPP/1R4K1 b - - 0 1".to_owned(),
+            moves: vec!["a7a6".to_owned(), "b1b8".to_owned()],
+            rating: Rating::default(),
+            themes: vec!["mateIn1".to_owned()],
+            plays: 0,
+            game_id: None,
+        }
+    }
+
+    fn settings() -> PuzzleSettings {
+        let dir = std::env::temp_dir().join(format!("puzzles-{}", nanoid::nanoid!(10)));
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        PuzzleSettings {
+            puzzles_path: path("puzzles.json"),
+            attempts_path: path("puzzle_attempts.json"),
+            import_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn invalid_puzzles_are_refused() {
+        let store = PuzzleStore::open(&settings()).unwrap();
+        let mut puzzle = mate_in_one("p");
+        puzzle.moves.push("a6a5".to_owned());
+        puzzle.moves.push("b8a8".to_owned());
+
+        assert!(store.insert(puzzle).is_err());
+        assert!(store.insert(mate_in_one("p")).unwrap());
+        assert!(!store.insert(mate_in_one("p")).unwrap());
+    }
+
+    #[test]
+    fn attempts_are_kept_by_account() {
+        let settings = settings();
+        let store = PuzzleStore::open(&settings).unwrap();
+        store.insert(mate_in_one("p")).unwrap();
+        store.insert(mate_in_one("q")).unwrap();
+
+        assert!(store.start_attempt("p", "alice"));
+        assert!(!store.start_attempt("p", "alice"));
+        assert!(store.start_attempt("p", "bob"));
+
+        // nothing is written before the store is flushed
+        assert!(PuzzleStore::open(&settings)
+            .unwrap()
+            .start_attempt("p", "bob"));
+        store.flush().unwrap();
+
+        // alice gets the other puzzle, guests get either
+        let reopened = PuzzleStore::open(&settings).unwrap();
+        assert!(!reopened.start_attempt("p", "alice"));
+        for _ in 0..10 {
+            let picked = reopened.pick(1500.0, &[], Some("alice")).unwrap();
+            assert_eq!(picked.id, "q");
+        }
+        assert!(reopened
+            .pick(1500.0, &["q".to_owned()], Some("alice"))
+            .is_none());
+        assert!(reopened.pick(1500.0, &["q".to_owned()], None).is_some());
+    }
+
+    #[test]
+    fn puzzles_closest_to_the_rating_are_picked() {
+        let store = PuzzleStore::open(&settings()).unwrap();
+        for number in 0..100 {
+            let mut puzzle = mate_in_one(&number.to_string());
+            puzzle.rating.rating = 1000.0 + 10.0 * number as f64;
+            store.insert(puzzle).unwrap();
+        }
+
+        // the twenty closest to 1500 are rated 1400 to 1590
+        for _ in 0..50 {
+            let rating = store.pick(1500.0, &[], None).unwrap().rating.rating;
+            assert!((1400.0..=1590.0).contains(&rating), "{rating}");
+        }
+        let rating = store.pick(0.0, &[], None).unwrap().rating.rating;
+        assert!(rating < 1200.0, "{rating}");
+    }
+
+    #[test]
+    fn rated_attempts_move_puzzles_in_the_rating_order() {
+        let settings = settings();
+        let store = PuzzleStore::open(&settings).unwrap();
+        store.insert(mate_in_one("p")).unwrap();
+        let mut easy = mate_in_one("q");
+        easy.rating.rating = 800.0;
+        store.insert(easy).unwrap();
+
+        // a weak player failing the puzzle makes it look easier, a strong one solving it harder
+        let strong = Rating {
+            rating: 2500.0,
+            deviation: 50.0,
+            ..Rating::default()
+        };
+        let rating = store.record_attempt("p", &strong, true).unwrap();
+        assert!(rating.rating < Rating::default().rating);
+        assert!(store.record_attempt("missing", &strong, true).is_none());
+        let collection = store.collection.lock().unwrap();
+        let index: Vec<(f64, &str)> = collection
+            .by_rating
+            .iter()
+            .map(|(rating, id)| (*rating, id.as_str()))
+            .collect();
+        let mut expected = vec![(rating.rating, "p"), (800.0, "q")];
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(index, expected);
+        drop(collection);
+
+        store.flush().unwrap();
+        let reopened = PuzzleStore::open(&settings).unwrap();
+        let puzzle = reopened.get("p").unwrap();
+        assert_eq!(puzzle.plays, 1);
+        assert_eq!(puzzle.rating.rating, rating.rating);
+    }
+}
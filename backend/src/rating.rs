@@ -0,0 +1,400 @@
+//! Glicko-2 ratings, see <http://www.glicko.net/glicko/glicko2.pdf>
+//!
+//! Every rated game is treated as its own rating period, which keeps the
+//! ratings live instead of waiting for a batch update.
+
+use serde::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+use crate::config::RatingSettings;
+use crate::types::{GameResult, TimeControl};
+
+/// Converts between the Glicko and the Glicko-2 scale
+const SCALE: f64 = 173.7178;
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Constrains how much the volatility can change between games
+const TAU: f64 = 0.5;
+const CONVERGENCE_TOLERANCE: f64 = 0.000001;
+
+/// Ratings are kept separately for each speed of play
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pool {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    /// Tactics puzzles, every attempt counts as a game against the puzzle
+    Puzzle,
+}
+
+impl Pool {
+    /// The pool a time control is rated in, based on the estimated duration of a
+    /// 40 move game for one player
+    pub fn for_time_control(time_control: &TimeControl) -> Self {
+        let estimated_secs =
+            u64::from(time_control.initial_secs) + 40 * u64::from(time_control.increment_secs);
+
+        match estimated_secs {
+            0..=179 => Pool::Bullet,
+            180..=479 => Pool::Blitz,
+            480..=1499 => Pool::Rapid,
+            _ => Pool::Classical,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Rating {
+    /// The rating after a single game against `opponent`, where `score` is 1 for a win,
+    /// 0.5 for a draw and 0 for a loss
+    pub fn update(&self, opponent: &Rating, score: f64) -> Rating {
+        self.update_period(&[(*opponent, score)])
+    }
+
+    /// The rating after a rating period made of `games`, each an opponent and the score
+    /// against them
+    fn update_period(&self, games: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+
+        // steps 3 and 4, the estimated variance and the sum the improvement is built from
+        let mut inverse_variance = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in games {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let opponent_phi = opponent.deviation / SCALE;
+
+            let g = 1.0 / (1.0 + 3.0 * opponent_phi.powi(2) / PI.powi(2)).sqrt();
+            let expected = 1.0 / (1.0 + (-g * (mu - opponent_mu)).exp());
+
+            inverse_variance += g.powi(2) * expected * (1.0 - expected);
+            improvement += g * (score - expected);
+        }
+        let variance = 1.0 / inverse_variance;
+        let delta = variance * improvement;
+
+        let volatility = new_volatility(phi, self.volatility, variance, delta);
+
+        let pre_period_phi = (phi.powi(2) + volatility.powi(2)).sqrt();
+        let new_phi = 1.0 / (1.0 / pre_period_phi.powi(2) + 1.0 / variance).sqrt();
+        let new_mu = mu + new_phi.powi(2) * improvement;
+
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+}
+
+/// Step 5 of the paper, finds the new volatility with the Illinois algorithm
+fn new_volatility(phi: f64, sigma: f64, variance: f64, delta: f64) -> f64 {
+    let a = sigma.powi(2).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let d = phi.powi(2) + variance + ex;
+
+        ex * (delta.powi(2) - d) / (2.0 * d.powi(2)) - (x - a) / TAU.powi(2)
+    };
+
+    let mut lower = a;
+    let mut upper = if delta.powi(2) > phi.powi(2) + variance {
+        (delta.powi(2) - phi.powi(2) - variance).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+
+    while (upper - lower).abs() > CONVERGENCE_TOLERANCE {
+        let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_c = f(c);
+
+        if f_c * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+
+        upper = c;
+        f_upper = f_c;
+    }
+
+    (lower / 2.0).exp()
+}
+
+/// How a rated game moved a player's rating, rounded the way it is displayed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub pool: Pool,
+    pub before: i32,
+    pub after: i32,
+    pub delta: i32,
+}
+
+impl RatingChange {
+    fn new(pool: Pool, before: &Rating, after: &Rating) -> Self {
+        let before = before.rating.round() as i32;
+        let after = after.rating.round() as i32;
+
+        Self {
+            pool,
+            before,
+            after,
+            delta: after - before,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingChanges {
+    pub white: RatingChange,
+    pub black: RatingChange,
+}
+
+/// The ratings of every account, persisted as a JSON file
+pub struct RatingStore {
+    path: PathBuf,
+    ratings: Mutex<HashMap<String, HashMap<Pool, Rating>>>,
+}
+
+impl RatingStore {
+    /// Open the rating store at the configured path, a missing file yields an empty store
+    pub fn open(settings: &RatingSettings) -> io::Result<Self> {
+        let path = PathBuf::from(&settings.ratings_path);
+        let ratings: HashMap<String, HashMap<Pool, Rating>> = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        info!(players = ratings.len(), "loaded rating store");
+
+        Ok(Self {
+            path,
+            ratings: Mutex::new(ratings),
+        })
+    }
+
+    /// The player's rating in a pool, players who haven't played in it get the default rating
+    pub fn get(&self, username: &str, pool: Pool) -> Rating {
+        rating_in(&self.ratings.lock().unwrap(), username, pool)
+    }
+
+    /// Every player rated in the pool, highest rating first
+    pub fn leaderboard(&self, pool: Pool) -> Vec<(String, Rating)> {
+        let mut leaderboard: Vec<(String, Rating)> = self
+            .ratings
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(username, pools)| Some((username.clone(), *pools.get(&pool)?)))
+            .collect();
+        leaderboard.sort_by(|(_, a), (_, b)| b.rating.total_cmp(&a.rating));
+
+        leaderboard
+    }
+
+    /// Every pool the player has a rating in
+    pub fn ratings_of(&self, username: &str) -> HashMap<Pool, Rating> {
+        self.ratings
+            .lock()
+            .unwrap()
+            .get(username)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Update both players' ratings for a finished game.
+    ///
+    /// Both players are updated under the same lock so concurrent games can't interleave
+    /// between reading and writing a rating. Aborted games don't change any rating.
+    pub fn record_game(
+        &self,
+        white: &str,
+        black: &str,
+        pool: Pool,
+        result: GameResult,
+    ) -> io::Result<Option<RatingChanges>> {
+        let white_score = match result {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::Aborted => return Ok(None),
+        };
+
+        let mut ratings = self.ratings.lock().unwrap();
+
+        let white_before = rating_in(&ratings, white, pool);
+        let black_before = rating_in(&ratings, black, pool);
+        let white_after = white_before.update(&black_before, white_score);
+        let black_after = black_before.update(&white_before, 1.0 - white_score);
+
+        self.commit(
+            &mut ratings,
+            &[(white, pool, white_after), (black, pool, black_after)],
+        )?;
+
+        Ok(Some(RatingChanges {
+            white: RatingChange::new(pool, &white_before, &white_after),
+            black: RatingChange::new(pool, &black_before, &black_after),
+        }))
+    }
+
+    /// Update the player's puzzle rating after an attempt, the puzzle plays the part
+    /// of the opponent
+    ///
+    /// Returns the change along with the player's rating before the attempt, which the
+    /// puzzle's own rating is updated against.
+    pub fn record_puzzle(
+        &self,
+        username: &str,
+        puzzle: &Rating,
+        solved: bool,
+    ) -> io::Result<(RatingChange, Rating)> {
+        let mut ratings = self.ratings.lock().unwrap();
+
+        let before = rating_in(&ratings, username, Pool::Puzzle);
+        let after = before.update(puzzle, if solved { 1.0 } else { 0.0 });
+        self.commit(&mut ratings, &[(username, Pool::Puzzle, after)])?;
+
+        Ok((RatingChange::new(Pool::Puzzle, &before, &after), before))
+    }
+
+    /// Save the ratings with the updates applied, the ratings held in memory only
+    /// change once they are on disk so a failed save leaves both as they were
+    fn commit(
+        &self,
+        ratings: &mut HashMap<String, HashMap<Pool, Rating>>,
+        updates: &[(&str, Pool, Rating)],
+    ) -> io::Result<()> {
+        let mut updated = ratings.clone();
+        for &(username, pool, rating) in updates {
+            updated
+                .entry(username.to_owned())
+                .or_default()
+                .insert(pool, rating);
+        }
+
+        save(&self.path, &updated)?;
+        *ratings = updated;
+
+        Ok(())
+    }
+}
+
+fn rating_in(
+    ratings: &HashMap<String, HashMap<Pool, Rating>>,
+    username: &str,
+    pool: Pool,
+) -> Rating {
+    ratings
+        .get(username)
+        .and_then(|pools| pools.get(&pool))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Write the store to a temporary file first so a crash never leaves a half written file behind
+fn save(path: &Path, ratings: &HashMap<String, HashMap<Pool, Rating>>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string(ratings)?)?;
+    fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    #[test]
+    fn glickman_example() {
+        // the worked example at the end of the Glicko-2 paper
+        let player = rating(1500.0, 200.0);
+        let after = player.update_period(&[
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ]);
+
+        assert!((after.rating - 1464.06).abs() < 0.01, "{after:?}");
+        assert!((after.deviation - 151.52).abs() < 0.01, "{after:?}");
+        assert!((after.volatility - 0.05999).abs() < 0.00001, "{after:?}");
+    }
+
+    #[test]
+    fn single_game() {
+        let winner = Rating::default().update(&Rating::default(), 1.0);
+        let loser = Rating::default().update(&Rating::default(), 0.0);
+
+        assert!(winner.rating > DEFAULT_RATING);
+        assert!((winner.rating - DEFAULT_RATING + loser.rating - DEFAULT_RATING).abs() < 1e-9);
+        assert!(winner.deviation < DEFAULT_DEVIATION);
+    }
+
+    #[test]
+    fn pools() {
+        let pool = |initial_secs, increment_secs| {
+            Pool::for_time_control(&TimeControl {
+                initial_secs,
+                increment_secs,
+            })
+        };
+
+        assert_eq!(pool(60, 0), Pool::Bullet);
+        assert_eq!(pool(120, 1), Pool::Bullet);
+        assert_eq!(pool(179, 0), Pool::Bullet);
+        assert_eq!(pool(180, 0), Pool::Blitz);
+        assert_eq!(pool(120, 2), Pool::Blitz);
+        assert_eq!(pool(300, 3), Pool::Blitz);
+        assert_eq!(pool(479, 0), Pool::Blitz);
+        assert_eq!(pool(480, 0), Pool::Rapid);
+        assert_eq!(pool(600, 5), Pool::Rapid);
+        assert_eq!(pool(1499, 0), Pool::Rapid);
+        assert_eq!(pool(1500, 0), Pool::Classical);
+        assert_eq!(pool(900, 15), Pool::Classical);
+        assert_eq!(pool(u32::MAX, u32::MAX), Pool::Classical);
+    }
+}
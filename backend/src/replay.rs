@@ -0,0 +1,212 @@
+//! Stepping through a game from the moves the server stored, so clients can look at
+//! any position of a game they didn't watch and play it back at its original pace.
+
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::archive::GameRecord;
+use crate::chess::{Move, VariantKind};
+
+/// Pause between moves stored without a time, games archived before moves were timed
+const DEFAULT_MOVE_DELAY: Duration = Duration::from_secs(1);
+/// Longer thinks, and every correspondence move, are cut down to this
+const MAX_MOVE_DELAY: Duration = Duration::from_secs(30);
+/// Fastest a replay can be sped up
+const MAX_SPEED: f64 = 100.0;
+
+/// The speed a replay is played at, the one asked for as long as it is between real
+/// time and [`MAX_SPEED`]
+pub fn speed(requested: Option<f64>) -> f64 {
+    requested
+        .filter(|speed| speed.is_finite())
+        .unwrap_or(1.0)
+        .clamp(1.0, MAX_SPEED)
+}
+
+/// The moves of a game as the server keeps them
+#[derive(Debug, Clone)]
+pub struct StoredGame {
+    pub variant: VariantKind,
+    /// In UCI notation
+    pub moves: Vec<String>,
+    /// When each move was played, in milliseconds since the unix epoch
+    pub move_times: Vec<u64>,
+    /// Whether the game is still being played
+    pub live: bool,
+}
+
+impl From<GameRecord> for StoredGame {
+    fn from(record: GameRecord) -> Self {
+        Self {
+            variant: record.variant,
+            moves: record.moves,
+            move_times: record.move_times,
+            live: false,
+        }
+    }
+}
+
+/// A game after some of its moves
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayPosition {
+    /// How many moves were played to reach the position
+    pub ply: usize,
+    pub fen: String,
+    /// The move that led to the position in UCI notation, None before the first move
+    pub last_move: Option<String>,
+    pub san: Option<String>,
+    /// How many moves the game has
+    pub plies: usize,
+}
+
+impl StoredGame {
+    /// Every position of the game, from the starting position to the latest one
+    pub fn positions(&self) -> Result<Vec<ReplayPosition>, &'static str> {
+        let rules = self.variant.rules();
+        let mut board = rules.starting_board();
+        let mut positions = Vec::with_capacity(self.moves.len() + 1);
+        positions.push(ReplayPosition {
+            ply: 0,
+            fen: rules.fen(&board),
+            last_move: None,
+            san: None,
+            plies: self.moves.len(),
+        });
+
+        for (ply, uci) in self.moves.iter().enumerate() {
+            let mv = Move::from_uci(uci).ok_or("unreadable stored move")?;
+            // bughouse drops pieces captured on the partner board, which this game's moves
+            // don't show going into the pocket
+            if let Some(kind) = mv.drop {
+                if self.variant == VariantKind::Bughouse && !rules.is_legal(&board, mv) {
+                    let color = board.position.side_to_move();
+                    board.variant_state.add_to_pocket(color, kind);
+                }
+            }
+            if !rules.is_legal(&board, mv) {
+                return Err("illegal stored move");
+            }
+
+            let san = rules.san(&board, mv);
+            board = rules.play(&board, mv);
+            positions.push(ReplayPosition {
+                ply: ply + 1,
+                fen: rules.fen(&board),
+                last_move: Some(uci.clone()),
+                san: Some(san),
+                plies: self.moves.len(),
+            });
+        }
+
+        Ok(positions)
+    }
+
+    /// The position after the first `ply` moves
+    pub fn position_at(&self, ply: usize) -> Result<ReplayPosition, &'static str> {
+        if ply > self.moves.len() {
+            return Err("the game doesn't have that many moves");
+        }
+
+        Ok(self.positions()?.swap_remove(ply))
+    }
+
+    /// How long to wait before each move when playing the game back, the first move
+    /// comes right away. `speed` divides the time the players took.
+    pub fn move_delays(&self, speed: f64) -> Vec<Duration> {
+        (0..self.moves.len())
+            .map(|ply| {
+                if ply == 0 {
+                    return Duration::ZERO;
+                }
+                let taken = match (self.move_times.get(ply - 1), self.move_times.get(ply)) {
+                    (Some(previous), Some(time)) => {
+                        Duration::from_millis(time.saturating_sub(*previous))
+                    }
+                    _ => DEFAULT_MOVE_DELAY,
+                };
+
+                taken.div_f64(speed).min(MAX_MOVE_DELAY)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(variant: VariantKind, moves: &str) -> StoredGame {
+        StoredGame {
+            variant,
+            moves: moves.split_whitespace().map(str::to_owned).collect(),
+            move_times: Vec::new(),
+            live: false,
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let game = game(VariantKind::Standard, "e2e4 e7e5 g1f3");
+        let positions = game.positions().unwrap();
+
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[0].last_move, None);
+        assert_eq!(positions[3].san.as_deref(), Some("Nf3"));
+        assert_eq!(positions[3].plies, 3);
+        assert_eq!(
+            game.position_at(2).unwrap().fen,
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+        );
+        assert!(game.position_at(4).is_err());
+    }
+
+    #[test]
+    fn illegal_moves_are_refused() {
+        assert!(game(VariantKind::Standard, "e2e5").positions().is_err());
+        assert!(game(VariantKind::Standard, "e2").positions().is_err());
+        // crazyhouse pockets only fill up with the game's own captures
+        assert!(game(VariantKind::Crazyhouse, "e2e4 N@f6")
+            .positions()
+            .is_err());
+    }
+
+    #[test]
+    fn bughouse_drops_come_from_the_other_board() {
+        let game = game(VariantKind::Bughouse, "e2e4 N@f6 Q@h5");
+        let positions = game.positions().unwrap();
+
+        assert_eq!(positions[2].san.as_deref(), Some("N@f6"));
+        assert_eq!(
+            positions[3].fen,
+            "rnbqkbnr/pppppppp/5n2/7Q/4P3/8/PPPP1PPP/RNBQKBNR[] b KQkq - 2 2"
+        );
+    }
+
+    #[test]
+    fn speed_stays_within_bounds() {
+        assert_eq!(speed(None), 1.0);
+        assert_eq!(speed(Some(4.0)), 4.0);
+        assert_eq!(speed(Some(0.0)), 1.0);
+        assert_eq!(speed(Some(-3.0)), 1.0);
+        assert_eq!(speed(Some(1e9)), MAX_SPEED);
+        assert_eq!(speed(Some(f64::NAN)), 1.0);
+        assert_eq!(speed(Some(f64::INFINITY)), 1.0);
+    }
+
+    #[test]
+    fn move_delays() {
+        let mut game = game(VariantKind::Standard, "e2e4 e7e5 g1f3 b8c6 f1b5");
+        game.move_times = vec![1_000, 3_000, 4_000, 604_000];
+
+        assert_eq!(
+            game.move_delays(2.0),
+            [
+                Duration::ZERO,
+                Duration::from_secs(1),
+                Duration::from_millis(500),
+                MAX_MOVE_DELAY,
+                DEFAULT_MOVE_DELAY / 2,
+            ]
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Finds puzzles in our own archived games. The engine review of a game points at the
+//! moves that handed the opponent a decisive advantage, the engine then has to confirm
+//! that a single line wins from there. That line, with the losing move as its setup
+//! move, becomes the puzzle, and its themes are guessed from the line itself.
+
+use serde::Serialize;
+use std::io;
+use tracing::debug;
+
+use crate::analysis::GameAnalysis;
+use crate::archive::GameRecord;
+use crate::bot::eval::piece_value;
+use crate::chess::{file_of, rank_of, Move, Outcome, PieceKind, Position};
+use crate::engine::{Score, SearchLimit, UciEngine};
+use crate::puzzles::Puzzle;
+use crate::rating::Rating;
+use crate::types::Color;
+
+/// Mates count as this many centipawns
+const MATE_CENTIPAWNS: i32 = 10_000;
+/// An advantage the side to move should win with
+const DECISIVE: i32 = 300;
+/// Above this the advantage is crushing rather than just decisive
+const CRUSHING: i32 = 600;
+/// How much the setup move has to give away for the position to be worth a puzzle
+const MIN_SWING: i32 = 300;
+/// How much worse the second best move has to be for the best one to count as unique
+const UNIQUE_MARGIN: i32 = 200;
+/// Longest line, in moves of the solver
+const MAX_SOLVER_MOVES: usize = 4;
+/// Material the solver has to have won for a line without mate to be over
+const CONVERTED: i32 = 200;
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+/// A puzzle found in an archived game
+#[derive(Debug, Clone, Serialize)]
+pub struct MinedPuzzle {
+    pub puzzle: Puzzle,
+    /// The ply of the setup move, counting from 1
+    pub ply: usize,
+    /// Whether the player found the winning move in the game
+    pub found: bool,
+}
+
+/// The solver's winning line and how much it wins, in centipawns
+struct WinningLine {
+    moves: Vec<String>,
+    centipawns: i32,
+}
+
+/// Search a reviewed standard game for puzzles, one for every move that turned the game
+/// into a win for the opponent when the engine confirms a single winning line
+pub async fn mine_game(
+    engine: &mut UciEngine,
+    record: &GameRecord,
+    analysis: &GameAnalysis,
+    depth: u32,
+) -> io::Result<Vec<MinedPuzzle>> {
+    let mut mined = Vec::new();
+    let mut position = Position::default();
+    let mut eval_before = Score::Cp(0);
+
+    for (ply, (uci, annotated)) in record.moves.iter().zip(&analysis.moves).enumerate() {
+        let Some(mv) = Move::from_uci(uci).filter(|&mv| position.is_legal(mv)) else {
+            break;
+        };
+        let next = position.play(mv);
+
+        // the review scores from white's point of view
+        let sign = match next.side_to_move() {
+            Color::White => 1,
+            _ => -1,
+        };
+        let before = centipawns(eval_before) * sign;
+        let after = centipawns(annotated.eval) * sign;
+        if after >= DECISIVE && after - before >= MIN_SWING && next.outcome().is_none() {
+            if let Some(line) = winning_line(engine, &next, depth).await? {
+                debug!(game_id = %record.id, ply = ply + 1, line = ?line.moves, "found a puzzle");
+                let found = record.moves.get(ply + 1) == line.moves.first();
+                let themes = themes(&next, &line);
+                mined.push(MinedPuzzle {
+                    puzzle: Puzzle {
+                        id: format!("{}-{}", record.id, ply + 1),
+                        fen: position.to_fen(),
+                        moves: [uci.clone()].into_iter().chain(line.moves).collect(),
+                        rating: Rating::default(),
+                        themes,
+                        plays: 0,
+                        game_id: Some(record.id.clone()),
+                    },
+                    ply: ply + 1,
+                    found,
+                });
+            }
+        }
+
+        position = next;
+        eval_before = annotated.eval;
+    }
+
+    Ok(mined)
+}
+
+/// The solver's line from the position, alternating with the opponent's best replies.
+/// None unless each of the solver's moves is the only one keeping a decisive advantage,
+/// or when a mate doesn't fit in the line.
+async fn winning_line(
+    engine: &mut UciEngine,
+    start: &Position,
+    depth: u32,
+) -> io::Result<Option<WinningLine>> {
+    let limit = SearchLimit::Depth(depth);
+    let solver = start.side_to_move();
+    let material_before = material(start, solver);
+    let mut position = start.clone();
+    let mut line = WinningLine {
+        moves: Vec::new(),
+        centipawns: 0,
+    };
+    let mut mating = false;
+
+    for solver_moves in 0..MAX_SOLVER_MOVES {
+        let analyses = engine.analyse_lines(&position.to_fen(), limit, 2).await?;
+        let best = &analyses[0];
+        let Some(mv) = best
+            .best_move
+            .as_deref()
+            .and_then(Move::from_uci)
+            .filter(|&mv| position.is_legal(mv))
+        else {
+            return Ok(None);
+        };
+        let score = best.score.map(centipawns).unwrap_or_default();
+        if solver_moves == 0 {
+            line.centipawns = score;
+            if matches!(best.score, Some(Score::Mate(n)) if n > MAX_SOLVER_MOVES as i32) {
+                return Ok(None);
+            }
+        }
+        mating = matches!(best.score, Some(Score::Mate(n)) if n > 0);
+
+        let next = position.play(mv);
+        let mates = next.outcome() == Some(Outcome::Checkmate);
+        // every mate in one is accepted when solving, so those don't need to be unique
+        let unique = mates
+            || analyses
+                .get(1)
+                .and_then(|second| second.score)
+                .map(centipawns)
+                .is_none_or(|second| second < DECISIVE && second <= score - UNIQUE_MARGIN);
+        if score < DECISIVE || !unique {
+            // a mate the line stops short of doesn't make a puzzle
+            if solver_moves == 0 || mating {
+                return Ok(None);
+            }
+            line.moves.pop();
+            return Ok(Some(line));
+        }
+
+        line.moves.push(mv.uci());
+        if mates {
+            return Ok(Some(line));
+        }
+
+        let reply = engine.analyse(&next.to_fen(), limit).await?;
+        let Some(reply) = reply
+            .best_move
+            .as_deref()
+            .and_then(Move::from_uci)
+            .filter(|&mv| next.is_legal(mv))
+        else {
+            // a stalemate throws the win away
+            return Ok(None);
+        };
+        line.moves.push(reply.uci());
+        position = next.play(reply);
+
+        // the solver's last move won material the opponent couldn't win back
+        if !mating && material(&position, solver) - material_before >= CONVERTED {
+            line.moves.pop();
+            return Ok(Some(line));
+        }
+    }
+
+    if mating {
+        return Ok(None);
+    }
+    line.moves.pop();
+
+    Ok(Some(line))
+}
+
+/// Mate, its length and the tactical motifs of the solver's moves, or how big the
+/// advantage is when the line doesn't mate
+fn themes(start: &Position, line: &WinningLine) -> Vec<String> {
+    let mut themes = Vec::new();
+    let solver_moves = line.moves.len().div_ceil(2);
+    let mut position = start.clone();
+    let (mut fork, mut pin) = (false, false);
+
+    for (i, uci) in line.moves.iter().enumerate() {
+        let Some(mv) = Move::from_uci(uci) else {
+            break;
+        };
+        position = position.play(mv);
+        if i % 2 == 0 {
+            fork |= is_fork(&position, mv.to);
+            pin |= is_pin(&position, mv.to);
+        }
+    }
+
+    if position.outcome() == Some(Outcome::Checkmate) {
+        themes.push("mate".to_owned());
+        themes.push(format!("mateIn{solver_moves}"));
+    } else if line.centipawns >= CRUSHING {
+        themes.push("crushing".to_owned());
+    } else {
+        themes.push("advantage".to_owned());
+    }
+    if fork {
+        themes.push("fork".to_owned());
+    }
+    if pin {
+        themes.push("pin".to_owned());
+    }
+    themes.push(
+        match solver_moves {
+            1 => "oneMove",
+            2 => "short",
+            3 => "long",
+            _ => "veryLong",
+        }
+        .to_owned(),
+    );
+
+    themes
+}
+
+/// The piece that just moved to the square attacks two enemy pieces it would gain
+/// from taking: the king, pieces worth more than itself or undefended ones
+fn is_fork(position: &Position, square: u8) -> bool {
+    let Some(piece) = position.piece_at(square) else {
+        return false;
+    };
+    let enemy = piece.color.opposite();
+
+    attacked_squares(position, square)
+        .into_iter()
+        .filter_map(|target| Some((target, position.piece_at(target)?)))
+        .filter(|(target, victim)| {
+            victim.color == enemy
+                && (victim.kind == PieceKind::King
+                    || piece_value(victim.kind) > piece_value(piece.kind)
+                    || !position.is_attacked(*target, enemy))
+        })
+        .count()
+        >= 2
+}
+
+/// The piece that just moved to the square pins an enemy piece to the king or to a
+/// piece worth more behind it
+fn is_pin(position: &Position, square: u8) -> bool {
+    let Some(piece) = position.piece_at(square) else {
+        return false;
+    };
+    let enemy = piece.color.opposite();
+
+    slider_directions(piece.kind).iter().any(|&(df, dr)| {
+        let mut hit = ray(square, df, dr).filter_map(|sq| position.piece_at(sq));
+        match (hit.next(), hit.next()) {
+            (Some(pinned), Some(behind)) => {
+                pinned.color == enemy
+                    && pinned.kind != PieceKind::King
+                    && behind.color == enemy
+                    && (behind.kind == PieceKind::King
+                        || piece_value(behind.kind) > piece_value(pinned.kind))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Every square the piece on the square attacks
+fn attacked_squares(position: &Position, square: u8) -> Vec<u8> {
+    let Some(piece) = position.piece_at(square) else {
+        return Vec::new();
+    };
+    let forward = match piece.color {
+        Color::White => 1,
+        _ => -1,
+    };
+    let pawn_steps = [(-1, forward), (1, forward)];
+    let steps: &[(i8, i8)] = match piece.kind {
+        PieceKind::Pawn => &pawn_steps,
+        PieceKind::Knight => &KNIGHT_STEPS,
+        PieceKind::King => &KING_STEPS,
+        _ => &[],
+    };
+
+    let mut squares: Vec<u8> = steps
+        .iter()
+        .filter_map(|&(df, dr)| ray(square, df, dr).next())
+        .collect();
+    for &(df, dr) in slider_directions(piece.kind) {
+        for target in ray(square, df, dr) {
+            squares.push(target);
+            if position.piece_at(target).is_some() {
+                break;
+            }
+        }
+    }
+
+    squares
+}
+
+fn slider_directions(kind: PieceKind) -> &'static [(i8, i8)] {
+    match kind {
+        PieceKind::Bishop => &BISHOP_DIRECTIONS,
+        PieceKind::Rook => &ROOK_DIRECTIONS,
+        // the queen slides wherever the king steps
+        PieceKind::Queen => &KING_STEPS,
+        _ => &[],
+    }
+}
+
+/// The squares from the square outwards in one direction, up to the edge of the board
+fn ray(square: u8, df: i8, dr: i8) -> impl Iterator<Item = u8> {
+    let (file, rank) = (file_of(square) as i8, rank_of(square) as i8);
+
+    (1..8)
+        .map(move |distance| (file + df * distance, rank + dr * distance))
+        .take_while(|(f, r)| (0..8).contains(f) && (0..8).contains(r))
+        .map(|(f, r)| (r * 8 + f) as u8)
+}
+
+/// The color's material minus the opponent's, in centipawns
+fn material(position: &Position, color: Color) -> i32 {
+    position
+        .pieces()
+        .map(|(_, piece)| match piece.color == color {
+            true => piece_value(piece.kind),
+            false => -piece_value(piece.kind),
+        })
+        .sum()
+}
+
+fn centipawns(score: Score) -> i32 {
+    match score {
+        Score::Cp(cp) => cp,
+        Score::Mate(moves) => MATE_CENTIPAWNS * moves.signum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn themes_of(fen: &str, moves: &[&str], centipawns: i32) -> Vec<String> {
+        let line = WinningLine {
+            moves: moves.iter().map(|mv| mv.to_string()).collect(),
+            centipawns,
+        };
+        themes(&Position::from_fen(fen).unwrap(), &line)
+    }
+
+    #[test]
+    fn mates() {
+        // the rook mates on the back rank
+        assert_eq!(
+            themes_of("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", &["a1a8"], 10_000),
+            ["mate", "mateIn1", "oneMove"]
+        );
+        // the rook swings round once the king is boxed in
+        assert_eq!(
+            themes_of(
+                "k7/8/1K6/8/8/8/8/7R w - - 0 1",
+                &["h1h2", "a8b8", "h2h8"],
+                10_000
+            ),
+            ["mate", "mateIn2", "short"]
+        );
+    }
+
+    #[test]
+    fn forks() {
+        // the knight checks the king and attacks the rook
+        assert_eq!(
+            themes_of("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1", &["b5c7"], 500),
+            ["advantage", "fork", "oneMove"]
+        );
+        // a knight only attacking the king isn't one
+        assert_eq!(
+            themes_of("4k3/8/8/1N6/8/8/8/4K3 w - - 0 1", &["b5c7"], 500),
+            ["advantage", "oneMove"]
+        );
+    }
+
+    #[test]
+    fn pins() {
+        // the knight can't leave the king's diagonal
+        assert_eq!(
+            themes_of("4k3/8/2n5/8/8/8/8/4KB2 w - - 0 1", &["f1b5"], 700),
+            ["crushing", "pin", "oneMove"]
+        );
+        // the queen behind the rook is worth more, the pawn behind the knight isn't
+        assert_eq!(
+            themes_of("4k3/q7/8/r7/8/8/8/R3K3 w - - 0 1", &["a1a2"], 700),
+            ["crushing", "pin", "oneMove"]
+        );
+        assert_eq!(
+            themes_of("4k3/p7/8/n7/8/8/8/R3K3 w - - 0 1", &["a1a2"], 700),
+            ["crushing", "oneMove"]
+        );
+    }
+
+    #[test]
+    fn line_lengths() {
+        assert_eq!(
+            themes_of(
+                "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+                &["a1a2", "e8d8", "a2a3", "d8e8", "a3a4"],
+                200
+            ),
+            ["advantage", "long"]
+        );
+        assert_eq!(
+            themes_of(
+                "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+                &["a1a2", "e8d8", "a2a3", "d8e8", "a3a4", "e8d8", "a4a5"],
+                200
+            ),
+            ["advantage", "veryLong"]
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use tracing_subscriber::{fmt, EnvFilter};
+
+use crate::config::LogSettings;
+
+/// Install the global tracing subscriber.
+///
+/// `RUST_LOG` takes precedence over the level from the config files so the
+/// verbosity can be bumped for a single run without editing them.
+pub fn init_subscriber(settings: &LogSettings) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&settings.level));
+
+    let subscriber = fmt().with_env_filter(filter);
+
+    if settings.json {
+        subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init();
+    } else {
+        subscriber.init();
+    }
+}
@@ -0,0 +1,531 @@
+//! Arena tournaments
+//!
+//! An arena runs for a fixed amount of time. Players are paired again as soon as
+//! their game ends and whoever has scored the most points when the clock runs out wins.
+//! Two wins in a row put a player on a streak, which doubles the points of their
+//! following games until they fail to win. Players can berserk to halve their clock
+//! in exchange for an extra point if they win.
+
+use serde::*;
+
+use super::{Participant, TournamentStatus};
+use crate::types::{Color, GameOptions, GameResult, TimeControl};
+
+const WIN_POINTS: u32 = 2;
+const DRAW_POINTS: u32 = 1;
+const BERSERK_BONUS: u32 = 1;
+/// Wins in a row needed before the points of the next games are doubled
+const STREAK_THRESHOLD: u32 = 2;
+
+/// One player's side of an arena game
+#[derive(Debug, Clone, Serialize)]
+pub struct ArenaSide {
+    /// Index into the arena's players
+    pub player: usize,
+    pub berserk: bool,
+    /// Berserking is only possible before the player's first move
+    pub moved: bool,
+}
+
+impl ArenaSide {
+    fn new(player: usize) -> Self {
+        Self {
+            player,
+            berserk: false,
+            moved: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArenaGame {
+    pub game_id: String,
+    pub white: ArenaSide,
+    pub black: ArenaSide,
+    pub result: Option<GameResult>,
+}
+
+impl ArenaGame {
+    fn side_of(&self, player: usize) -> Option<(Color, &ArenaSide)> {
+        if self.white.player == player {
+            Some((Color::White, &self.white))
+        } else if self.black.player == player {
+            Some((Color::Black, &self.black))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArenaStanding {
+    pub rank: usize,
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    pub score: u32,
+    pub games: u32,
+    pub wins: u32,
+    pub berserks: u32,
+    /// Wins in a row going into the player's next game
+    pub streak: u32,
+    /// Whether the next game is worth double points
+    pub on_fire: bool,
+    pub playing: bool,
+    pub withdrawn: bool,
+}
+
+/// A granted berserk, sent to both players so they can adjust the clock
+#[derive(Debug, Clone)]
+pub struct Berserk {
+    pub game_id: String,
+    pub color: Color,
+    /// The player's clock once halved
+    pub time_control: TimeControl,
+}
+
+#[derive(Debug)]
+pub struct ArenaTournament {
+    pub id: String,
+    pub name: String,
+    /// The session that created the arena, the only one allowed to start it
+    pub creator_id: String,
+    pub duration_secs: u64,
+    pub options: GameOptions,
+    pub status: TournamentStatus,
+    /// Seconds since the unix epoch, set once the arena starts
+    pub ends_at: Option<u64>,
+    pub players: Vec<Participant>,
+    /// Every game paired so far, in the order they were paired
+    pub games: Vec<ArenaGame>,
+    /// Indices into `games` in the order they finished, which is what streaks follow
+    finished: Vec<usize>,
+    /// Players waiting for an opponent
+    waiting: Vec<usize>,
+}
+
+impl ArenaTournament {
+    pub fn new(
+        id: String,
+        name: String,
+        creator_id: String,
+        duration_secs: u64,
+        options: GameOptions,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            creator_id,
+            duration_secs,
+            options,
+            status: TournamentStatus::Created,
+            ends_at: None,
+            players: Vec::new(),
+            games: Vec::new(),
+            finished: Vec::new(),
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Players can join an arena until it is over, and come back after withdrawing
+    pub fn join(&mut self, participant: Participant) -> Result<(), &'static str> {
+        if self.status == TournamentStatus::Finished {
+            return Err("the arena is over");
+        }
+
+        let index = match self.player_index(&participant.id) {
+            Some(index) if !self.players[index].withdrawn => return Err("already joined"),
+            Some(index) => {
+                self.players[index].withdrawn = false;
+                index
+            }
+            None => {
+                self.players.push(participant);
+                self.players.len() - 1
+            }
+        };
+
+        if self.status == TournamentStatus::Running && !self.is_playing(index) {
+            self.waiting.push(index);
+        }
+
+        Ok(())
+    }
+
+    pub fn start(&mut self, now: u64) {
+        self.status = TournamentStatus::Running;
+        self.ends_at = Some(now + self.duration_secs);
+        self.waiting = (0..self.players.len())
+            .filter(|&i| !self.players[i].withdrawn)
+            .collect();
+    }
+
+    pub fn finish(&mut self) {
+        self.status = TournamentStatus::Finished;
+        self.waiting.clear();
+    }
+
+    pub fn seconds_left(&self, now: u64) -> u64 {
+        self.ends_at
+            .map_or(self.duration_secs, |ends_at| ends_at.saturating_sub(now))
+    }
+
+    pub fn player_index(&self, id: &str) -> Option<usize> {
+        self.players.iter().position(|player| player.id == id)
+    }
+
+    pub fn withdraw(&mut self, id: &str) {
+        if let Some(index) = self.player_index(id) {
+            self.players[index].withdrawn = true;
+            self.waiting.retain(|&i| i != index);
+        }
+    }
+
+    pub fn active_players(&self) -> usize {
+        self.players.iter().filter(|p| !p.withdrawn).count()
+    }
+
+    /// Put a player back in the queue, e.g. when their game couldn't be started
+    pub fn requeue(&mut self, player: usize) {
+        if !self.players[player].withdrawn && !self.waiting.contains(&player) {
+            self.waiting.push(player);
+        }
+    }
+
+    /// Pair as many waiting players as possible, returned as `(white, black)`.
+    ///
+    /// Players are matched with the closest scored player waiting, but never with the
+    /// opponent they just played unless nobody else is left in the arena.
+    pub fn pair_waiting(&mut self) -> Vec<(usize, usize)> {
+        if self.status != TournamentStatus::Running {
+            return Vec::new();
+        }
+
+        let standings = self.tallies();
+        let mut waiting = std::mem::take(&mut self.waiting);
+        waiting.sort_by(|&a, &b| {
+            standings[b]
+                .score
+                .cmp(&standings[a].score)
+                .then(self.players[b].rating.cmp(&self.players[a].rating))
+        });
+
+        let rematch_allowed = self.active_players() == 2;
+        let mut pairs = Vec::new();
+
+        while !waiting.is_empty() {
+            let a = waiting.remove(0);
+            let partner = waiting.iter().position(|&b| {
+                rematch_allowed
+                    || (self.last_opponent(a) != Some(b) && self.last_opponent(b) != Some(a))
+            });
+
+            match partner {
+                Some(j) => {
+                    let b = waiting.remove(j);
+                    pairs.push(self.assign_colors(a, b));
+                }
+                None => self.waiting.push(a),
+            }
+        }
+
+        pairs
+    }
+
+    pub fn add_game(&mut self, game_id: String, white: usize, black: usize) {
+        self.games.push(ArenaGame {
+            game_id,
+            white: ArenaSide::new(white),
+            black: ArenaSide::new(black),
+            result: None,
+        });
+    }
+
+    /// Store the result of a game and put both players back in the queue,
+    /// returns false if the game isn't part of the arena
+    pub fn record_result(&mut self, game_id: &str, result: GameResult) -> bool {
+        let Some(index) = self
+            .games
+            .iter()
+            .position(|game| game.game_id == game_id && game.result.is_none())
+        else {
+            return false;
+        };
+
+        let game = &mut self.games[index];
+        game.result = Some(result);
+        self.finished.push(index);
+
+        let (white, black) = (game.white.player, game.black.player);
+        self.requeue(white);
+        self.requeue(black);
+
+        true
+    }
+
+    /// Remember that the player has moved in their current game, which rules out berserking
+    pub fn record_move(&mut self, player_id: &str) {
+        let Some(player) = self.player_index(player_id) else {
+            return;
+        };
+
+        if let Some(game) = self.current_game_mut(player) {
+            match game.white.player == player {
+                true => game.white.moved = true,
+                false => game.black.moved = true,
+            }
+        }
+    }
+
+    /// Halve the player's clock in their current game
+    pub fn berserk(&mut self, player_id: &str) -> Result<Berserk, &'static str> {
+        let time_control = self
+            .options
+            .time_control
+            .ok_or("berserk needs a time control")?;
+        let player = self
+            .player_index(player_id)
+            .ok_or("not playing in this arena")?;
+        let game = self
+            .current_game_mut(player)
+            .ok_or("not playing an arena game")?;
+
+        let (color, side) = match game.white.player == player {
+            true => (Color::White, &mut game.white),
+            false => (Color::Black, &mut game.black),
+        };
+        if side.berserk {
+            return Err("already berserked");
+        }
+        if side.moved {
+            return Err("berserk is only possible before your first move");
+        }
+        side.berserk = true;
+
+        Ok(Berserk {
+            game_id: game.game_id.clone(),
+            color,
+            // half the time and no increment
+            time_control: TimeControl {
+                initial_secs: time_control.initial_secs / 2,
+                increment_secs: 0,
+            },
+        })
+    }
+
+    pub fn standings(&self) -> Vec<ArenaStanding> {
+        let tallies = self.tallies();
+
+        let mut standings: Vec<ArenaStanding> = tallies
+            .into_iter()
+            .enumerate()
+            .map(|(i, tally)| {
+                let player = &self.players[i];
+                ArenaStanding {
+                    rank: 0,
+                    id: player.id.clone(),
+                    name: player.name.clone(),
+                    user: player.user.clone(),
+                    score: tally.score,
+                    games: tally.games,
+                    wins: tally.wins,
+                    berserks: tally.berserks,
+                    streak: tally.streak,
+                    on_fire: tally.streak >= STREAK_THRESHOLD,
+                    playing: self.is_playing(i),
+                    withdrawn: player.withdrawn,
+                }
+            })
+            .collect();
+
+        standings.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(b.wins.cmp(&a.wins))
+                .then(a.games.cmp(&b.games))
+        });
+        for (i, standing) in standings.iter_mut().enumerate() {
+            standing.rank = i + 1;
+        }
+
+        standings
+    }
+
+    /// Every player's results, indexed like `players`
+    fn tallies(&self) -> Vec<Tally> {
+        let mut tallies = vec![Tally::default(); self.players.len()];
+
+        for game in self.finished.iter().map(|&i| &self.games[i]) {
+            for (color, side) in [(Color::White, &game.white), (Color::Black, &game.black)] {
+                tallies[side.player].add(color, side.berserk, game.result);
+            }
+        }
+
+        tallies
+    }
+
+    /// The session the player is facing in their current arena game
+    pub fn current_opponent(&self, player_id: &str) -> Option<String> {
+        let player = self.player_index(player_id)?;
+        let game = self
+            .games
+            .iter()
+            .rev()
+            .find(|game| game.result.is_none() && game.side_of(player).is_some())?;
+
+        let opponent = match game.white.player == player {
+            true => game.black.player,
+            false => game.white.player,
+        };
+        Some(self.players[opponent].id.clone())
+    }
+
+    fn current_game_mut(&mut self, player: usize) -> Option<&mut ArenaGame> {
+        self.games
+            .iter_mut()
+            .rev()
+            .find(|game| game.result.is_none() && game.side_of(player).is_some())
+    }
+
+    fn is_playing(&self, player: usize) -> bool {
+        self.games
+            .iter()
+            .any(|game| game.result.is_none() && game.side_of(player).is_some())
+    }
+
+    fn last_opponent(&self, player: usize) -> Option<usize> {
+        self.games.iter().rev().find_map(|game| {
+            let (color, _) = game.side_of(player)?;
+            Some(match color {
+                Color::White => game.black.player,
+                _ => game.white.player,
+            })
+        })
+    }
+
+    /// Give white to whoever has played black more often, alternating on ties
+    fn assign_colors(&self, a: usize, b: usize) -> (usize, usize) {
+        let balance = |player: usize| -> i32 {
+            self.games
+                .iter()
+                .filter_map(|game| game.side_of(player))
+                .map(|(color, _)| if color == Color::White { 1 } else { -1 })
+                .sum()
+        };
+
+        match balance(a).cmp(&balance(b)) {
+            std::cmp::Ordering::Less => (a, b),
+            std::cmp::Ordering::Greater => (b, a),
+            std::cmp::Ordering::Equal => {
+                let a_last_white = self
+                    .games
+                    .iter()
+                    .rev()
+                    .find_map(|game| game.side_of(a))
+                    .is_some_and(|(color, _)| color == Color::White);
+
+                match a_last_white {
+                    true => (b, a),
+                    false => (a, b),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tally {
+    score: u32,
+    games: u32,
+    wins: u32,
+    berserks: u32,
+    streak: u32,
+}
+
+impl Tally {
+    fn add(&mut self, color: Color, berserk: bool, result: Option<GameResult>) {
+        let on_fire = self.streak >= STREAK_THRESHOLD;
+
+        let won = matches!(
+            (result, color),
+            (Some(GameResult::WhiteWins), Color::White)
+                | (Some(GameResult::BlackWins), Color::Black)
+        );
+        let points = match result {
+            _ if won => WIN_POINTS,
+            Some(GameResult::Draw) => DRAW_POINTS,
+            _ => 0,
+        };
+
+        self.games += 1;
+        self.score += if on_fire { points * 2 } else { points };
+        if won {
+            self.wins += 1;
+            self.streak += 1;
+        } else {
+            self.streak = 0;
+        }
+        if berserk {
+            self.berserks += 1;
+            if won {
+                self.score += BERSERK_BONUS;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE_WINS: Option<GameResult> = Some(GameResult::WhiteWins);
+    const BLACK_WINS: Option<GameResult> = Some(GameResult::BlackWins);
+    const DRAW: Option<GameResult> = Some(GameResult::Draw);
+
+    #[test]
+    fn streak_doubles_points() {
+        let mut tally = Tally::default();
+        tally.add(Color::White, false, WHITE_WINS);
+        tally.add(Color::Black, false, BLACK_WINS);
+        assert_eq!((tally.score, tally.streak), (4, 2));
+
+        // the third win in a row is worth double
+        tally.add(Color::White, false, WHITE_WINS);
+        assert_eq!((tally.score, tally.streak), (8, 3));
+        assert_eq!((tally.games, tally.wins), (3, 3));
+    }
+
+    #[test]
+    fn draw_breaks_the_streak() {
+        let mut tally = Tally::default();
+        tally.add(Color::White, false, WHITE_WINS);
+        tally.add(Color::White, false, WHITE_WINS);
+
+        // still on fire for the draw itself
+        tally.add(Color::Black, false, DRAW);
+        assert_eq!((tally.score, tally.streak), (6, 0));
+
+        tally.add(Color::Black, false, BLACK_WINS);
+        assert_eq!((tally.score, tally.streak), (8, 1));
+    }
+
+    #[test]
+    fn berserk_bonus_only_for_wins() {
+        let mut tally = Tally::default();
+        tally.add(Color::White, true, BLACK_WINS);
+        assert_eq!((tally.score, tally.berserks, tally.streak), (0, 1, 0));
+
+        tally.add(Color::Black, true, BLACK_WINS);
+        assert_eq!(
+            (tally.score, tally.berserks),
+            (WIN_POINTS + BERSERK_BONUS, 2)
+        );
+
+        // aborted games score nothing either way
+        tally.add(Color::White, true, Some(GameResult::Aborted));
+        assert_eq!(
+            (tally.score, tally.berserks),
+            (WIN_POINTS + BERSERK_BONUS, 3)
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use serde::*;
+
+pub mod arena;
+pub mod swiss;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentFormat {
+    /// A fixed number of rounds, everyone plays one game per round
+    #[default]
+    Swiss,
+    /// Continuous pairings until the time runs out
+    Arena,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentStatus {
+    /// Players can still join
+    Created,
+    Running,
+    Finished,
+}
+
+/// A player registered in a tournament
+#[derive(Debug, Clone, Serialize)]
+pub struct Participant {
+    /// The session the player joined with
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    /// Used to seed the pairings
+    pub rating: i32,
+    /// Withdrawn players keep their results but aren't paired anymore
+    pub withdrawn: bool,
+}
+
+/// Half points, so draws can be counted without floats
+pub(crate) fn half_points_to_score(half_points: u32) -> f64 {
+    f64::from(half_points) / 2.0
+}
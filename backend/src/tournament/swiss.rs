@@ -0,0 +1,610 @@
+//! Swiss system tournaments
+//!
+//! Every round players are sorted by score and paired with the closest
+//! ranked player they haven't met yet, so players with similar scores meet
+//! each other while nobody plays the same opponent twice.
+
+use serde::*;
+
+use super::{half_points_to_score, Participant, TournamentStatus};
+use crate::types::{Color, GameOptions, GameResult};
+
+/// Give up on finding a perfect pairing after this many attempts and relax the constraints
+const MAX_PAIRING_STEPS: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Pairing {
+    /// Index into the tournament's players
+    pub white: usize,
+    /// None when white was given a bye
+    pub black: Option<usize>,
+    /// None for byes and forfeits, which never get a game
+    pub game_id: Option<String>,
+    pub result: Option<GameResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwissStanding {
+    pub rank: usize,
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    pub score: f64,
+    /// Sum of the opponents' scores
+    pub buchholz: f64,
+    /// Sum of the scores of beaten opponents plus half the scores of drawn ones
+    pub sonneborn_berger: f64,
+    pub withdrawn: bool,
+}
+
+#[derive(Debug)]
+pub struct SwissTournament {
+    pub id: String,
+    pub name: String,
+    /// The session that created the tournament, the only one allowed to start it
+    pub creator_id: String,
+    pub total_rounds: u32,
+    pub options: GameOptions,
+    pub status: TournamentStatus,
+    pub players: Vec<Participant>,
+    pub rounds: Vec<Vec<Pairing>>,
+}
+
+impl SwissTournament {
+    pub fn new(
+        id: String,
+        name: String,
+        creator_id: String,
+        total_rounds: u32,
+        options: GameOptions,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            creator_id,
+            total_rounds,
+            options,
+            status: TournamentStatus::Created,
+            players: Vec::new(),
+            rounds: Vec::new(),
+        }
+    }
+
+    pub fn join(&mut self, participant: Participant) -> Result<(), &'static str> {
+        if self.status != TournamentStatus::Created {
+            return Err("the tournament has already started");
+        }
+        if self.player_index(&participant.id).is_some() {
+            return Err("already joined");
+        }
+
+        self.players.push(participant);
+        Ok(())
+    }
+
+    pub fn player_index(&self, id: &str) -> Option<usize> {
+        self.players.iter().position(|player| player.id == id)
+    }
+
+    pub fn withdraw(&mut self, id: &str) {
+        if let Some(index) = self.player_index(id) {
+            self.players[index].withdrawn = true;
+        }
+    }
+
+    pub fn active_players(&self) -> usize {
+        self.players.iter().filter(|p| !p.withdrawn).count()
+    }
+
+    pub fn current_round(&self) -> usize {
+        self.rounds.len()
+    }
+
+    pub fn round_complete(&self) -> bool {
+        self.rounds
+            .last()
+            .is_none_or(|round| round.iter().all(|p| p.result.is_some()))
+    }
+
+    /// True once the final round has been paired, or there aren't enough players to go on
+    pub fn is_over(&self) -> bool {
+        self.current_round() >= self.total_rounds as usize || self.active_players() < 2
+    }
+
+    /// The current round's pairing at `index`
+    pub fn pairing_mut(&mut self, index: usize) -> Option<&mut Pairing> {
+        self.rounds.last_mut()?.get_mut(index)
+    }
+
+    /// Store the result of a game, returns false if the game isn't part of the current round
+    pub fn record_result(&mut self, game_id: &str, result: GameResult) -> bool {
+        let pairing = self.rounds.last_mut().and_then(|round| {
+            round
+                .iter_mut()
+                .find(|p| p.game_id.as_deref() == Some(game_id))
+        });
+
+        match pairing {
+            Some(pairing) => {
+                pairing.result = Some(result);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pair the active players for the next round, byes are scored right away
+    pub fn pair_next_round(&mut self) {
+        let mut ranked: Vec<usize> = (0..self.players.len())
+            .filter(|&i| !self.players[i].withdrawn)
+            .collect();
+        ranked.sort_by(|&a, &b| {
+            self.half_points(b)
+                .cmp(&self.half_points(a))
+                .then(self.players[b].rating.cmp(&self.players[a].rating))
+                .then(self.players[a].name.cmp(&self.players[b].name))
+        });
+
+        let mut round = Vec::new();
+
+        if ranked.len() % 2 == 1 {
+            // the lowest ranked player who hasn't had a bye yet sits this round out
+            let bye_position = ranked
+                .iter()
+                .rposition(|&i| !self.had_bye(i))
+                .unwrap_or(ranked.len() - 1);
+            let bye = ranked.remove(bye_position);
+
+            round.push(Pairing {
+                white: bye,
+                black: None,
+                game_id: None,
+                result: Some(GameResult::WhiteWins),
+            });
+        }
+
+        for (a, b) in self.pair(&ranked) {
+            let (white, black) = self.assign_colors(a, b);
+
+            round.push(Pairing {
+                white,
+                black: Some(black),
+                game_id: None,
+                result: None,
+            });
+        }
+
+        self.rounds.push(round);
+    }
+
+    pub fn standings(&self) -> Vec<SwissStanding> {
+        let mut standings: Vec<SwissStanding> = (0..self.players.len())
+            .map(|i| {
+                let mut buchholz = 0;
+                let mut sonneborn_berger = 0.0;
+
+                for (opponent, scored) in self.games_of(i) {
+                    let opponent_points = self.half_points(opponent);
+                    buchholz += opponent_points;
+                    sonneborn_berger += f64::from(opponent_points * scored) / 4.0;
+                }
+
+                let player = &self.players[i];
+                SwissStanding {
+                    rank: 0,
+                    id: player.id.clone(),
+                    name: player.name.clone(),
+                    user: player.user.clone(),
+                    score: half_points_to_score(self.half_points(i)),
+                    buchholz: half_points_to_score(buchholz),
+                    sonneborn_berger,
+                    withdrawn: player.withdrawn,
+                }
+            })
+            .collect();
+
+        standings.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.buchholz.total_cmp(&a.buchholz))
+                .then(b.sonneborn_berger.total_cmp(&a.sonneborn_berger))
+        });
+        for (i, standing) in standings.iter_mut().enumerate() {
+            standing.rank = i + 1;
+        }
+
+        standings
+    }
+
+    /// Points scored by the player, byes and forfeit wins included
+    fn half_points(&self, player: usize) -> u32 {
+        self.rounds
+            .iter()
+            .flatten()
+            .filter_map(|pairing| half_points_for(pairing, player))
+            .sum()
+    }
+
+    /// The opponents the player actually faced and the half points scored against each
+    fn games_of(&self, player: usize) -> Vec<(usize, u32)> {
+        self.rounds
+            .iter()
+            .flatten()
+            .filter_map(|pairing| {
+                let black = pairing.black?;
+                let opponent = match player {
+                    p if p == pairing.white => black,
+                    p if p == black => pairing.white,
+                    _ => return None,
+                };
+                Some((opponent, half_points_for(pairing, player)?))
+            })
+            .collect()
+    }
+
+    fn had_bye(&self, player: usize) -> bool {
+        self.rounds
+            .iter()
+            .flatten()
+            .any(|pairing| pairing.white == player && pairing.black.is_none())
+    }
+
+    fn have_met(&self, a: usize, b: usize) -> bool {
+        self.rounds.iter().flatten().any(|pairing| {
+            (pairing.white == a && pairing.black == Some(b))
+                || (pairing.white == b && pairing.black == Some(a))
+        })
+    }
+
+    /// Colors the player had in previous rounds, oldest first
+    fn color_history(&self, player: usize) -> Vec<Color> {
+        self.rounds
+            .iter()
+            .flatten()
+            .filter_map(|pairing| match pairing.black {
+                Some(_) if pairing.white == player => Some(Color::White),
+                Some(black) if black == player => Some(Color::Black),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// How much the player is owed white, negative when they are owed black.
+    /// Players who had the same color twice in a row or are two games off balance
+    /// must get the other color, which is reported as ±10.
+    fn white_preference(&self, player: usize) -> i32 {
+        let history = self.color_history(player);
+        let balance: i32 = history
+            .iter()
+            .map(|color| if *color == Color::White { 1 } else { -1 })
+            .sum();
+        let last_two_same =
+            history.len() >= 2 && history[history.len() - 1] == history[history.len() - 2];
+
+        match history.last() {
+            _ if balance >= 2 => -10,
+            _ if balance <= -2 => 10,
+            Some(Color::White) if last_two_same => -10,
+            Some(Color::Black) if last_two_same => 10,
+            Some(Color::White) => -1 - balance,
+            Some(_) => 1 - balance,
+            None => 0,
+        }
+    }
+
+    /// Give white to the player who is owed it the most, the higher ranked player `a` breaks ties
+    fn assign_colors(&self, a: usize, b: usize) -> (usize, usize) {
+        let a_preference = self.white_preference(a);
+        let b_preference = self.white_preference(b);
+
+        match a_preference.cmp(&b_preference) {
+            std::cmp::Ordering::Greater => (a, b),
+            std::cmp::Ordering::Less => (b, a),
+            std::cmp::Ordering::Equal => match self.color_history(a).last() {
+                Some(Color::White) => (b, a),
+                Some(_) => (a, b),
+                None if self.current_round().is_multiple_of(2) => (a, b),
+                None => (b, a),
+            },
+        }
+    }
+
+    /// Pair the ranked players, relaxing the constraints when no pairing satisfies them:
+    /// first no rematches and no forced color clashes, then only no rematches, then anything.
+    fn pair(&self, ranked: &[usize]) -> Vec<(usize, usize)> {
+        let no_color_clash = |a: usize, b: usize| {
+            let (a, b) = (self.white_preference(a), self.white_preference(b));
+            !(a.abs() == 10 && a == b)
+        };
+
+        let attempts: [&dyn Fn(usize, usize) -> bool; 3] = [
+            &|a, b| !self.have_met(a, b) && no_color_clash(a, b),
+            &|a, b| !self.have_met(a, b),
+            &|_, _| true,
+        ];
+
+        for allowed in attempts {
+            let mut pairs = Vec::new();
+            let mut steps = 0;
+            if backtrack(&mut ranked.to_vec(), &mut pairs, allowed, &mut steps) {
+                return pairs;
+            }
+        }
+
+        unreachable!("any even number of players can be paired without constraints")
+    }
+}
+
+/// Points the player scored in a pairing, None if they weren't part of it or it isn't over
+fn half_points_for(pairing: &Pairing, player: usize) -> Option<u32> {
+    let color = if pairing.white == player {
+        Color::White
+    } else if pairing.black == Some(player) {
+        Color::Black
+    } else {
+        return None;
+    };
+
+    Some(match (pairing.result?, color) {
+        (GameResult::WhiteWins, Color::White) | (GameResult::BlackWins, Color::Black) => 2,
+        (GameResult::Draw, _) => 1,
+        // aborted games are scored as a double forfeit
+        _ => 0,
+    })
+}
+
+/// Pair the first remaining player with the closest ranked allowed opponent, backtracking
+/// when the rest of the players can't be paired
+fn backtrack(
+    remaining: &mut Vec<usize>,
+    pairs: &mut Vec<(usize, usize)>,
+    allowed: &dyn Fn(usize, usize) -> bool,
+    steps: &mut usize,
+) -> bool {
+    if remaining.is_empty() {
+        return true;
+    }
+    *steps += 1;
+    if *steps > MAX_PAIRING_STEPS {
+        return false;
+    }
+
+    let a = remaining.remove(0);
+    for j in 0..remaining.len() {
+        let b = remaining[j];
+        if !allowed(a, b) {
+            continue;
+        }
+
+        remaining.remove(j);
+        pairs.push((a, b));
+        if backtrack(remaining, pairs, allowed, steps) {
+            return true;
+        }
+        pairs.pop();
+        remaining.insert(j, b);
+    }
+    remaining.insert(0, a);
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Players rated 2000, 1900, 1800... so the ranking is their index until results come in
+    fn tournament(players: usize) -> SwissTournament {
+        let mut tournament = SwissTournament::new(
+            "swiss".into(),
+            "Swiss".into(),
+            "p0".into(),
+            5,
+            GameOptions::default(),
+        );
+        for i in 0..players {
+            tournament
+                .join(Participant {
+                    id: format!("p{i}"),
+                    name: format!("Player {i}"),
+                    user: None,
+                    rating: 2000 - 100 * i as i32,
+                    withdrawn: false,
+                })
+                .unwrap();
+        }
+        tournament.status = TournamentStatus::Running;
+
+        tournament
+    }
+
+    fn game(white: usize, black: usize, result: GameResult) -> Pairing {
+        Pairing {
+            white,
+            black: Some(black),
+            game_id: None,
+            result: Some(result),
+        }
+    }
+
+    fn bye(player: usize) -> Pairing {
+        Pairing {
+            white: player,
+            black: None,
+            game_id: None,
+            result: Some(GameResult::WhiteWins),
+        }
+    }
+
+    fn pairs_of(round: &[Pairing]) -> Vec<(usize, Option<usize>)> {
+        round.iter().map(|p| (p.white, p.black)).collect()
+    }
+
+    #[test]
+    fn backtracks_when_the_rest_cant_be_paired() {
+        // 0-1 is allowed but leaves 2-3, which isn't
+        let allowed = |a: usize, b: usize| (a, b) != (2, 3);
+        let mut pairs = Vec::new();
+
+        assert!(backtrack(
+            &mut vec![0, 1, 2, 3],
+            &mut pairs,
+            &allowed,
+            &mut 0
+        ));
+        assert_eq!(pairs, [(0, 2), (1, 3)]);
+
+        let mut pairs = Vec::new();
+        assert!(!backtrack(
+            &mut vec![0, 1],
+            &mut pairs,
+            &|_, _| false,
+            &mut 0
+        ));
+        assert!(pairs.is_empty());
+    }
+
+    #[test]
+    fn relaxes_constraints_when_nothing_satisfies_them() {
+        let mut tournament = tournament(4);
+        // 0 and 1 had white twice and 2 and 3 black twice, the only pairs that haven't
+        // met both need the same color
+        tournament.rounds = vec![
+            vec![game(0, 2, GameResult::Draw), game(1, 3, GameResult::Draw)],
+            vec![game(0, 3, GameResult::Draw), game(1, 2, GameResult::Draw)],
+        ];
+        assert_eq!(tournament.pair(&[0, 1, 2, 3]), [(0, 1), (2, 3)]);
+
+        // everyone has met everyone, so someone has to play again
+        tournament.rounds.push(vec![
+            game(0, 1, GameResult::Draw),
+            game(2, 3, GameResult::Draw),
+        ]);
+        assert_eq!(tournament.pair(&[0, 1, 2, 3]), [(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn no_repeat_pairings() {
+        let mut tournament = tournament(4);
+
+        tournament.pair_next_round();
+        assert_eq!(
+            pairs_of(&tournament.rounds[0]),
+            [(0, Some(1)), (2, Some(3))]
+        );
+        for pairing in &mut tournament.rounds[0] {
+            pairing.result = Some(GameResult::WhiteWins);
+        }
+
+        // the winners meet, the losers meet, and white alternates
+        tournament.pair_next_round();
+        assert_eq!(
+            pairs_of(&tournament.rounds[1]),
+            [(2, Some(0)), (1, Some(3))]
+        );
+    }
+
+    #[test]
+    fn white_preference() {
+        let mut tournament = tournament(6);
+        assert_eq!(tournament.white_preference(0), 0);
+
+        tournament.rounds = vec![
+            vec![
+                game(0, 1, GameResult::Draw),
+                game(2, 3, GameResult::Draw),
+                game(5, 4, GameResult::Draw),
+            ],
+            vec![
+                game(0, 3, GameResult::Draw),
+                game(2, 1, GameResult::Draw),
+                game(4, 5, GameResult::Draw),
+            ],
+        ];
+
+        // white twice, black twice
+        assert_eq!(tournament.white_preference(0), -10);
+        assert_eq!(tournament.white_preference(3), 10);
+        // balanced, owed the color they didn't have last
+        assert_eq!(tournament.white_preference(4), -1);
+        assert_eq!(tournament.white_preference(5), 1);
+
+        tournament.rounds.push(vec![
+            game(1, 0, GameResult::Draw),
+            game(2, 4, GameResult::Draw),
+            game(3, 5, GameResult::Draw),
+        ]);
+
+        // white three times in a row
+        assert_eq!(tournament.white_preference(2), -10);
+        // black, white, black: owed white
+        assert_eq!(tournament.white_preference(4), 2);
+        // white, black, black
+        assert_eq!(tournament.white_preference(5), 10);
+        // white twice then black, one game off balance
+        assert_eq!(tournament.white_preference(0), 0);
+    }
+
+    #[test]
+    fn bye_goes_to_the_lowest_ranked_player_without_one() {
+        let mut tournament = tournament(5);
+
+        tournament.pair_next_round();
+        assert!(tournament.rounds[0]
+            .iter()
+            .any(|p| p.white == 4 && p.black.is_none()));
+
+        // everyone ends up level, so the ranking follows the ratings again
+        tournament.rounds = vec![
+            vec![
+                bye(4),
+                game(0, 1, GameResult::Draw),
+                game(2, 3, GameResult::Draw),
+            ],
+            vec![game(0, 2, GameResult::Draw), game(1, 3, GameResult::Draw)],
+        ];
+        tournament.pair_next_round();
+
+        let round = &tournament.rounds[2];
+        let byes: Vec<_> = round.iter().filter(|p| p.black.is_none()).collect();
+        assert_eq!(byes.len(), 1);
+        assert_eq!(byes[0].white, 3);
+        assert_eq!(byes[0].result, Some(GameResult::WhiteWins));
+        assert_eq!(round.len(), 3);
+    }
+
+    #[test]
+    fn tiebreaks() {
+        let mut tournament = tournament(4);
+        tournament.rounds = vec![
+            vec![
+                game(0, 1, GameResult::WhiteWins),
+                game(2, 3, GameResult::Draw),
+            ],
+            vec![
+                game(2, 0, GameResult::WhiteWins),
+                game(1, 3, GameResult::WhiteWins),
+            ],
+        ];
+
+        let standings = tournament.standings();
+        let summary: Vec<_> = standings
+            .iter()
+            .map(|s| (s.id.as_str(), s.score, s.buchholz, s.sonneborn_berger))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                ("p2", 1.5, 1.5, 1.25),
+                ("p0", 1.0, 2.5, 1.0),
+                ("p1", 1.0, 1.5, 0.5),
+                ("p3", 0.5, 2.5, 0.75),
+            ]
+        );
+        assert_eq!(
+            standings.iter().map(|s| s.rank).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+    }
+}
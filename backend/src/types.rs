@@ -1,8 +1,9 @@
 use serde::*;
 
+use crate::chess::{square_name, Move, VariantKind};
 use crate::websocket::messages::MakeMove;
 
-#[derive(Debug, Deserialize, Serialize, Copy, Clone)]
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Color {
     #[serde(rename(serialize = "b", deserialize = "b"))]
     Black,
@@ -12,11 +13,108 @@ pub enum Color {
     None,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Color {
+    pub fn opposite(&self) -> Self {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+            Color::None => Color::None,
+        }
+    }
+}
+
+/// Starting time on each clock and the time added after every move
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq)]
+pub struct TimeControl {
+    pub initial_secs: u32,
+    pub increment_secs: u32,
+}
+
+impl TimeControl {
+    /// Longest clock a game can start with, three hours
+    pub const MAX_INITIAL_SECS: u32 = 3 * 60 * 60;
+    /// Most time added after a move, three minutes
+    pub const MAX_INCREMENT_SECS: u32 = 3 * 60;
+
+    /// Whether the clocks are within the limits and give the players any time at all
+    pub fn is_valid(&self) -> bool {
+        self.initial_secs <= Self::MAX_INITIAL_SECS
+            && self.increment_secs <= Self::MAX_INCREMENT_SECS
+            && (self.initial_secs > 0 || self.increment_secs > 0)
+    }
+}
+
+/// Who can find and join a game
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Listed in the lobby while it waits for an opponent
+    #[default]
+    Public,
+    /// Anyone who knows the game id can join, but it isn't listed
+    Unlisted,
+    /// Joining needs the game's invite token or its password
+    Private,
+}
+
+/// Settings chosen by the player creating a game
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct GameOptions {
+    /// Only honoured when both players are logged in
+    #[serde(default)]
+    pub rated: bool,
+    /// None for untimed games, which are never rated
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+    /// Makes a correspondence game, where each side has this many days per move
+    /// instead of a clock
+    #[serde(default)]
+    pub days_per_move: Option<u32>,
+    #[serde(default)]
+    pub variant: VariantKind,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Lets private games be joined with a password as well as with the invite token,
+    /// never sent back to the client
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    /// Set by the server for games it pairs in a tournament, never by the client
+    #[serde(skip)]
+    pub tournament_id: Option<String>,
+}
+
+/// The outcome of a game, serialized the way PGN writes it
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq, Eq)]
+pub enum GameResult {
+    #[serde(rename = "1-0")]
+    WhiteWins,
+    #[serde(rename = "0-1")]
+    BlackWins,
+    #[serde(rename = "1/2-1/2")]
+    Draw,
+    /// The game was stopped without a winner, e.g. by an arbiter
+    #[serde(rename = "*")]
+    Aborted,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChessMove {
     pub from: String,
     pub to: String,
     pub promotion_piece: Option<String>,
+    /// The piece put down by a crazyhouse drop, `from` is ignored then
+    #[serde(default)]
+    pub drop_piece: Option<String>,
+    /// Standard algebraic notation, filled in by the server
+    #[serde(default)]
+    pub san: Option<String>,
+    /// The position after the move, filled in by the server
+    #[serde(default)]
+    pub fen: Option<String>,
+    /// Set on moves the server played from the player's premove queue, those are
+    /// echoed back to the player and took no time on their clock
+    #[serde(default)]
+    pub premove: bool,
 }
 
 impl From<MakeMove> for ChessMove {
@@ -25,6 +123,24 @@ impl From<MakeMove> for ChessMove {
             from: value.from,
             to: value.to,
             promotion_piece: value.promotion_piece,
+            drop_piece: value.drop_piece,
+            san: None,
+            fen: None,
+            premove: false,
+        }
+    }
+}
+
+impl From<Move> for ChessMove {
+    fn from(value: Move) -> Self {
+        Self {
+            from: square_name(value.from),
+            to: square_name(value.to),
+            promotion_piece: value.promotion.map(|kind| kind.name().to_owned()),
+            drop_piece: value.drop.map(|kind| kind.name().to_owned()),
+            san: None,
+            fen: None,
+            premove: false,
         }
     }
 }
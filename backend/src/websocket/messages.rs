@@ -1,8 +1,22 @@
 use actix::prelude::*;
 use serde::*;
+use std::io;
+use std::path::PathBuf;
 
-use super::{servers::in_memory::PlayerStatus, session::Message};
-use crate::types::Color;
+use super::{
+    servers::in_memory::{
+        CorrespondenceGame, GameState, GameSummary, OpenGame, Player, PlayerStatus, SessionSummary,
+    },
+    session::{Close, Message},
+};
+use crate::bot::BotOptions;
+use crate::chess::CheckDirection;
+use crate::rating::{RatingChange, RatingChanges};
+use crate::replay::{ReplayPosition, StoredGame};
+use crate::tournament::{
+    arena::ArenaStanding, swiss::SwissStanding, TournamentFormat, TournamentStatus,
+};
+use crate::types::{ChessMove, Color, GameOptions, GameResult, TimeControl};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
@@ -16,6 +30,39 @@ pub enum Type {
     OpponentJoined,
     MakeMove,
     UpdateGameState,
+    GameOver,
+    Maintenance,
+    Announcement,
+    CreateTournament,
+    JoinTournament,
+    StartTournament,
+    TournamentPairing,
+    TournamentStandings,
+    ArenaStandings,
+    Berserk,
+    Opening,
+    Premove,
+    CancelPremoves,
+    ResumeGame,
+    OfferRematch,
+    AcceptRematch,
+    DeclineRematch,
+    CreateBughouse,
+    JoinBughouse,
+    BughouseSeats,
+    BughouseStarted,
+    BughouseBoard,
+    TeamChat,
+    Referee,
+    RequestPuzzle,
+    Puzzle,
+    PuzzleMove,
+    PuzzleFeedback,
+    StartReplay,
+    StopReplay,
+    ReplayMove,
+    ReplayEnd,
+    DrawOffer,
 }
 
 #[derive(Message, Serialize)]
@@ -27,17 +74,30 @@ pub struct Connect {
 
     pub id: String,
 
+    /// The authenticated username, None for guests
+    pub user: Option<String>,
+
     #[serde(skip_serializing)]
     /// the address of the session actor
     pub addr: Recipient<Message>,
+
+    #[serde(skip_serializing)]
+    pub close_addr: Recipient<Close>,
 }
 
 impl Connect {
-    pub fn new(id: String, addr: Recipient<Message>) -> Self {
+    pub fn new(
+        id: String,
+        user: Option<String>,
+        addr: Recipient<Message>,
+        close_addr: Recipient<Close>,
+    ) -> Self {
         Self {
             m_type: Type::Connect,
             id,
+            user,
             addr,
+            close_addr,
         }
     }
 }
@@ -72,12 +132,25 @@ pub struct CreateGame {
     pub name: String,
     #[serde(skip_serializing)]
     pub color: Color,
+    #[serde(flatten)]
+    pub options: GameOptions,
+    /// Play against the computer instead of waiting for an opponent
+    #[serde(default)]
+    pub bot: Option<BotOptions>,
+    /// Set by the server on private games, the creator shares it as `/join/{token}`
+    #[serde(skip_deserializing)]
+    pub invite_token: Option<String>,
 }
 
 #[derive(Message, Deserialize, Serialize, Debug)]
 #[rtype(result = "()")]
 pub struct JoinGame {
     pub game_id: String,
+    /// Private games need either their invite token or their password
+    #[serde(default)]
+    pub invite_token: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
     #[serde(skip_deserializing)]
     pub player_id: String,
 }
@@ -89,12 +162,148 @@ pub struct MakeMove {
     pub from: String,
     pub to: String,
     pub promotion_piece: Option<String>,
+    /// Set for crazyhouse drops
+    #[serde(default)]
+    pub drop_piece: Option<String>,
     #[serde(skip_deserializing)]
     pub game_id: String,
     #[serde(skip_deserializing)]
     pub player_id: String,
 }
 
+/// Ask the opponent of a game that just ended for another game, also sent to the
+/// opponent to let them know about the offer
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct OfferRematch {
+    pub game_id: String,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub player_id: String,
+}
+
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct AcceptRematch {
+    pub game_id: String,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Turn down a rematch offer, also sent to the player who offered it
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct DeclineRematch {
+    pub game_id: String,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub player_id: String,
+}
+
+/// Sent to both players once a rematch was accepted, before they get `OpponentJoined`
+#[derive(Serialize, Debug)]
+pub struct RematchStarted {
+    pub previous_game_id: String,
+    pub game_id: String,
+    pub color: Color,
+}
+
+/// Open a bughouse match, the creator takes the first seat
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct CreateBughouse {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub player_id: String,
+    pub name: String,
+    /// Applied to both boards
+    #[serde(default)]
+    pub time_control: Option<TimeControl>,
+}
+
+/// Take a seat in a bughouse match, the first free one unless a seat is asked for.
+/// Seats 0 and 1 are white and black on the first board, 2 and 3 on the second, and
+/// partners sit on opposite colors of different boards: 0 with 3, 1 with 2.
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct JoinBughouse {
+    pub match_id: String,
+    #[serde(default)]
+    pub seat: Option<usize>,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Sent to everyone seated in a bughouse match whenever somebody sits down or leaves
+#[derive(Serialize, Debug)]
+pub struct BughouseSeats {
+    pub match_id: String,
+    pub name: String,
+    /// The names of the seated players, by seat
+    pub seats: Vec<Option<String>>,
+}
+
+/// Sent to the four players once every seat is taken, before they get `OpponentJoined`
+#[derive(Serialize, Debug)]
+pub struct BughouseStarted {
+    pub match_id: String,
+    pub game_id: String,
+    pub color: Color,
+    /// The board the partner plays on
+    pub partner_game_id: String,
+    pub partner: Option<Player>,
+}
+
+/// The position of a bughouse board after a move on it or after a capture on the
+/// other board filled one of its pockets
+#[derive(Serialize, Debug)]
+pub struct BughouseBoard {
+    pub game_id: String,
+    pub fen: String,
+    /// The move that was played, None when only a pocket changed
+    pub san: Option<String>,
+}
+
+/// A message between bughouse partners, nobody else sees it
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct TeamChat {
+    pub message: String,
+    /// The sender's name, filled in by the server
+    #[serde(skip_deserializing)]
+    pub from: String,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub player_id: String,
+}
+
+/// Queue a move to be played as soon as it is the player's turn
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct Premove {
+    pub from: String,
+    pub to: String,
+    pub promotion_piece: Option<String>,
+    #[serde(default)]
+    pub drop_piece: Option<String>,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct CancelPremoves {
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Sent to a player whenever their premove queue changes
+#[derive(Serialize, Debug)]
+pub struct PremoveQueue {
+    /// How many premoves are still waiting to be played
+    pub queued: usize,
+    /// Why the server emptied the queue, if it did
+    pub discarded: Option<String>,
+}
+
 #[derive(Message, Deserialize, Serialize, Debug)]
 #[rtype(result = "()")]
 pub struct UpdateName {
@@ -111,6 +320,336 @@ pub struct UpdateGameState {
     pub player_id: String,
 }
 
+/// Sent to the opponent of a player offering a draw, who accepts by offering one too
+#[derive(Serialize, Debug)]
+pub struct DrawOffer {
+    pub game_id: String,
+}
+
+/// Sent to both players once their game is over
+#[derive(Serialize, Debug)]
+pub struct GameOver {
+    pub game_id: String,
+    pub result: GameResult,
+    pub game_state: GameState,
+    /// Only present for rated games
+    pub rating_changes: Option<RatingChanges>,
+    /// In UCI notation, only present when the variant kept the board from the players
+    pub moves: Option<Vec<String>>,
+    /// The final position, only present when the variant kept the board from the players
+    pub fen: Option<String>,
+}
+
+/// What the Kriegspiel referee tells both players after every attempted move
+#[derive(Serialize, Debug)]
+pub struct RefereeAnnouncement {
+    pub game_id: String,
+    /// The side that tried to move
+    pub color: Color,
+    pub legal: bool,
+    pub capture: Option<CaptureAnnouncement>,
+    /// Where the checks on the side to move come from
+    pub checks: Vec<CheckDirection>,
+    /// What the recipient can see of the board, their own pieces
+    pub fen: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CaptureAnnouncement {
+    pub square: String,
+    /// Whether a pawn or a piece was taken, which one isn't announced
+    pub pawn: bool,
+}
+
+/// Ask for a puzzle close to the player's puzzle rating, or for a specific one
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct RequestPuzzle {
+    #[serde(default)]
+    pub puzzle_id: Option<String>,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// A puzzle to solve, the opponent's setup move was already played
+#[derive(Serialize, Debug)]
+pub struct PuzzlePosition {
+    pub puzzle_id: String,
+    pub fen: String,
+    /// The opponent's setup move in UCI notation
+    pub last_move: String,
+    /// The side the solver plays
+    pub color: Color,
+    pub rating: i32,
+    pub themes: Vec<String>,
+    /// The archived game the puzzle was found in
+    pub game_id: Option<String>,
+}
+
+/// A move in the puzzle the player is solving
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct PuzzleMove {
+    pub from: String,
+    pub to: String,
+    pub promotion_piece: Option<String>,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// The answer to a puzzle move, a wrong move ends the attempt
+#[derive(Serialize, Debug)]
+pub struct PuzzleFeedback {
+    pub puzzle_id: String,
+    pub correct: bool,
+    pub solved: bool,
+    /// The opponent's answer in UCI notation, already played
+    pub reply: Option<String>,
+    /// The position after the move and the reply
+    pub fen: String,
+    /// The line the solver should have played, only sent after a wrong move
+    pub solution: Option<Vec<String>>,
+    /// Only sent once a signed in player's attempt is over
+    pub rating_change: Option<RatingChange>,
+}
+
+/// Play a stored game back to the player, at the pace it was played unless sped up
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct StartReplay {
+    pub game_id: String,
+    /// How many times faster than the players the moves come
+    #[serde(default)]
+    pub speed: Option<f64>,
+    /// The ply to start from, the starting position by default
+    #[serde(default)]
+    pub from_ply: usize,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct StopReplay {
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// The next position of a replay
+#[derive(Serialize, Debug)]
+pub struct ReplayMove {
+    pub game_id: String,
+    #[serde(flatten)]
+    pub position: ReplayPosition,
+}
+
+/// Sent once a replay played its last stored move
+#[derive(Serialize, Debug)]
+pub struct ReplayEnd {
+    pub game_id: String,
+    /// Whether the game is still going on, with moves the replay didn't have yet
+    pub live: bool,
+}
+
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct CreateTournament {
+    #[serde(skip_deserializing)]
+    pub id: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub player_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub format: TournamentFormat,
+    /// Number of rounds of a Swiss tournament
+    #[serde(default)]
+    pub rounds: u32,
+    /// How long an arena runs once started
+    #[serde(default)]
+    pub duration_mins: u32,
+    /// Applied to every game of the tournament
+    #[serde(flatten)]
+    pub options: GameOptions,
+}
+
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct JoinTournament {
+    pub tournament_id: String,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Pairs the first round of a Swiss tournament or starts the clock of an arena,
+/// only the creator can start a tournament
+#[derive(Message, Deserialize, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct StartTournament {
+    pub tournament_id: String,
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Sent to every paired player when a round starts
+#[derive(Serialize, Debug)]
+pub struct TournamentPairing {
+    #[serde(skip_serializing)]
+    pub player_id: String,
+    pub tournament_id: String,
+    /// None in arenas, which don't have rounds
+    pub round: Option<usize>,
+    /// None when the player got a bye or their opponent couldn't play
+    pub game_id: Option<String>,
+    pub color: Option<Color>,
+    pub opponent: Option<Player>,
+}
+
+impl TournamentPairing {
+    /// A round the player sits out, because of a bye or because the game couldn't be started
+    pub fn unplayed(player_id: &str, tournament_id: &str, round: usize) -> Self {
+        Self {
+            player_id: player_id.to_owned(),
+            tournament_id: tournament_id.to_owned(),
+            round: Some(round),
+            game_id: None,
+            color: None,
+            opponent: None,
+        }
+    }
+}
+
+/// Sent to every participant whenever the standings change
+#[derive(Serialize, Debug)]
+pub struct TournamentStandings {
+    pub tournament_id: String,
+    pub name: String,
+    pub status: TournamentStatus,
+    pub round: usize,
+    pub total_rounds: u32,
+    pub standings: Vec<SwissStanding>,
+}
+
+/// Pushed to arena players every few seconds and whenever a game ends, until the arena is over
+#[derive(Serialize, Debug)]
+pub struct ArenaStandings {
+    pub tournament_id: String,
+    pub name: String,
+    pub status: TournamentStatus,
+    pub seconds_left: u64,
+    pub standings: Vec<ArenaStanding>,
+}
+
+/// Trade half of the clock for an extra point in the current arena game
+#[derive(Message, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct Berserk {
+    #[serde(skip_deserializing)]
+    pub player_id: String,
+}
+
+/// Sent to both players of an arena game when one of them berserks
+#[derive(Serialize, Debug)]
+pub struct Berserked {
+    pub tournament_id: String,
+    pub game_id: String,
+    pub color: Color,
+    /// The berserking player's clock
+    pub time_control: TimeControl,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<SessionSummary>")]
+pub struct ListSessions;
+
+#[derive(Message, Debug)]
+#[rtype(result = "bool")]
+pub struct KickSession {
+    pub id: String,
+    pub reason: String,
+}
+
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<GameSummary>")]
+pub struct ListGames;
+
+#[derive(Message, Debug)]
+#[rtype(result = "Option<GameSummary>")]
+pub struct GetGame {
+    pub id: String,
+}
+
+/// The moves of an archived game, or of an ongoing one anybody may look at
+#[derive(Message, Debug)]
+#[rtype(result = "Option<StoredGame>")]
+pub struct GetStoredGame {
+    pub id: String,
+}
+
+/// Ends a game with the given result, regardless of what the players report
+#[derive(Message, Debug)]
+#[rtype(result = "bool")]
+pub struct TerminateGame {
+    pub id: String,
+    pub result: GameResult,
+}
+
+/// A notice from the administrators pushed to every connected session
+#[derive(Message, Serialize, Deserialize, Debug)]
+#[rtype(result = "()")]
+pub struct Announcement {
+    pub message: String,
+}
+
+/// Sent to the server when the process is asked to terminate.
+/// New games are refused from then on and every session is told the server is going down.
+#[derive(Message, Serialize, Debug)]
+#[rtype(result = "()")]
+pub struct BeginShutdown {
+    pub message: String,
+    /// How many seconds the running games have left before the server stops
+    pub deadline_secs: u64,
+}
+
+/// Asks the server how many games are still being played
+#[derive(Message, Debug)]
+#[rtype(result = "usize")]
+pub struct ActiveGames;
+
+/// Write every game to disk so it can be restored on the next startup
+#[derive(Message, Debug)]
+#[rtype(result = "io::Result<()>")]
+pub struct SaveSnapshot {
+    pub path: PathBuf,
+}
+
+/// A correspondence move submitted over HTTP by the account seated in the game
+#[derive(Message, Debug)]
+#[rtype(result = "Result<ChessMove, String>")]
+pub struct SubmitMove {
+    pub game_id: String,
+    pub user: String,
+    pub chess_move: ChessMove,
+}
+
+/// Public games waiting for an opponent
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<OpenGame>")]
+pub struct ListOpenGames;
+
+/// The game an invite token lets into, if it is still waiting for an opponent
+#[derive(Message, Debug)]
+#[rtype(result = "Option<OpenGame>")]
+pub struct GetInvitation {
+    pub token: String,
+}
+
+/// The correspondence games an account is playing
+#[derive(Message, Debug)]
+#[rtype(result = "Vec<CorrespondenceGame>")]
+pub struct ListCorrespondenceGames {
+    pub user: String,
+}
+
 /// Represents a message that will be sent to the client
 /// It is not meant to be sent between actors
 #[derive(Serialize, Deserialize, Debug)]
@@ -1,46 +1,370 @@
 use actix::{Actor, Context, Handler};
 use serde_json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, error, info, info_span, warn};
 
 use super::{
     messages::{
-        Connect, CreateGame, Disconnect, JoinGame, MakeMove, Type, UpdateGameState, UpdateName,
+        ActiveGames, Announcement, BeginShutdown, Connect, CreateGame, Disconnect, DrawOffer,
+        GameOver, GetGame, JoinGame, KickSession, ListGames, ListSessions, MakeMove, SaveSnapshot,
+        TerminateGame, Type, UpdateGameState, UpdateName,
+    },
+    servers::{
+        in_memory::{FinishedGame, GameSummary, SessionSummary, ILLEGAL_MOVE},
+        WsServer,
     },
-    servers::WsServer,
 };
 use crate::{
-    types::{ChessMove, Color},
+    accounts::Accounts,
+    archive::{GameArchive, GameRecord},
+    chess,
+    config::{BotSettings, Settings},
+    engine::EnginePool,
+    puzzles::PuzzleStore,
+    rating::{Pool, RatingChanges, RatingStore},
+    tournament::{arena::ArenaTournament, swiss::SwissTournament},
+    types::{ChessMove, Color, TimeControl},
+    utils::now_secs,
     websocket::{
         messages::ClientMessage,
         session::{Message, Session},
     },
 };
+
+mod bots;
+mod bughouse;
+mod correspondence;
+mod invites;
+mod kriegspiel;
+mod premoves;
+mod puzzles;
+mod rematch;
+mod replays;
+mod tournaments;
+
 pub struct WsChessServer<T: WsServer> {
     inner_server: T,
-    player_count: u8,
+    player_count: usize,
+    max_sessions: usize,
+    /// Cleared once the server starts shutting down
+    accepting_games: bool,
+    ratings: Arc<RatingStore>,
+    archive: Arc<GameArchive>,
+    tournaments: HashMap<String, SwissTournament>,
+    arenas: HashMap<String, ArenaTournament>,
+    bot_settings: BotSettings,
+    /// Shared with the analysis endpoints
+    engines: EnginePool,
+    /// The game each computer opponent's session is seated in
+    bots: HashMap<String, String>,
+    /// Games that ended recently enough for a rematch, by game ID
+    rematches: HashMap<String, rematch::Rematch>,
+    /// Bughouse matches, by match ID
+    bughouse: HashMap<String, bughouse::BughouseMatch>,
+    puzzles: Arc<PuzzleStore>,
+    accounts: Arc<Accounts>,
+    /// What each session solving puzzles is working on, by session ID
+    puzzle_solvers: HashMap<String, puzzles::PuzzleSolver>,
+    /// Replays being played back, by session ID
+    replays: HashMap<String, replays::Replay>,
 }
 
 impl<T: WsServer> WsChessServer<T> {
-    pub fn new(inner_server: T) -> Self {
+    pub fn new(
+        inner_server: T,
+        config: &Settings,
+        ratings: Arc<RatingStore>,
+        archive: Arc<GameArchive>,
+        puzzles: Arc<PuzzleStore>,
+        accounts: Arc<Accounts>,
+        engines: EnginePool,
+    ) -> Self {
         Self {
             inner_server,
             player_count: 0,
+            max_sessions: config.app.max_sessions,
+            accepting_games: true,
+            ratings,
+            archive,
+            tournaments: HashMap::new(),
+            arenas: HashMap::new(),
+            bot_settings: config.bot.clone(),
+            engines,
+            bots: HashMap::new(),
+            rematches: HashMap::new(),
+            bughouse: HashMap::new(),
+            puzzles,
+            accounts,
+            puzzle_solvers: HashMap::new(),
+            replays: HashMap::new(),
+        }
+    }
+
+    /// Update the ratings for a finished game, archive it and let both players know how it ended
+    fn game_over(&mut self, finished: FinishedGame) {
+        let rating_changes = self.rate(&finished);
+
+        // games that ended before an opponent showed up aren't worth keeping
+        if finished.player_ids.len() == 2 {
+            let record = GameRecord {
+                id: finished.game_id.clone(),
+                white_name: finished.white_name.clone(),
+                black_name: finished.black_name.clone(),
+                white_user: finished.white_user.clone(),
+                black_user: finished.black_user.clone(),
+                result: finished.result,
+                rated: rating_changes.is_some(),
+                time_control: finished.time_control,
+                variant: finished.variant,
+                visibility: finished.visibility,
+                rating_changes: rating_changes.clone(),
+                ended_at: now_secs(),
+                moves: finished.moves.clone(),
+                move_times: finished.move_times.clone(),
+                opening: finished.opening.clone(),
+                analysis: None,
+                mined: false,
+            };
+            if let Err(e) = self.archive.record(record) {
+                error!(error = %e, game_id = %finished.game_id, "unable to archive game");
+            }
+        }
+
+        self.allow_rematch(&finished);
+
+        let reveal = finished.variant.rules().hides_information();
+        let client_msg = serde_json::to_string(&ClientMessage {
+            m_type: Type::GameOver,
+            payload: serde_json::to_value(GameOver {
+                game_id: finished.game_id.clone(),
+                result: finished.result,
+                game_state: finished.game_state.clone(),
+                rating_changes,
+                // the players finally get to see the whole board
+                moves: reveal.then(|| finished.moves.clone()),
+                fen: reveal.then(|| finished.fen.clone()),
+            })
+            .unwrap(),
+        })
+        .expect("unable to parse game over message");
+
+        for player_id in &finished.player_ids {
+            self.inner_server
+                .send(player_id, Message(client_msg.clone()));
+        }
+
+        self.end_bughouse(&finished);
+
+        if let Some(tournament_id) = &finished.tournament_id {
+            self.tournament_game_over(tournament_id, &finished.game_id, finished.result);
+        }
+    }
+
+    fn send_to(&self, id: &str, m_type: Type, payload: serde_json::Value) {
+        let client_msg = serde_json::to_string(&ClientMessage { m_type, payload })
+            .expect("unable to parse client message");
+
+        self.inner_server.send(id, Message(client_msg));
+    }
+
+    /// Send a message to both players of a game
+    fn send_to_game(&self, game_id: &str, m_type: Type, payload: serde_json::Value) {
+        let players = [
+            self.inner_server.get_player_one(game_id),
+            self.inner_server.get_player_two(game_id),
+        ];
+        let client_msg = serde_json::to_string(&ClientMessage { m_type, payload })
+            .expect("unable to parse client message");
+
+        for player in players.into_iter().flatten() {
+            self.inner_server
+                .send(&player.id, Message(client_msg.clone()));
+        }
+    }
+
+    /// Play the move in the player's game and relay it, with its SAN and the resulting
+    /// position, to the opponent
+    fn play_move(
+        &mut self,
+        player_id: &str,
+        mut chess_move: ChessMove,
+    ) -> Result<ChessMove, &'static str> {
+        let played = chess::Move::from_client(
+            &chess_move.from,
+            &chess_move.to,
+            chess_move.promotion_piece.as_deref(),
+            chess_move.drop_piece.as_deref(),
+        )
+        .ok_or("unreadable move")
+        .and_then(|parsed| self.inner_server.record_move(player_id, parsed));
+        let played = match played {
+            Ok(played) => played,
+            Err(e) => {
+                if e == ILLEGAL_MOVE {
+                    self.announce_illegal(player_id);
+                }
+                return Err(e);
+            }
+        };
+
+        for arena in self.arenas.values_mut() {
+            arena.record_move(player_id);
+        }
+
+        let rules = played.variant.rules();
+        let mover = played.board.position.side_to_move().opposite();
+        chess_move.san = Some(played.san.clone());
+        chess_move.fen = Some(match rules.hides_information() {
+            true => rules.fen_for(&played.board, mover),
+            false => played.fen.clone(),
+        });
+        let client_msg = serde_json::to_string(&ClientMessage {
+            m_type: Type::MakeMove,
+            payload: serde_json::to_value(&chess_move).unwrap(),
+        })
+        .expect("failed to parse connect message");
+
+        match &played.finished {
+            // the opponent only hears from the referee
+            _ if played.variant == chess::VariantKind::Kriegspiel => {
+                self.announce_move(&played, &chess_move)
+            }
+            // the game is gone already, relay the last move to whoever sat in it
+            Some(finished) => {
+                for id in finished.player_ids.iter().filter(|id| *id != player_id) {
+                    let viewer = match finished.white_id.as_ref() == Some(id) {
+                        true => Color::White,
+                        false => Color::Black,
+                    };
+                    self.send_to(
+                        id,
+                        Type::MakeMove,
+                        serde_json::to_value(played.seen_by(&chess_move, viewer)).unwrap(),
+                    );
+                }
+            }
+            None => self.inner_server.make_move(&chess_move, player_id),
+        }
+        if chess_move.premove {
+            self.inner_server.send(player_id, Message(client_msg));
+        }
+
+        if let (Some(opening), Some(game_id)) =
+            (played.opening, self.inner_server.game_of(player_id))
+        {
+            debug!(eco = %opening.eco, name = %opening.name, "opening changed");
+            self.send_to_game(
+                &game_id,
+                Type::Opening,
+                serde_json::to_value(opening).unwrap(),
+            );
+        }
+
+        self.link_bughouse_move(&played.game_id, &chess_move, played.captured);
+
+        if let Some(finished) = played.finished {
+            self.game_over(finished);
+        }
+
+        Ok(chess_move)
+    }
+
+    fn rate(&self, finished: &FinishedGame) -> Option<RatingChanges> {
+        if !finished.rated {
+            return None;
+        }
+
+        let white = finished.white_user.as_deref()?;
+        let black = finished.black_user.as_deref()?;
+        let pool = Pool::for_time_control(finished.time_control.as_ref()?);
+
+        match self
+            .ratings
+            .record_game(white, black, pool, finished.result)
+        {
+            Ok(changes) => changes,
+            Err(e) => {
+                error!(error = %e, game_id = %finished.game_id, "unable to save ratings");
+                None
+            }
+        }
+    }
+
+    /// Let the opponent know about the player's draw offer
+    fn offer_draw(&self, game_id: &str, player_id: &str) {
+        let opponent = [
+            self.inner_server.get_player_one(game_id),
+            self.inner_server.get_player_two(game_id),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|player| player.id != player_id);
+
+        if let Some(opponent) = opponent {
+            self.send_to(
+                &opponent.id,
+                Type::DrawOffer,
+                serde_json::to_value(DrawOffer {
+                    game_id: game_id.to_owned(),
+                })
+                .unwrap(),
+            );
+        }
+    }
+
+    /// Refuse clocks the players couldn't play with, or too long to make sense
+    fn check_time_control(time_control: Option<&TimeControl>) -> Result<(), String> {
+        match time_control.is_some_and(|time_control| !time_control.is_valid()) {
+            true => Err(format!(
+                "Clocks start with up to {} minutes and add up to {} seconds a move",
+                TimeControl::MAX_INITIAL_SECS / 60,
+                TimeControl::MAX_INCREMENT_SECS
+            )),
+            false => Ok(()),
+        }
+    }
+
+    fn send_error(&self, id: &str, message: &str) {
+        let client_msg = serde_json::to_string(&ClientMessage {
+            m_type: Type::Error,
+            payload: serde_json::json!({ "message": message }),
+        })
+        .expect("unable to parse error message");
+
+        self.inner_server.send(id, Message(client_msg));
+    }
+
+    /// Send the same message to every connected session
+    fn broadcast(&self, m_type: Type, payload: serde_json::Value) {
+        let client_msg = serde_json::to_string(&ClientMessage { m_type, payload })
+            .expect("unable to parse broadcast message");
+
+        for id in self.inner_server.session_ids() {
+            self.inner_server.send(&id, Message(client_msg.clone()));
         }
     }
 }
 
 impl<T: WsServer> Actor for WsChessServer<T> {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_correspondence_sweep(ctx);
+        self.start_puzzle_flush(ctx);
+    }
 }
 
 impl<T: WsServer> Handler<Connect> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "connect", skip_all, fields(session_id = %msg.id))]
     fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
-        if self.player_count >= 3 {
-            println!("player count limit reached, can't connect");
+        if self.player_count >= self.max_sessions {
+            warn!("player count limit reached, can't connect");
             return;
         }
-        println!("Someone connected!");
+        info!("session connected");
 
         let id = msg.id.clone();
 
@@ -49,8 +373,11 @@ impl<T: WsServer> Handler<Connect> for WsChessServer<T> {
             Session {
                 id: id.clone(),
                 color: Color::None,
-                name: String::new(),
+                // authenticated players always go by their username
+                name: msg.user.clone().unwrap_or_default(),
+                user: msg.user.clone(),
                 addr: Clone::clone(&msg.addr),
+                close_addr: Clone::clone(&msg.close_addr),
                 joined_game: None,
             },
         );
@@ -70,37 +397,102 @@ impl<T: WsServer> Handler<Connect> for WsChessServer<T> {
 impl<T: WsServer> Handler<Disconnect> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "disconnect", skip_all, fields(session_id = %msg.id))]
     fn handle(&mut self, msg: Disconnect, _: &mut Self::Context) -> Self::Result {
-        println!("Someone disconnected!");
+        info!("session disconnected");
+        // bots never went through `Connect`
+        let was_bot = self.bots.remove(&msg.id).is_some();
+
+        if let Some(finished) = self.inner_server.delete_session(&msg.id) {
+            self.game_over(finished);
+        }
 
-        self.inner_server.delete_session(&msg.id);
+        self.leave_tournaments(&msg.id);
+        self.leave_bughouse(&msg.id);
+        self.puzzle_solvers.remove(&msg.id);
+        self.replays.remove(&msg.id);
+        self.dismiss_idle_bots();
 
-        println!("{:?}", self.inner_server);
-        self.player_count = self.player_count.saturating_sub(1);
+        if !was_bot {
+            self.player_count = self.player_count.saturating_sub(1);
+        }
     }
 }
 
 impl<T: WsServer> Handler<CreateGame> for WsChessServer<T> {
     type Result = ();
 
-    fn handle(&mut self, mut msg: CreateGame, _: &mut Self::Context) -> Self::Result {
-        if self.player_count >= 3 {
-            println!("player limit reached, cant create games");
+    #[tracing::instrument(name = "create_game", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, mut msg: CreateGame, ctx: &mut Self::Context) -> Self::Result {
+        if self.player_count >= self.max_sessions {
+            warn!("player limit reached, can't create games");
+            return;
+        }
+        if !self.accepting_games {
+            self.send_error(
+                &msg.player_id,
+                "The server is shutting down, no new games can be created",
+            );
+            return;
+        }
+        if self.is_playing(&msg.player_id) {
+            self.send_error(&msg.player_id, "You are already playing");
             return;
         }
-        println!("Creating game");
 
-        let player_id = msg.player_id.clone();
+        if let Some(days) = msg.options.days_per_move {
+            if let Err(e) = self.check_correspondence_options(&msg.player_id, days, &msg.bot) {
+                self.send_error(&msg.player_id, &e);
+                return;
+            }
+        }
+
+        if let Err(e) = Self::check_visibility_options(&msg.options) {
+            self.send_error(&msg.player_id, e);
+            return;
+        }
+
+        if let Err(e) = Self::check_time_control(msg.options.time_control.as_ref()) {
+            self.send_error(&msg.player_id, &e);
+            return;
+        }
 
-        println!("{}", player_id);
+        if msg.options.variant == chess::VariantKind::Bughouse {
+            self.send_error(
+                &msg.player_id,
+                "Bughouse games are played in matches of four, create a bughouse match instead",
+            );
+            return;
+        }
+
+        if msg.bot.is_some() && msg.options.variant != chess::VariantKind::Standard {
+            self.send_error(&msg.player_id, "Bots only play standard chess");
+            return;
+        }
+
+        if msg.bot.is_some_and(|bot| bot.engine) && self.engines.settings().path.is_empty() {
+            self.send_error(&msg.player_id, "No engine is available on this server");
+            return;
+        }
+
+        let player_id = msg.player_id.clone();
+        // the bot needs to know which side it is playing
+        if msg.bot.is_some() && msg.color == Color::None {
+            msg.color = Color::White;
+        }
 
         let id = self
             .inner_server
-            .create_game(&msg.name, &msg.player_id, msg.color)
+            .create_game(&msg.name, &msg.player_id, msg.color, msg.options.clone())
             .expect("Player Id must be present when creating game");
 
+        let _game = info_span!("game", game_id = %id).entered();
+        info!(name = %msg.name, color = ?msg.color, "game created");
+
         // attach the game id to the message to send back to the client
-        msg.id = id;
+        msg.id = id.clone();
+        msg.invite_token = self.inner_server.invite_token(&id);
+        let bot = msg.bot;
 
         let client_msg = serde_json::to_string(&ClientMessage {
             m_type: Type::CreateGame,
@@ -110,20 +502,64 @@ impl<T: WsServer> Handler<CreateGame> for WsChessServer<T> {
 
         self.inner_server
             .send(player_id.as_str(), Message(client_msg));
+
+        if let Some(bot) = bot {
+            self.seat_bot(&id, bot, ctx);
+        }
     }
 }
 
 impl<T: WsServer> Handler<JoinGame> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "join_game", skip_all, fields(session_id = %msg.player_id))]
     fn handle(&mut self, msg: JoinGame, _: &mut Self::Context) -> Self::Result {
-        if self.player_count >= 3 {
+        if self.player_count >= self.max_sessions {
+            return;
+        }
+        if !self.accepting_games {
+            self.send_error(
+                &msg.player_id,
+                "The server is shutting down, no new games can be joined",
+            );
+            return;
+        }
+        // coming back to the game the session is already seated in is fine
+        if self.is_playing(&msg.player_id)
+            && self.inner_server.game_of(&msg.player_id).as_deref() != Some(msg.game_id.as_str())
+        {
+            self.send_error(&msg.player_id, "You are already playing");
             return;
         }
-        println!("Joining game");
+
+        let _game = info_span!("game", game_id = %msg.game_id).entered();
+        info!("joining game");
 
         let player_id = msg.player_id.clone();
 
+        let Some(summary) = self.inner_server.game_summary(&msg.game_id) else {
+            self.send_error(&player_id, "No such game");
+            return;
+        };
+        if summary.days_per_move.is_some() {
+            let Some(user) = self.inner_server.session_user(&player_id) else {
+                self.send_error(&player_id, "Log in to play correspondence games");
+                return;
+            };
+            if self.resume_correspondence_game(&msg.game_id, &player_id, &user) {
+                return;
+            }
+        }
+
+        if let Err(e) = self.inner_server.check_admission(
+            &msg.game_id,
+            msg.invite_token.as_deref(),
+            msg.password.as_deref(),
+        ) {
+            self.send_error(&player_id, &format!("Unable to join: {e}"));
+            return;
+        }
+
         self.inner_server.join_game(&msg.game_id, &msg.player_id);
 
         let player_one = self.inner_server.get_player_one(&msg.game_id);
@@ -149,7 +585,7 @@ impl<T: WsServer> Handler<JoinGame> for WsChessServer<T> {
 
         if let Some(player_one_id) = player_one_id {
             self.inner_server
-                .send(&player_one_id.as_str(), Message(player_one_msg));
+                .send(player_one_id.as_str(), Message(player_one_msg));
         }
     }
 }
@@ -157,33 +593,309 @@ impl<T: WsServer> Handler<JoinGame> for WsChessServer<T> {
 impl<T: WsServer> Handler<MakeMove> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "make_move", skip_all, fields(session_id = %msg.player_id))]
     fn handle(&mut self, msg: MakeMove, _: &mut Self::Context) -> Self::Result {
         let player_id = msg.player_id.clone();
+        // the client doesn't say which game the move is for, the session's seat does
+        let game_id = self.inner_server.game_of(&player_id);
+        let _game = info_span!("game", game_id = game_id.as_deref()).entered();
 
-        let client_msg = serde_json::to_string(&ClientMessage {
-            m_type: Type::MakeMove,
-            payload: serde_json::to_value::<ChessMove>(msg.into()).unwrap(),
-        })
-        .expect("failed to parse connect message");
+        debug!(from = %msg.from, to = %msg.to, "relaying move");
+
+        if let Err(e) = self.play_move(&player_id, msg.into()) {
+            warn!(error = e, "rejected move");
+            self.send_error(&player_id, &format!("Move rejected: {e}"));
+            return;
+        }
 
-        self.inner_server.make_move(&client_msg, &player_id);
+        self.play_premoves(&player_id);
     }
 }
 
 impl<T: WsServer> Handler<UpdateGameState> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "update_game_state", skip_all, fields(session_id = %msg.player_id))]
     fn handle(&mut self, msg: UpdateGameState, _: &mut Self::Context) -> Self::Result {
-        // TODO: IMPLEMENT
-        // we want to send the client a message to update their game state, not the other way around
+        let game_id = self.inner_server.game_of(&msg.player_id);
+        let offers_draw = msg.new_status.offers_draw();
+
+        match self
+            .inner_server
+            .update_game_state(&msg.player_id, msg.new_status)
+        {
+            Ok(Some(finished)) => self.game_over(finished),
+            Ok(None) => {
+                if let Some(game_id) = game_id.filter(|_| offers_draw) {
+                    self.offer_draw(&game_id, &msg.player_id);
+                }
+            }
+            Err(e) => {
+                warn!(error = e, "rejected game state");
+                self.send_error(&msg.player_id, &format!("Unable to update the game: {e}"));
+            }
+        }
     }
 }
 
 impl<T: WsServer> Handler<UpdateName> for WsChessServer<T> {
     type Result = ();
 
+    #[tracing::instrument(name = "update_name", skip_all, fields(session_id = %msg.player_id))]
     fn handle(&mut self, msg: UpdateName, _: &mut Self::Context) -> Self::Result {
+        debug!(name = %msg.name, "updating session name");
+        let guest = self.inner_server.session_user(&msg.player_id).is_none();
+        if guest && self.accounts.is_registered(&msg.name) {
+            self.send_error(&msg.player_id, "This name belongs to a registered player");
+            return;
+        }
         self.inner_server
             .update_session_name(&msg.player_id, &msg.name);
     }
 }
+
+impl<T: WsServer> Handler<BeginShutdown> for WsChessServer<T> {
+    type Result = ();
+
+    fn handle(&mut self, msg: BeginShutdown, _: &mut Self::Context) -> Self::Result {
+        info!(
+            deadline_secs = msg.deadline_secs,
+            "refusing new games, notifying sessions"
+        );
+        self.accepting_games = false;
+
+        self.broadcast(Type::Maintenance, serde_json::to_value(msg).unwrap());
+    }
+}
+
+impl<T: WsServer> Handler<ActiveGames> for WsChessServer<T> {
+    type Result = usize;
+
+    fn handle(&mut self, _: ActiveGames, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.active_game_count()
+    }
+}
+
+impl<T: WsServer> Handler<SaveSnapshot> for WsChessServer<T> {
+    type Result = std::io::Result<()>;
+
+    fn handle(&mut self, msg: SaveSnapshot, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.save_snapshot(&msg.path)
+    }
+}
+
+impl<T: WsServer> Handler<ListSessions> for WsChessServer<T> {
+    type Result = Vec<SessionSummary>;
+
+    fn handle(&mut self, _: ListSessions, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.session_summaries()
+    }
+}
+
+impl<T: WsServer> Handler<KickSession> for WsChessServer<T> {
+    type Result = bool;
+
+    fn handle(&mut self, msg: KickSession, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.kick_session(&msg.id, &msg.reason)
+    }
+}
+
+impl<T: WsServer> Handler<ListGames> for WsChessServer<T> {
+    type Result = Vec<GameSummary>;
+
+    fn handle(&mut self, _: ListGames, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.game_summaries()
+    }
+}
+
+impl<T: WsServer> Handler<GetGame> for WsChessServer<T> {
+    type Result = Option<GameSummary>;
+
+    fn handle(&mut self, msg: GetGame, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.game_summary(&msg.id)
+    }
+}
+
+impl<T: WsServer> Handler<TerminateGame> for WsChessServer<T> {
+    type Result = bool;
+
+    #[tracing::instrument(name = "terminate_game", skip_all, fields(game_id = %msg.id))]
+    fn handle(&mut self, msg: TerminateGame, _: &mut Self::Context) -> Self::Result {
+        match self.inner_server.terminate_game(&msg.id, msg.result) {
+            Some(finished) => {
+                self.game_over(finished);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T: WsServer> Handler<Announcement> for WsChessServer<T> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Announcement, _: &mut Self::Context) -> Self::Result {
+        info!(message = %msg.message, "broadcasting announcement");
+
+        self.broadcast(Type::Announcement, serde_json::to_value(msg).unwrap());
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::{GameOptions, GameResult};
+    use crate::websocket::servers::in_memory::InMemoryServer;
+    use crate::websocket::session::Close;
+    use std::path::Path;
+
+    /// Stands in for a session's websocket, whatever is sent to it is dropped
+    struct Inbox;
+
+    impl Actor for Inbox {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Message> for Inbox {
+        type Result = ();
+
+        fn handle(&mut self, _: Message, _: &mut Context<Self>) {}
+    }
+
+    impl Handler<Close> for Inbox {
+        type Result = ();
+
+        fn handle(&mut self, _: Close, _: &mut Context<Self>) {}
+    }
+
+    /// A server keeping its stores in a directory of its own
+    pub(crate) fn test_server() -> WsChessServer<InMemoryServer> {
+        let dir = std::env::temp_dir().join(format!("chess-backend-{}", nanoid::nanoid!(10)));
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        let config: Settings = config::Config::builder()
+            .add_source(config::File::from(Path::new(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/configs/base.yaml"
+            ))))
+            .set_override("auth.accounts_path", path("accounts.json"))
+            .and_then(|builder| builder.set_override("ratings.ratings_path", path("ratings.json")))
+            .and_then(|builder| builder.set_override("archive.games_path", path("games.jsonl")))
+            .and_then(|builder| builder.set_override("puzzles.puzzles_path", path("puzzles.json")))
+            .and_then(|builder| {
+                builder.set_override("puzzles.attempts_path", path("puzzle_attempts.json"))
+            })
+            .and_then(|builder| builder.build())
+            .and_then(|config| config.try_deserialize())
+            .unwrap();
+
+        WsChessServer::new(
+            InMemoryServer::default(),
+            &config,
+            Arc::new(RatingStore::open(&config.ratings).unwrap()),
+            Arc::new(GameArchive::open(&config.archive).unwrap()),
+            Arc::new(PuzzleStore::open(&config.puzzles).unwrap()),
+            Arc::new(Accounts::open(&config.auth).unwrap()),
+            EnginePool::new(config.engine.clone()),
+        )
+    }
+
+    /// Connect a session, signed in when it has a user, which needs a running actix system
+    pub(super) fn connect(
+        server: &mut WsChessServer<InMemoryServer>,
+        id: &str,
+        user: Option<&str>,
+    ) {
+        let inbox = Inbox.start();
+        let connect = Connect::new(
+            id.to_owned(),
+            user.map(str::to_owned),
+            inbox.clone().recipient(),
+            inbox.recipient(),
+        );
+        server.handle(connect, &mut Context::new());
+    }
+
+    /// A move the way a client sends it, in UCI notation
+    pub(super) fn client_move(uci: &str) -> ChessMove {
+        serde_json::from_value(serde_json::json!({
+            "from": &uci[0..2],
+            "to": &uci[2..4],
+            "promotion_piece": uci.get(4..).filter(|piece| !piece.is_empty()),
+        }))
+        .unwrap()
+    }
+
+    fn create_game(id: &str, options: GameOptions) -> CreateGame {
+        CreateGame {
+            id: String::new(),
+            player_id: id.to_owned(),
+            name: "game".to_owned(),
+            color: Color::White,
+            options,
+            bot: None,
+            invite_token: None,
+        }
+    }
+
+    #[actix::test]
+    async fn out_of_range_time_controls_are_refused() {
+        let mut server = test_server();
+        connect(&mut server, "a", None);
+
+        for (initial_secs, increment_secs) in [(0, 0), (60, u32::MAX), (u32::MAX, 0)] {
+            let options = GameOptions {
+                time_control: Some(TimeControl {
+                    initial_secs,
+                    increment_secs,
+                }),
+                ..GameOptions::default()
+            };
+            server.handle(create_game("a", options), &mut Context::new());
+        }
+        assert!(server.inner_server.game_summaries().is_empty());
+
+        let options = GameOptions {
+            time_control: Some(TimeControl {
+                initial_secs: 300,
+                increment_secs: 3,
+            }),
+            ..GameOptions::default()
+        };
+        server.handle(create_game("a", options), &mut Context::new());
+        assert_eq!(server.inner_server.game_summaries().len(), 1);
+    }
+
+    #[actix::test]
+    async fn leaving_a_rated_game_forfeits_it() {
+        let mut server = test_server();
+        connect(&mut server, "a", Some("alice"));
+        connect(&mut server, "b", Some("bob"));
+        let options = GameOptions {
+            rated: true,
+            time_control: Some(TimeControl {
+                initial_secs: 300,
+                increment_secs: 0,
+            }),
+            ..GameOptions::default()
+        };
+        let game_id = server
+            .inner_server
+            .create_game("game", "a", Color::White, options)
+            .unwrap();
+        server.inner_server.join_game(&game_id, "b");
+        server.play_move("a", client_move("e2e4")).unwrap();
+        server.play_move("b", client_move("e7e5")).unwrap();
+
+        server.handle(Disconnect::new("b".to_owned()), &mut Context::new());
+
+        assert!(server.inner_server.get_game(&game_id).is_none());
+        let record = server.archive.get(&game_id).unwrap();
+        assert_eq!(record.result, GameResult::WhiteWins);
+        assert!(record.rated && record.rating_changes.is_some());
+        let (alice, bob) = (
+            server.ratings.get("alice", Pool::Blitz),
+            server.ratings.get("bob", Pool::Blitz),
+        );
+        assert!(alice.rating > bob.rating);
+    }
+}
@@ -0,0 +1,70 @@
+//! Seats computer opponents in games: each bot gets a session of its own whose messages
+//! go to a `BotActor`, so moves flow through the same relay as between two clients.
+
+use actix::{Actor, AsyncContext, Context};
+use nanoid::nanoid;
+use tracing::info;
+
+use super::WsChessServer;
+use crate::{
+    bot::{BotActor, BotOptions},
+    types::Color,
+    websocket::{messages::Type, servers::WsServer, session::Session},
+};
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Start a bot and seat it as the second player of the game
+    pub(super) fn seat_bot(&mut self, game_id: &str, options: BotOptions, ctx: &mut Context<Self>) {
+        let bot_id = nanoid!(10);
+        let bot = BotActor::new(
+            bot_id.clone(),
+            ctx.address(),
+            options,
+            self.bot_settings.clone(),
+            options.engine.then(|| self.engines.clone()),
+        )
+        .start();
+
+        self.inner_server.create_session(
+            &bot_id,
+            Session {
+                id: bot_id.clone(),
+                addr: bot.clone().recipient(),
+                close_addr: bot.recipient(),
+                name: options.name(),
+                user: None,
+                joined_game: None,
+                color: Color::None,
+            },
+        );
+        self.inner_server.join_game(game_id, &bot_id);
+        self.bots.insert(bot_id.clone(), game_id.to_owned());
+        info!(%game_id, bot_id = %bot_id, skill = options.skill(), "bot joined game");
+
+        let human = self.inner_server.get_player_one(game_id);
+        let computer = self.inner_server.get_player_two(game_id);
+        if let Some(human) = &human {
+            self.send_to(
+                &human.id,
+                Type::OpponentJoined,
+                serde_json::to_value(&computer).unwrap(),
+            );
+        }
+        // the bot learns its color the same way a client does
+        self.send_to(
+            &bot_id,
+            Type::OpponentJoined,
+            serde_json::to_value(&human).unwrap(),
+        );
+    }
+
+    /// Send away the bots whose game was abandoned by their opponent
+    pub(super) fn dismiss_idle_bots(&mut self) {
+        for (bot_id, game_id) in &self.bots {
+            if self.inner_server.get_game(game_id).is_none() {
+                self.inner_server
+                    .kick_session(bot_id, "The game was abandoned");
+            }
+        }
+    }
+}
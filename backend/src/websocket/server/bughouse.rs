@@ -0,0 +1,478 @@
+//! Bughouse is played by two teams of two on two crazyhouse boards at once. Whatever a
+//! player captures goes to their partner's pocket on the other board, and the first
+//! board to finish decides the match for both.
+
+use actix::{Context, Handler};
+use nanoid::nanoid;
+use tracing::{info, warn};
+
+use super::WsChessServer;
+use crate::{
+    chess::{Piece, VariantKind},
+    types::{ChessMove, Color, GameOptions, GameResult, TimeControl, Visibility},
+    websocket::{
+        messages::{
+            BughouseBoard, BughouseSeats, BughouseStarted, CreateBughouse, JoinBughouse, TeamChat,
+            Type,
+        },
+        servers::{in_memory::FinishedGame, WsServer},
+    },
+};
+
+/// Longest team chat message accepted, in characters
+const MAX_CHAT_LENGTH: usize = 500;
+
+/// Four players on two linked boards
+pub(super) struct BughouseMatch {
+    name: String,
+    time_control: Option<TimeControl>,
+    /// Session IDs by seat: white and black on the first board, then on the second
+    seats: [Option<String>; 4],
+    /// The game of each board once every seat is taken
+    games: Option<[String; 2]>,
+}
+
+impl BughouseMatch {
+    fn seat_of(&self, player_id: &str) -> Option<usize> {
+        self.seats
+            .iter()
+            .position(|seat| seat.as_deref() == Some(player_id))
+    }
+
+    /// Partners play opposite colors on different boards
+    fn partner_of(&self, player_id: &str) -> Option<&str> {
+        let seat = self.seat_of(player_id)?;
+        self.seats[3 - seat].as_deref()
+    }
+
+    /// The game being played on the other board than this one
+    fn other_board(&self, game_id: &str) -> Option<&str> {
+        let [first, second] = self.games.as_ref()?;
+        match game_id {
+            id if id == first => Some(second),
+            id if id == second => Some(first),
+            _ => None,
+        }
+    }
+
+    fn player_ids(&self) -> impl Iterator<Item = &str> {
+        self.seats.iter().flatten().map(String::as_str)
+    }
+}
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Whether the session is seated in a game or waiting in a bughouse match
+    pub(super) fn is_playing(&self, id: &str) -> bool {
+        !self.inner_server.is_available(id)
+            || self
+                .bughouse
+                .values()
+                .any(|bughouse| bughouse.seat_of(id).is_some())
+    }
+
+    /// The ID of the match the game is one of the boards of
+    fn bughouse_match_of(&self, game_id: &str) -> Option<String> {
+        self.bughouse
+            .iter()
+            .find(|(_, bughouse)| bughouse.other_board(game_id).is_some())
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Show the move to the players of the other board and hand the captured piece,
+    /// if any, to the mover's partner
+    pub(super) fn link_bughouse_move(
+        &mut self,
+        game_id: &str,
+        chess_move: &ChessMove,
+        captured: Option<Piece>,
+    ) {
+        let Some(bughouse) = self
+            .bughouse_match_of(game_id)
+            .and_then(|id| self.bughouse.get(&id))
+        else {
+            return;
+        };
+        let Some(other_game_id) = bughouse.other_board(game_id).map(str::to_owned) else {
+            return;
+        };
+        let player_ids: Vec<String> = bughouse.player_ids().map(str::to_owned).collect();
+
+        let board = BughouseBoard {
+            game_id: game_id.to_owned(),
+            fen: chess_move.fen.clone().unwrap_or_default(),
+            san: chess_move.san.clone(),
+        };
+        self.send_to_game(
+            &other_game_id,
+            Type::BughouseBoard,
+            serde_json::to_value(board).unwrap(),
+        );
+
+        // the captured piece changes color, which is the partner's color on their board
+        let Some(piece) = captured else {
+            return;
+        };
+        if let Some(fen) = self.inner_server.add_to_pocket(&other_game_id, piece) {
+            let board = serde_json::to_value(BughouseBoard {
+                game_id: other_game_id,
+                fen,
+                san: None,
+            })
+            .unwrap();
+            for id in &player_ids {
+                self.send_to(id, Type::BughouseBoard, board.clone());
+            }
+        }
+    }
+
+    /// End the other board of a finished bughouse game with the same result for each team
+    pub(super) fn end_bughouse(&mut self, finished: &FinishedGame) {
+        if finished.variant != VariantKind::Bughouse {
+            return;
+        }
+        let Some(bughouse) = self
+            .bughouse_match_of(&finished.game_id)
+            .and_then(|id| self.bughouse.remove(&id))
+        else {
+            return;
+        };
+        let Some(other_game_id) = bughouse.other_board(&finished.game_id) else {
+            return;
+        };
+
+        // the winner's partner plays the other color on the other board
+        let result = match finished.result {
+            GameResult::WhiteWins => GameResult::BlackWins,
+            GameResult::BlackWins => GameResult::WhiteWins,
+            result => result,
+        };
+        info!(game_id = %finished.game_id, ?result, "bughouse match over");
+
+        if let Some(other) = self
+            .inner_server
+            .conclude_linked_game(other_game_id, result)
+        {
+            self.game_over(other);
+        }
+    }
+
+    /// Take the player out of the matches that haven't started yet
+    pub(super) fn leave_bughouse(&mut self, player_id: &str) {
+        let left: Vec<String> = self
+            .bughouse
+            .iter_mut()
+            .filter(|(_, bughouse)| bughouse.games.is_none())
+            .filter_map(|(id, bughouse)| {
+                let seat = bughouse.seat_of(player_id)?;
+                bughouse.seats[seat] = None;
+                Some(id.clone())
+            })
+            .collect();
+
+        for id in left {
+            if self.bughouse[&id].player_ids().next().is_none() {
+                info!(match_id = %id, "empty bughouse match removed");
+                self.bughouse.remove(&id);
+            } else {
+                self.send_seats(&id);
+            }
+        }
+    }
+
+    fn send_seats(&self, match_id: &str) {
+        let Some(bughouse) = self.bughouse.get(match_id) else {
+            return;
+        };
+        let summaries = self.inner_server.session_summaries();
+        let name_of = |id: &String| {
+            summaries
+                .iter()
+                .find(|session| &session.id == id)
+                .map(|session| session.name.clone())
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| "Anonymous".to_string())
+        };
+
+        let seats = BughouseSeats {
+            match_id: match_id.to_owned(),
+            name: bughouse.name.clone(),
+            seats: bughouse
+                .seats
+                .iter()
+                .map(|id| id.as_ref().map(name_of))
+                .collect(),
+        };
+        let payload = serde_json::to_value(seats).unwrap();
+        for id in bughouse.player_ids() {
+            self.send_to(id, Type::BughouseSeats, payload.clone());
+        }
+    }
+
+    /// Create both boards once every seat is taken and tell each player where they play
+    fn start_bughouse(&mut self, match_id: &str) -> Result<(), &'static str> {
+        let bughouse = self
+            .bughouse
+            .get(match_id)
+            .ok_or("no such bughouse match")?;
+        let [Some(a_white), Some(a_black), Some(b_white), Some(b_black)] = bughouse.seats.clone()
+        else {
+            return Ok(());
+        };
+        let options = GameOptions {
+            time_control: bughouse.time_control,
+            variant: VariantKind::Bughouse,
+            // both seats are taken straight away, nobody else can join
+            visibility: Visibility::Unlisted,
+            ..GameOptions::default()
+        };
+        let name = bughouse.name.clone();
+
+        let first = self
+            .inner_server
+            .create_paired_game(&name, &a_white, &a_black, options.clone())
+            .ok_or("a player is no longer available")?;
+        let Some(second) = self
+            .inner_server
+            .create_paired_game(&name, &b_white, &b_black, options)
+        else {
+            self.inner_server.delete_game(&first);
+            return Err("a player is no longer available");
+        };
+        info!(%match_id, first_game_id = %first, second_game_id = %second, "bughouse match started");
+
+        if let Some(bughouse) = self.bughouse.get_mut(match_id) {
+            bughouse.games = Some([first.clone(), second.clone()]);
+        }
+
+        let white_a = self.inner_server.get_player_one(&first);
+        let black_a = self.inner_server.get_player_two(&first);
+        let white_b = self.inner_server.get_player_one(&second);
+        let black_b = self.inner_server.get_player_two(&second);
+        for (id, game_id, color, partner_game_id, partner, opponent) in [
+            (&a_white, &first, Color::White, &second, &black_b, &black_a),
+            (&a_black, &first, Color::Black, &second, &white_b, &white_a),
+            (&b_white, &second, Color::White, &first, &black_a, &black_b),
+            (&b_black, &second, Color::Black, &first, &white_a, &white_b),
+        ] {
+            let started = BughouseStarted {
+                match_id: match_id.to_owned(),
+                game_id: game_id.clone(),
+                color,
+                partner_game_id: partner_game_id.clone(),
+                partner: partner.clone(),
+            };
+            self.send_to(
+                id,
+                Type::BughouseStarted,
+                serde_json::to_value(started).unwrap(),
+            );
+            self.send_to(
+                id,
+                Type::OpponentJoined,
+                serde_json::to_value(opponent).unwrap(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: WsServer> Handler<CreateBughouse> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "create_bughouse", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, mut msg: CreateBughouse, _: &mut Context<Self>) -> Self::Result {
+        if !self.accepting_games {
+            self.send_error(
+                &msg.player_id,
+                "The server is shutting down, no new games can be created",
+            );
+            return;
+        }
+        if self.is_playing(&msg.player_id) {
+            self.send_error(&msg.player_id, "You are already playing");
+            return;
+        }
+        if let Err(e) = Self::check_time_control(msg.time_control.as_ref()) {
+            self.send_error(&msg.player_id, &e);
+            return;
+        }
+
+        let id = nanoid!(10);
+        info!(match_id = %id, name = %msg.name, "bughouse match created");
+        self.bughouse.insert(
+            id.clone(),
+            BughouseMatch {
+                name: msg.name.clone(),
+                time_control: msg.time_control,
+                seats: [Some(msg.player_id.clone()), None, None, None],
+                games: None,
+            },
+        );
+
+        msg.id = id.clone();
+        self.send_to(
+            &msg.player_id,
+            Type::CreateBughouse,
+            serde_json::to_value(&msg).unwrap(),
+        );
+        self.send_seats(&id);
+    }
+}
+
+impl<T: WsServer> Handler<JoinBughouse> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "join_bughouse", skip_all, fields(session_id = %msg.player_id, match_id = %msg.match_id))]
+    fn handle(&mut self, msg: JoinBughouse, _: &mut Context<Self>) -> Self::Result {
+        if !self.accepting_games {
+            self.send_error(
+                &msg.player_id,
+                "The server is shutting down, no new games can be joined",
+            );
+            return;
+        }
+        if self.is_playing(&msg.player_id) {
+            self.send_error(&msg.player_id, "You are already playing");
+            return;
+        }
+
+        let Some(bughouse) = self.bughouse.get_mut(&msg.match_id) else {
+            self.send_error(&msg.player_id, "No such bughouse match");
+            return;
+        };
+        let seat = match msg.seat {
+            Some(seat) => Some(seat).filter(|&seat| bughouse.seats.get(seat) == Some(&None)),
+            None => bughouse.seats.iter().position(Option::is_none),
+        };
+        let Some(seat) = seat else {
+            self.send_error(&msg.player_id, "Unable to join: that seat is taken");
+            return;
+        };
+        bughouse.seats[seat] = Some(msg.player_id.clone());
+        info!(seat, "joined bughouse match");
+
+        self.send_seats(&msg.match_id);
+        if let Err(e) = self.start_bughouse(&msg.match_id) {
+            warn!(error = e, "unable to start bughouse match");
+            self.send_error(&msg.player_id, &format!("Unable to start the match: {e}"));
+        }
+    }
+}
+
+impl<T: WsServer> Handler<TeamChat> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "team_chat", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, mut msg: TeamChat, _: &mut Context<Self>) -> Self::Result {
+        let message = msg.message.trim();
+        if message.is_empty() || message.chars().count() > MAX_CHAT_LENGTH {
+            self.send_error(
+                &msg.player_id,
+                &format!("Messages have to be between 1 and {MAX_CHAT_LENGTH} characters"),
+            );
+            return;
+        }
+
+        let Some(partner_id) = self
+            .bughouse
+            .values()
+            .find_map(|bughouse| bughouse.partner_of(&msg.player_id))
+            .map(str::to_owned)
+        else {
+            self.send_error(&msg.player_id, "You don't have a partner to talk to");
+            return;
+        };
+
+        msg.message = message.to_owned();
+        msg.from = self
+            .inner_server
+            .session_summaries()
+            .into_iter()
+            .find(|session| session.id == msg.player_id)
+            .map(|session| session.name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "Anonymous".to_string());
+
+        let payload = serde_json::to_value(&msg).unwrap();
+        // echoed back so the sender sees the message in order with their partner's
+        self.send_to(&partner_id, Type::TeamChat, payload.clone());
+        self.send_to(&msg.player_id, Type::TeamChat, payload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::PieceKind;
+    use crate::websocket::server::tests::{client_move, connect, test_server};
+    use crate::websocket::servers::in_memory::InMemoryServer;
+
+    /// A started match with a and b on the first board, c and d on the second
+    fn started_match() -> (WsChessServer<InMemoryServer>, [String; 2]) {
+        let mut server = test_server();
+        for id in ["a", "b", "c", "d"] {
+            connect(&mut server, id, None);
+        }
+        server.bughouse.insert(
+            "m".to_owned(),
+            BughouseMatch {
+                name: "m".to_owned(),
+                time_control: None,
+                seats: ["a", "b", "c", "d"].map(|id| Some(id.to_owned())),
+                games: None,
+            },
+        );
+        server.start_bughouse("m").unwrap();
+
+        let games = server.bughouse["m"].games.clone().unwrap();
+        (server, games)
+    }
+
+    #[actix::test]
+    async fn captures_go_to_the_partners_pocket() {
+        let (mut server, [first, second]) = started_match();
+
+        for (player, uci) in [("a", "e2e4"), ("b", "d7d5"), ("a", "e4d5")] {
+            server.play_move(player, client_move(uci)).unwrap();
+        }
+
+        // a's partner d plays black on the other board and gets the black pawn
+        let pockets = |game_id: &str| {
+            server
+                .inner_server
+                .get_game(game_id)
+                .unwrap()
+                .board
+                .variant_state
+                .pockets
+        };
+        assert_eq!(pockets(&second)[1].count(PieceKind::Pawn), 1);
+        assert_eq!(pockets(&second)[0].count(PieceKind::Pawn), 0);
+        assert_eq!(pockets(&first)[0].count(PieceKind::Pawn), 0);
+        assert!(server
+            .inner_server
+            .board_view(&second, Color::Black)
+            .unwrap()
+            .contains("[p]"));
+    }
+
+    #[actix::test]
+    async fn one_board_decides_the_other() {
+        let (mut server, [first, second]) = started_match();
+
+        let finished = server
+            .inner_server
+            .terminate_game(&first, GameResult::WhiteWins)
+            .unwrap();
+        server.game_over(finished);
+
+        // a won with white, so d, a's partner, wins with black on the other board
+        assert!(server.inner_server.get_game(&second).is_none());
+        assert_eq!(
+            server.archive.get(&second).map(|record| record.result),
+            Some(GameResult::BlackWins)
+        );
+        assert!(server.bughouse.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Correspondence games belong to accounts rather than sessions: players come and go,
+//! moves can be sent over HTTP, and a periodic sweep ends the games whose side to move
+//! let its deadline pass.
+
+use actix::{AsyncContext, Context, Handler};
+use std::time::Duration;
+use tracing::info;
+
+use super::WsChessServer;
+use crate::{
+    bot::BotOptions,
+    types::ChessMove,
+    utils::now_secs,
+    websocket::{
+        messages::{ListCorrespondenceGames, SubmitMove, Type},
+        servers::{in_memory::CorrespondenceGame, WsServer},
+    },
+};
+
+/// The longest a side can be given to make a move
+const MAX_DAYS_PER_MOVE: u32 = 14;
+/// How often deadlines are checked, a game ends at most this late
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Check a correspondence game can be created with these options
+    pub(super) fn check_correspondence_options(
+        &self,
+        player_id: &str,
+        days_per_move: u32,
+        bot: &Option<BotOptions>,
+    ) -> Result<(), String> {
+        if !(1..=MAX_DAYS_PER_MOVE).contains(&days_per_move) {
+            return Err(format!(
+                "Correspondence games allow between 1 and {MAX_DAYS_PER_MOVE} days per move"
+            ));
+        }
+        if bot.is_some() {
+            return Err("Correspondence games can't be played against the computer".to_owned());
+        }
+        if self.inner_server.session_user(player_id).is_none() {
+            return Err("Log in to play correspondence games".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Seat the account back in its correspondence game and send it the game so far
+    ///
+    /// Returns false if the account has no seat in the game yet.
+    pub(super) fn resume_correspondence_game(
+        &mut self,
+        game_id: &str,
+        player_id: &str,
+        user: &str,
+    ) -> bool {
+        if self.inner_server.seat_of_user(game_id, user).is_none() {
+            return false;
+        }
+
+        self.inner_server.join_game(game_id, player_id);
+        if let Some(game) = self.inner_server.correspondence_game(game_id, user) {
+            self.send_to(
+                player_id,
+                Type::ResumeGame,
+                serde_json::to_value(game).unwrap(),
+            );
+        }
+
+        true
+    }
+
+    pub(super) fn start_correspondence_sweep(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(SWEEP_INTERVAL, |act, _| {
+            for finished in act.inner_server.expire_correspondence_games(now_secs()) {
+                act.game_over(finished);
+            }
+        });
+    }
+}
+
+impl<T: WsServer> Handler<SubmitMove> for WsChessServer<T> {
+    type Result = Result<ChessMove, String>;
+
+    #[tracing::instrument(name = "submit_move", skip_all, fields(game_id = %msg.game_id, user = %msg.user))]
+    fn handle(&mut self, msg: SubmitMove, _: &mut Self::Context) -> Self::Result {
+        let seat_id = self
+            .inner_server
+            .seat_of_user(&msg.game_id, &msg.user)
+            .ok_or("You aren't playing this correspondence game")?;
+
+        let played = self
+            .play_move(&seat_id, msg.chess_move)
+            .map_err(|e| format!("Move rejected: {e}"))?;
+        info!(
+            san = played.san.as_deref().unwrap_or_default(),
+            "move submitted"
+        );
+
+        // a websocket the player left open sees the move as well
+        self.send_to(
+            &seat_id,
+            Type::MakeMove,
+            serde_json::to_value(&played).unwrap(),
+        );
+        self.play_premoves(&seat_id);
+
+        Ok(played)
+    }
+}
+
+impl<T: WsServer> Handler<ListCorrespondenceGames> for WsChessServer<T> {
+    type Result = Vec<CorrespondenceGame>;
+
+    fn handle(&mut self, msg: ListCorrespondenceGames, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.correspondence_games(&msg.user)
+    }
+}
@@ -0,0 +1,148 @@
+//! Public games are listed in the lobby, unlisted ones can only be joined by id and
+//! private ones also need the invite token their creator shares or their password.
+
+use actix::{Context, Handler};
+
+use super::WsChessServer;
+use crate::{
+    types::{GameOptions, Visibility},
+    websocket::{
+        messages::{GetInvitation, ListOpenGames},
+        servers::{in_memory::OpenGame, WsServer},
+    },
+};
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Check the visibility and password chosen for a new game go together
+    pub(super) fn check_visibility_options(options: &GameOptions) -> Result<(), &'static str> {
+        let has_password = options
+            .password
+            .as_deref()
+            .is_some_and(|password| !password.is_empty());
+        if has_password && options.visibility != Visibility::Private {
+            return Err("Only private games can have a password");
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: WsServer> Handler<ListOpenGames> for WsChessServer<T> {
+    type Result = Vec<OpenGame>;
+
+    fn handle(&mut self, _: ListOpenGames, _: &mut Context<Self>) -> Self::Result {
+        self.inner_server.open_games()
+    }
+}
+
+impl<T: WsServer> Handler<GetInvitation> for WsChessServer<T> {
+    type Result = Option<OpenGame>;
+
+    fn handle(&mut self, msg: GetInvitation, _: &mut Context<Self>) -> Self::Result {
+        let game_id = self.inner_server.invited_game(&msg.token)?;
+        self.inner_server.open_game(&game_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Color;
+    use crate::websocket::messages::JoinGame;
+    use crate::websocket::server::tests::{connect, test_server};
+    use crate::websocket::servers::in_memory::InMemoryServer;
+
+    fn create(
+        server: &mut WsChessServer<InMemoryServer>,
+        host: &str,
+        visibility: Visibility,
+    ) -> String {
+        let options = GameOptions {
+            visibility,
+            password: (visibility == Visibility::Private).then(|| "secret".to_owned()),
+            ..GameOptions::default()
+        };
+        server
+            .inner_server
+            .create_game("game", host, Color::White, options)
+            .unwrap()
+    }
+
+    fn join(
+        game_id: &str,
+        player_id: &str,
+        invite_token: Option<&str>,
+        password: Option<&str>,
+    ) -> JoinGame {
+        JoinGame {
+            game_id: game_id.to_owned(),
+            invite_token: invite_token.map(str::to_owned),
+            password: password.map(str::to_owned),
+            player_id: player_id.to_owned(),
+        }
+    }
+
+    #[actix::test]
+    async fn private_games_need_their_invite_token_or_password() {
+        let mut server = test_server();
+        for id in ["a", "b", "c", "d"] {
+            connect(&mut server, id, None);
+        }
+        let first = create(&mut server, "a", Visibility::Private);
+        let token = server.inner_server.invite_token(&first).unwrap();
+
+        assert!(server
+            .inner_server
+            .check_admission(&first, None, None)
+            .is_err());
+        assert!(server
+            .inner_server
+            .check_admission(&first, Some("not-the-token"), Some("wrong"))
+            .is_err());
+
+        server.handle(join(&first, "b", None, None), &mut Context::new());
+        server.handle(
+            join(&first, "b", Some("not-the-token"), None),
+            &mut Context::new(),
+        );
+        server.handle(join(&first, "b", None, Some("wrong")), &mut Context::new());
+        assert!(server.inner_server.game_of("b").is_none());
+
+        server.handle(join(&first, "b", Some(&token), None), &mut Context::new());
+        assert_eq!(server.inner_server.game_of("b"), Some(first));
+
+        let second = create(&mut server, "c", Visibility::Private);
+        server.handle(
+            join(&second, "d", None, Some("secret")),
+            &mut Context::new(),
+        );
+        assert_eq!(server.inner_server.game_of("d"), Some(second));
+    }
+
+    #[actix::test]
+    async fn only_public_games_are_listed() {
+        let mut server = test_server();
+        for id in ["a", "b", "c"] {
+            connect(&mut server, id, None);
+        }
+        let public = create(&mut server, "a", Visibility::Public);
+        create(&mut server, "b", Visibility::Unlisted);
+        let private = create(&mut server, "c", Visibility::Private);
+
+        let listed = server.handle(ListOpenGames, &mut Context::new());
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, public);
+
+        // the invite token still finds the private game
+        let token = server.inner_server.invite_token(&private).unwrap();
+        let invitation = server.handle(GetInvitation { token }, &mut Context::new());
+        assert_eq!(invitation.map(|game| game.id), Some(private));
+        let invitation = server.handle(
+            GetInvitation {
+                token: "not-the-token".to_owned(),
+            },
+            &mut Context::new(),
+        );
+        assert!(invitation.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Kriegspiel players only see their own pieces. Instead of the opponent's moves they
+//! get the referee's announcements: whether an attempt was legal, where something was
+//! captured and where checks come from.
+
+use tracing::debug;
+
+use super::WsChessServer;
+use crate::{
+    chess::{Kriegspiel, PieceKind, VariantKind},
+    types::{ChessMove, Color},
+    websocket::{
+        messages::{CaptureAnnouncement, RefereeAnnouncement, Type},
+        servers::{in_memory::PlayedMove, WsServer},
+    },
+};
+
+impl<T: WsServer> WsChessServer<T> {
+    /// The players of the game with their colors, taken from the finished game when the
+    /// move ended it
+    fn seated_players(&self, played: &PlayedMove) -> Vec<(String, Color)> {
+        match &played.finished {
+            Some(finished) => [
+                (finished.white_id.clone(), Color::White),
+                (finished.black_id.clone(), Color::Black),
+            ]
+            .into_iter()
+            .filter_map(|(id, color)| Some((id?, color)))
+            .collect(),
+            None => [
+                self.inner_server.get_player_one(&played.game_id),
+                self.inner_server.get_player_two(&played.game_id),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|player| (player.id, player.color))
+            .collect(),
+        }
+    }
+
+    /// Announce a legal move to both players, each with what they can see of the board
+    pub(super) fn announce_move(&self, played: &PlayedMove, chess_move: &ChessMove) {
+        let rules = played.variant.rules();
+        let color = played.board.position.side_to_move().opposite();
+        let capture = played.captured.map(|piece| CaptureAnnouncement {
+            square: chess_move.to.clone(),
+            pawn: piece.kind == PieceKind::Pawn,
+        });
+        let checks = Kriegspiel::checks(&played.board.position);
+        debug!(?color, ?capture, ?checks, "referee announced a move");
+
+        for (id, viewer) in self.seated_players(played) {
+            let announcement = RefereeAnnouncement {
+                game_id: played.game_id.clone(),
+                color,
+                legal: true,
+                capture: capture.as_ref().map(|capture| CaptureAnnouncement {
+                    square: capture.square.clone(),
+                    pawn: capture.pawn,
+                }),
+                checks: checks.clone(),
+                fen: rules.fen_for(&played.board, viewer),
+            };
+            self.send_to(
+                &id,
+                Type::Referee,
+                serde_json::to_value(announcement).unwrap(),
+            );
+        }
+    }
+
+    /// Tell both players of a Kriegspiel game that the player tried an illegal move,
+    /// which move it was stays secret
+    pub(super) fn announce_illegal(&self, player_id: &str) {
+        let Some(game_id) = self.inner_server.game_of(player_id) else {
+            return;
+        };
+        if self
+            .inner_server
+            .game_summary(&game_id)
+            .is_none_or(|game| game.variant != VariantKind::Kriegspiel)
+        {
+            return;
+        }
+
+        let players = [
+            self.inner_server.get_player_one(&game_id),
+            self.inner_server.get_player_two(&game_id),
+        ];
+        let Some(color) = players
+            .iter()
+            .flatten()
+            .find(|player| player.id == player_id)
+            .map(|player| player.color)
+        else {
+            return;
+        };
+
+        for player in players.into_iter().flatten() {
+            let Some(fen) = self.inner_server.board_view(&game_id, player.color) else {
+                continue;
+            };
+            let announcement = RefereeAnnouncement {
+                game_id: game_id.clone(),
+                color,
+                legal: false,
+                capture: None,
+                checks: Vec::new(),
+                fen,
+            };
+            self.send_to(
+                &player.id,
+                Type::Referee,
+                serde_json::to_value(announcement).unwrap(),
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Premoves are queued on the game and played by the server the moment the opponent
+//! has moved, so they cost no round-trip and no clock time.
+
+use actix::Handler;
+use tracing::{debug, info};
+
+use super::WsChessServer;
+use crate::{
+    chess,
+    types::ChessMove,
+    websocket::{
+        messages::{CancelPremoves, Premove, PremoveQueue, Type},
+        servers::WsServer,
+    },
+};
+
+impl<T: WsServer> WsChessServer<T> {
+    fn send_premove_queue(&self, player_id: &str, queued: usize, discarded: Option<String>) {
+        self.send_to(
+            player_id,
+            Type::Premove,
+            serde_json::to_value(PremoveQueue { queued, discarded }).unwrap(),
+        );
+    }
+
+    /// Play the queued premoves of whoever is to move in the player's game, until a
+    /// queue runs dry or a premove turns out to be illegal
+    pub(super) fn play_premoves(&mut self, player_id: &str) {
+        let Some(game_id) = self.inner_server.game_of(player_id) else {
+            return;
+        };
+
+        while let Some((premover_id, premove)) = self.inner_server.next_premove(&game_id) {
+            let mut chess_move: ChessMove = premove.into();
+            chess_move.premove = true;
+
+            match self.play_move(&premover_id, chess_move) {
+                Ok(_) => {
+                    debug!(%game_id, premove = %premove, "played premove");
+                }
+                Err(e) => {
+                    info!(%game_id, premove = %premove, error = e, "discarding premoves");
+                    self.inner_server.cancel_premoves(&premover_id);
+                    self.send_premove_queue(
+                        &premover_id,
+                        0,
+                        Some(format!("Premove {premove} discarded: {e}")),
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<T: WsServer> Handler<Premove> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "premove", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: Premove, _: &mut Self::Context) -> Self::Result {
+        let queued = chess::Move::from_client(
+            &msg.from,
+            &msg.to,
+            msg.promotion_piece.as_deref(),
+            msg.drop_piece.as_deref(),
+        )
+        .ok_or("unreadable move")
+        .and_then(|premove| self.inner_server.queue_premove(&msg.player_id, premove));
+
+        match queued {
+            Ok(queued) => {
+                debug!(from = %msg.from, to = %msg.to, queued, "queued premove");
+                self.send_premove_queue(&msg.player_id, queued, None);
+            }
+            Err(e) => self.send_error(&msg.player_id, &format!("Premove rejected: {e}")),
+        }
+    }
+}
+
+impl<T: WsServer> Handler<CancelPremoves> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "cancel_premoves", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: CancelPremoves, _: &mut Self::Context) -> Self::Result {
+        self.inner_server.cancel_premoves(&msg.player_id);
+        self.send_premove_queue(&msg.player_id, 0, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Color, GameOptions};
+    use crate::websocket::messages::MakeMove;
+    use crate::websocket::server::tests::{client_move, connect, test_server};
+    use crate::websocket::servers::in_memory::InMemoryServer;
+    use actix::Context;
+
+    fn make_move(player_id: &str, uci: &str) -> MakeMove {
+        let chess_move = client_move(uci);
+        MakeMove {
+            from: chess_move.from,
+            to: chess_move.to,
+            promotion_piece: chess_move.promotion_piece,
+            drop_piece: None,
+            game_id: String::new(),
+            player_id: player_id.to_owned(),
+        }
+    }
+
+    fn premove(player_id: &str, uci: &str) -> Premove {
+        let chess_move = client_move(uci);
+        Premove {
+            from: chess_move.from,
+            to: chess_move.to,
+            promotion_piece: chess_move.promotion_piece,
+            drop_piece: None,
+            player_id: player_id.to_owned(),
+        }
+    }
+
+    /// A game between white "a" and black "b", along with its ID
+    fn started_game() -> (WsChessServer<InMemoryServer>, String) {
+        let mut server = test_server();
+        connect(&mut server, "a", None);
+        connect(&mut server, "b", None);
+        let id = server
+            .inner_server
+            .create_game("game", "a", Color::White, GameOptions::default())
+            .unwrap();
+        server.inner_server.join_game(&id, "b");
+
+        (server, id)
+    }
+
+    fn moves(server: &WsChessServer<InMemoryServer>, game_id: &str) -> Vec<String> {
+        server.inner_server.get_game(game_id).unwrap().moves.clone()
+    }
+
+    #[actix::test]
+    async fn premoves_are_played_when_the_turn_comes() {
+        let (mut server, id) = started_game();
+
+        server.handle(premove("b", "e7e5"), &mut Context::new());
+        server.handle(premove("b", "g8f6"), &mut Context::new());
+        server.handle(make_move("a", "e2e4"), &mut Context::new());
+        assert_eq!(moves(&server, &id), ["e2e4", "e7e5"]);
+
+        // the second premove waits for white's next move
+        server.handle(make_move("a", "g1f3"), &mut Context::new());
+        assert_eq!(moves(&server, &id), ["e2e4", "e7e5", "g1f3", "g8f6"]);
+        assert!(server.inner_server.next_premove(&id).is_none());
+    }
+
+    #[actix::test]
+    async fn illegal_premoves_are_dropped() {
+        let (mut server, id) = started_game();
+        server.handle(make_move("a", "e2e4"), &mut Context::new());
+
+        // black blocks the pawn, so neither of white's premoves gets played
+        server.handle(premove("a", "e4e5"), &mut Context::new());
+        server.handle(premove("a", "d2d4"), &mut Context::new());
+        server.handle(make_move("b", "e7e5"), &mut Context::new());
+        assert_eq!(moves(&server, &id), ["e2e4", "e7e5"]);
+        assert!(server.inner_server.next_premove(&id).is_none());
+
+        // white is left to move normally
+        server.handle(make_move("a", "d2d4"), &mut Context::new());
+        assert_eq!(moves(&server, &id), ["e2e4", "e7e5", "d2d4"]);
+    }
+
+    #[actix::test]
+    async fn cancelling_clears_the_queue() {
+        let (mut server, id) = started_game();
+
+        server.handle(premove("b", "e7e5"), &mut Context::new());
+        server.handle(premove("b", "g8f6"), &mut Context::new());
+        server.handle(
+            CancelPremoves {
+                player_id: "b".to_owned(),
+            },
+            &mut Context::new(),
+        );
+        server.handle(make_move("a", "e2e4"), &mut Context::new());
+
+        assert_eq!(moves(&server, &id), ["e2e4"]);
+        assert!(server.inner_server.next_premove(&id).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+//! Tactics puzzles. The solver gets the position after the opponent's setup move and
+//! plays the rest of the line move by move, the server answers each correct move with
+//! the opponent's reply. A wrong move ends the attempt. Signed in players are rated on
+//! their first attempt at each puzzle only, and the puzzle is rated against them.
+
+use actix::{AsyncContext, Context, Handler};
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::WsChessServer;
+use crate::{
+    chess::{Move, Outcome, Position},
+    puzzles::Puzzle,
+    rating::{Pool, RatingChange},
+    websocket::{
+        messages::{PuzzleFeedback, PuzzleMove, PuzzlePosition, RequestPuzzle, Type},
+        servers::WsServer,
+    },
+};
+
+/// How often puzzle ratings and attempts are written, at most this much is lost in a crash
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The puzzles a session has seen and the one it is working on
+#[derive(Default)]
+pub(super) struct PuzzleSolver {
+    seen: Vec<String>,
+    attempt: Option<Attempt>,
+}
+
+struct Attempt {
+    puzzle: Puzzle,
+    position: Position,
+    /// Index of the move of the line the solver has to find next
+    ply: usize,
+    /// Whether this is the account's first attempt at the puzzle
+    rated: bool,
+}
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Write the puzzle ratings and attempts that changed every so often, on a blocking
+    /// thread rather than this actor's
+    pub(super) fn start_puzzle_flush(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(FLUSH_INTERVAL, |act, _| {
+            let puzzles = act.puzzles.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = puzzles.flush() {
+                    error!(error = %e, "unable to save puzzles");
+                }
+            });
+        });
+    }
+
+    /// The puzzle asked for, or one close to the player's puzzle rating they haven't
+    /// seen or attempted yet, starting over once they have seen them all
+    fn choose_puzzle(&mut self, player_id: &str, puzzle_id: Option<&str>) -> Option<Puzzle> {
+        if let Some(id) = puzzle_id {
+            return self.puzzles.get(id);
+        }
+
+        let user = self.inner_server.session_user(player_id);
+        let rating = user
+            .as_ref()
+            .map(|user| self.ratings.get(user, Pool::Puzzle))
+            .unwrap_or_default()
+            .rating;
+        let solver = self.puzzle_solvers.entry(player_id.to_owned()).or_default();
+
+        self.puzzles
+            .pick(rating, &solver.seen, user.as_deref())
+            .or_else(|| {
+                solver.seen.clear();
+                self.puzzles.pick(rating, &solver.seen, None)
+            })
+    }
+
+    /// Whether the player's attempt at the puzzle counts, only an account's first one does
+    fn is_rated_attempt(&self, player_id: &str, puzzle: &Puzzle) -> bool {
+        self.inner_server
+            .session_user(player_id)
+            .is_some_and(|user| self.puzzles.start_attempt(&puzzle.id, &user))
+    }
+
+    /// Rate a finished attempt, guests and repeated attempts aren't rated
+    fn rate_attempt(
+        &self,
+        player_id: &str,
+        attempt: &Attempt,
+        solved: bool,
+    ) -> Option<RatingChange> {
+        if !attempt.rated {
+            return None;
+        }
+        let puzzle = &attempt.puzzle;
+        let user = self.inner_server.session_user(player_id)?;
+        let (change, before) = match self.ratings.record_puzzle(&user, &puzzle.rating, solved) {
+            Ok(rated) => rated,
+            Err(e) => {
+                error!(error = %e, puzzle_id = %puzzle.id, "unable to save puzzle rating");
+                return None;
+            }
+        };
+        self.puzzles.record_attempt(&puzzle.id, &before, solved);
+
+        Some(change)
+    }
+}
+
+impl<T: WsServer> Handler<RequestPuzzle> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "request_puzzle", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: RequestPuzzle, _: &mut Self::Context) -> Self::Result {
+        let Some(puzzle) = self.choose_puzzle(&msg.player_id, msg.puzzle_id.as_deref()) else {
+            self.send_error(&msg.player_id, "No such puzzle is available");
+            return;
+        };
+        let position = match puzzle.position_after(1) {
+            Ok(position) => position,
+            Err(e) => {
+                error!(error = %e, puzzle_id = %puzzle.id, "unable to set up puzzle");
+                self.send_error(&msg.player_id, "Unable to set up the puzzle");
+                return;
+            }
+        };
+        let rated = self.is_rated_attempt(&msg.player_id, &puzzle);
+        info!(puzzle_id = %puzzle.id, rated, "puzzle started");
+
+        let payload = PuzzlePosition {
+            puzzle_id: puzzle.id.clone(),
+            fen: position.to_fen(),
+            last_move: puzzle.moves[0].clone(),
+            color: position.side_to_move(),
+            rating: puzzle.rating.rating.round() as i32,
+            themes: puzzle.themes.clone(),
+            game_id: puzzle.game_id.clone(),
+        };
+        self.puzzle_solvers
+            .entry(msg.player_id.clone())
+            .or_default()
+            .attempt = Some(Attempt {
+            puzzle,
+            position,
+            ply: 1,
+            rated,
+        });
+
+        self.send_to(
+            &msg.player_id,
+            Type::Puzzle,
+            serde_json::to_value(payload).unwrap(),
+        );
+    }
+}
+
+impl<T: WsServer> Handler<PuzzleMove> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "puzzle_move", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: PuzzleMove, _: &mut Self::Context) -> Self::Result {
+        let Some(attempt) = self
+            .puzzle_solvers
+            .get_mut(&msg.player_id)
+            .and_then(|solver| solver.attempt.as_mut())
+        else {
+            self.send_error(&msg.player_id, "You aren't solving a puzzle");
+            return;
+        };
+        let Some(mv) = Move::from_parts(&msg.from, &msg.to, msg.promotion_piece.as_deref())
+            .filter(|&mv| attempt.position.is_legal(mv))
+        else {
+            self.send_error(&msg.player_id, "Unable to play the move: illegal move");
+            return;
+        };
+
+        let moves = &attempt.puzzle.moves;
+        let next = attempt.position.play(mv);
+        // any move that mates solves the puzzle, even one the line doesn't have
+        let mates = next.outcome() == Some(Outcome::Checkmate);
+        let correct = mates || moves[attempt.ply] == mv.uci();
+        attempt.position = next;
+        attempt.ply += 1;
+
+        let mut reply = None;
+        if correct && !mates && attempt.ply < moves.len() {
+            let answer =
+                Move::from_uci(&moves[attempt.ply]).filter(|&mv| attempt.position.is_legal(mv));
+            if let Some(answer) = answer {
+                attempt.position = attempt.position.play(answer);
+                attempt.ply += 1;
+                reply = Some(answer.uci());
+            }
+        }
+
+        let solved = correct && (mates || attempt.ply >= moves.len());
+        let solution = (!correct).then(|| moves[1..].to_vec());
+        let fen = attempt.position.to_fen();
+        let puzzle_id = attempt.puzzle.id.clone();
+
+        let mut rating_change = None;
+        if solved || !correct {
+            let solver = self.puzzle_solvers.get_mut(&msg.player_id).unwrap();
+            let attempt = solver.attempt.take().unwrap();
+            solver.seen.push(puzzle_id.clone());
+            info!(puzzle_id = %puzzle_id, solved, "puzzle attempt over");
+            rating_change = self.rate_attempt(&msg.player_id, &attempt, solved);
+        }
+
+        let feedback = PuzzleFeedback {
+            puzzle_id,
+            correct,
+            solved,
+            reply,
+            fen,
+            solution,
+            rating_change,
+        };
+        self.send_to(
+            &msg.player_id,
+            Type::PuzzleFeedback,
+            serde_json::to_value(feedback).unwrap(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rating::Rating;
+    use crate::websocket::server::tests::{connect, test_server};
+    use crate::websocket::servers::in_memory::InMemoryServer;
+
+    fn mate_in_one(id: &str) -> Puzzle {
+        Puzzle {
+            id: id.to_owned(),
+            fen: "6k1/p4ppp/8/8/8/8/5PPP/1R4K1 b - - 0 1".to_owned(),
+            moves: vec!["a7a6".to_owned(), "b1b8".to_owned()],
+            rating: Rating::default(),
+            themes: Vec::new(),
+            plays: 0,
+            game_id: None,
+        }
+    }
+
+    fn solve(server: &mut WsChessServer<InMemoryServer>, player_id: &str, puzzle_id: &str) {
+        let player_id = player_id.to_owned();
+        server.handle(
+            RequestPuzzle {
+                puzzle_id: Some(puzzle_id.to_owned()),
+                player_id: player_id.clone(),
+            },
+            &mut Context::new(),
+        );
+        server.handle(
+            PuzzleMove {
+                from: "b1".to_owned(),
+                to: "b8".to_owned(),
+                promotion_piece: None,
+                player_id,
+            },
+            &mut Context::new(),
+        );
+    }
+
+    #[actix::test]
+    async fn only_the_first_attempt_is_rated() {
+        let mut server = test_server();
+        connect(&mut server, "first", Some("alice"));
+        connect(&mut server, "second", Some("alice"));
+        server.puzzles.insert(mate_in_one("p")).unwrap();
+
+        solve(&mut server, "first", "p");
+        let rated = server.ratings.get("alice", Pool::Puzzle);
+        assert!(rated.rating > Rating::default().rating);
+
+        // neither the same session nor another one of the account is rated again
+        solve(&mut server, "first", "p");
+        solve(&mut server, "second", "p");
+        let after = server.ratings.get("alice", Pool::Puzzle);
+        assert_eq!(
+            (after.rating, after.deviation),
+            (rated.rating, rated.deviation)
+        );
+        assert_eq!(server.puzzles.get("p").unwrap().plays, 1);
+    }
+
+    #[actix::test]
+    async fn attempted_puzzles_are_not_offered_again() {
+        let mut server = test_server();
+        connect(&mut server, "first", Some("alice"));
+        connect(&mut server, "second", Some("alice"));
+        server.puzzles.insert(mate_in_one("p")).unwrap();
+        server.puzzles.insert(mate_in_one("q")).unwrap();
+
+        solve(&mut server, "first", "p");
+        for _ in 0..10 {
+            let puzzle = server.choose_puzzle("second", None).unwrap();
+            assert_eq!(puzzle.id, "q");
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Once a game is over either player can offer a rematch for a short while. When the
+//! other accepts, the same two sessions play again with the same options and colors
+//! swapped.
+
+use actix::Handler;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use super::WsChessServer;
+use crate::{
+    chess::VariantKind,
+    types::{Color, GameOptions},
+    websocket::{
+        messages::{AcceptRematch, DeclineRematch, OfferRematch, RematchStarted, Type},
+        servers::{in_memory::FinishedGame, WsServer},
+    },
+};
+
+/// How long after a game ends a rematch can be offered and accepted
+const REMATCH_WINDOW: Duration = Duration::from_secs(60);
+
+/// A finished game the players can play again
+pub(super) struct Rematch {
+    name: String,
+    white_id: String,
+    black_id: String,
+    options: GameOptions,
+    ended_at: Instant,
+    /// The player waiting for an answer
+    offered_by: Option<String>,
+}
+
+impl Rematch {
+    fn opponent_of(&self, player_id: &str) -> Option<&str> {
+        if self.white_id == player_id {
+            Some(&self.black_id)
+        } else if self.black_id == player_id {
+            Some(&self.white_id)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Keep what is needed to replay the game, tournament, bughouse and computer games
+    /// excluded
+    pub(super) fn allow_rematch(&mut self, finished: &FinishedGame) {
+        self.rematches
+            .retain(|_, rematch| rematch.ended_at.elapsed() < REMATCH_WINDOW);
+
+        let (Some(white_id), Some(black_id)) = (&finished.white_id, &finished.black_id) else {
+            return;
+        };
+        if finished.tournament_id.is_some()
+            || finished.variant == VariantKind::Bughouse
+            || self.bots.contains_key(white_id)
+            || self.bots.contains_key(black_id)
+        {
+            return;
+        }
+
+        self.rematches.insert(
+            finished.game_id.clone(),
+            Rematch {
+                name: finished.name.clone(),
+                white_id: white_id.clone(),
+                black_id: black_id.clone(),
+                options: finished.options(),
+                ended_at: Instant::now(),
+                offered_by: None,
+            },
+        );
+    }
+
+    /// The rematch of the game and the player's opponent, if the player can still ask for it
+    fn open_rematch(
+        &mut self,
+        game_id: &str,
+        player_id: &str,
+    ) -> Result<(&mut Rematch, String), &'static str> {
+        let rematch = self
+            .rematches
+            .get_mut(game_id)
+            .filter(|rematch| rematch.ended_at.elapsed() < REMATCH_WINDOW)
+            .ok_or("the rematch window has closed")?;
+        let opponent_id = rematch
+            .opponent_of(player_id)
+            .ok_or("you didn't play this game")?
+            .to_owned();
+
+        Ok((rematch, opponent_id))
+    }
+
+    /// Start the new game with colors swapped and tell both players
+    fn start_rematch(&mut self, game_id: &str) -> Result<(), &'static str> {
+        let rematch = self
+            .rematches
+            .remove(game_id)
+            .ok_or("no rematch to start")?;
+        let new_game_id = self
+            .inner_server
+            .create_paired_game(
+                &rematch.name,
+                &rematch.black_id,
+                &rematch.white_id,
+                rematch.options,
+            )
+            .ok_or("your opponent is no longer available")?;
+        info!(previous_game_id = %game_id, game_id = %new_game_id, "rematch started");
+
+        let white_player = self.inner_server.get_player_one(&new_game_id);
+        let black_player = self.inner_server.get_player_two(&new_game_id);
+        for (id, color, opponent) in [
+            (&rematch.black_id, Color::White, black_player),
+            (&rematch.white_id, Color::Black, white_player),
+        ] {
+            let started = RematchStarted {
+                previous_game_id: game_id.to_owned(),
+                game_id: new_game_id.clone(),
+                color,
+            };
+            self.send_to(
+                id,
+                Type::AcceptRematch,
+                serde_json::to_value(started).unwrap(),
+            );
+            self.send_to(
+                id,
+                Type::OpponentJoined,
+                serde_json::to_value(opponent).unwrap(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: WsServer> Handler<OfferRematch> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "offer_rematch", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: OfferRematch, _: &mut Self::Context) -> Self::Result {
+        let opponent_id = match self.open_rematch(&msg.game_id, &msg.player_id) {
+            Ok((rematch, opponent_id)) => {
+                // offering back is the same as accepting
+                if rematch.offered_by.as_deref() == Some(opponent_id.as_str()) {
+                    if let Err(e) = self.start_rematch(&msg.game_id) {
+                        self.send_error(&msg.player_id, &format!("Unable to rematch: {e}"));
+                    }
+                    return;
+                }
+                rematch.offered_by = Some(msg.player_id.clone());
+                opponent_id
+            }
+            Err(e) => {
+                self.send_error(&msg.player_id, &format!("Unable to offer a rematch: {e}"));
+                return;
+            }
+        };
+
+        info!(game_id = %msg.game_id, "rematch offered");
+        self.send_to(
+            &opponent_id,
+            Type::OfferRematch,
+            serde_json::to_value(&msg).unwrap(),
+        );
+    }
+}
+
+impl<T: WsServer> Handler<AcceptRematch> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "accept_rematch", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: AcceptRematch, _: &mut Self::Context) -> Self::Result {
+        let offered =
+            self.open_rematch(&msg.game_id, &msg.player_id)
+                .and_then(|(rematch, opponent_id)| {
+                    match rematch.offered_by.as_deref() == Some(opponent_id.as_str()) {
+                        true => Ok(()),
+                        false => Err("no rematch was offered"),
+                    }
+                });
+
+        if let Err(e) = offered.and_then(|_| self.start_rematch(&msg.game_id)) {
+            self.send_error(&msg.player_id, &format!("Unable to rematch: {e}"));
+        }
+    }
+}
+
+impl<T: WsServer> Handler<DeclineRematch> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "decline_rematch", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: DeclineRematch, _: &mut Self::Context) -> Self::Result {
+        let Ok((rematch, opponent_id)) = self.open_rematch(&msg.game_id, &msg.player_id) else {
+            return;
+        };
+        let was_offered = rematch.offered_by.as_deref() == Some(opponent_id.as_str());
+        self.rematches.remove(&msg.game_id);
+
+        if was_offered {
+            info!(game_id = %msg.game_id, "rematch declined");
+            self.send_to(
+                &opponent_id,
+                Type::DeclineRematch,
+                serde_json::to_value(&msg).unwrap(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{GameResult, TimeControl, Visibility};
+    use crate::websocket::server::tests::{client_move, connect, test_server};
+    use actix::Context;
+
+    #[actix::test]
+    async fn rematches_keep_the_options_and_swap_colors() {
+        let mut server = test_server();
+        connect(&mut server, "a", None);
+        connect(&mut server, "b", None);
+        let options = GameOptions {
+            time_control: Some(TimeControl {
+                initial_secs: 300,
+                increment_secs: 2,
+            }),
+            visibility: Visibility::Private,
+            password: Some("secret".to_owned()),
+            ..GameOptions::default()
+        };
+        let game_id = server
+            .inner_server
+            .create_game("game", "a", Color::White, options)
+            .unwrap();
+        server.inner_server.join_game(&game_id, "b");
+        server.play_move("a", client_move("e2e4")).unwrap();
+        let finished = server
+            .inner_server
+            .terminate_game(&game_id, GameResult::Draw)
+            .unwrap();
+        server.game_over(finished);
+
+        // there is nothing to accept before an offer
+        server.handle(
+            AcceptRematch {
+                game_id: game_id.clone(),
+                player_id: "a".to_owned(),
+            },
+            &mut Context::new(),
+        );
+        assert!(server.inner_server.game_of("a").is_none());
+
+        server.handle(
+            OfferRematch {
+                game_id: game_id.clone(),
+                player_id: "b".to_owned(),
+            },
+            &mut Context::new(),
+        );
+        server.handle(
+            AcceptRematch {
+                game_id: game_id.clone(),
+                player_id: "a".to_owned(),
+            },
+            &mut Context::new(),
+        );
+
+        let rematch_id = server.inner_server.game_of("a").unwrap();
+        assert_ne!(rematch_id, game_id);
+        assert_eq!(server.inner_server.game_of("b"), Some(rematch_id.clone()));
+        let rematch = server.inner_server.get_game(&rematch_id).unwrap();
+        assert_eq!(rematch.player_one_id, "b");
+        assert_eq!(rematch.player_one_color, Color::White);
+        assert_eq!(rematch.player_two_id.as_deref(), Some("a"));
+        assert_eq!(rematch.visibility, Visibility::Private);
+        assert_eq!(rematch.password.as_deref(), Some("secret"));
+        assert_eq!(rematch.time_control.map(|tc| tc.increment_secs), Some(2));
+        assert!(server.rematches.is_empty());
+    }
+}
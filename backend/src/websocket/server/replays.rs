@@ -0,0 +1,197 @@
+//! Replays play a stored game back to a session move by move, at the pace it was
+//! played or sped up. Finished games come from the archive, ongoing ones from the
+//! server's own record of them.
+
+use actix::{AsyncContext, Handler, SpawnHandle};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::info;
+
+use super::WsChessServer;
+use crate::{
+    replay::{self, ReplayPosition, StoredGame},
+    websocket::{
+        messages::{GetStoredGame, ReplayEnd, ReplayMove, StartReplay, StopReplay, Type},
+        servers::WsServer,
+    },
+};
+
+/// A replay being played back to a session
+pub(super) struct Replay {
+    game_id: String,
+    live: bool,
+    /// Positions still to send, each with how long to wait before sending it
+    frames: VecDeque<(Duration, ReplayPosition)>,
+    timer: Option<SpawnHandle>,
+}
+
+impl<T: WsServer> WsChessServer<T> {
+    /// The game if anyone may look at it, private games stay hidden once archived as
+    /// they are while being played
+    fn stored_game(&self, game_id: &str) -> Option<StoredGame> {
+        if let Some(record) = self.archive.get(game_id) {
+            return record.visible_to(None).then(|| StoredGame::from(record));
+        }
+
+        self.inner_server.stored_game(game_id)
+    }
+
+    /// Send the session's next replay position once it is due, or end the replay
+    fn schedule_replay_frame(
+        &mut self,
+        player_id: String,
+        ctx: &mut <Self as actix::Actor>::Context,
+    ) {
+        let Some(replay) = self.replays.get_mut(&player_id) else {
+            return;
+        };
+        let Some(&(delay, _)) = replay.frames.front() else {
+            let replay = self.replays.remove(&player_id).unwrap();
+            let end = ReplayEnd {
+                game_id: replay.game_id,
+                live: replay.live,
+            };
+            self.send_to(
+                &player_id,
+                Type::ReplayEnd,
+                serde_json::to_value(end).unwrap(),
+            );
+            return;
+        };
+
+        replay.timer = Some(ctx.run_later(delay, move |act, ctx| {
+            let Some(replay) = act.replays.get_mut(&player_id) else {
+                return;
+            };
+            let Some((_, position)) = replay.frames.pop_front() else {
+                return;
+            };
+            let frame = ReplayMove {
+                game_id: replay.game_id.clone(),
+                position,
+            };
+            act.send_to(
+                &player_id,
+                Type::ReplayMove,
+                serde_json::to_value(frame).unwrap(),
+            );
+            act.schedule_replay_frame(player_id, ctx);
+        }));
+    }
+
+    fn stop_replay(&mut self, player_id: &str, ctx: &mut <Self as actix::Actor>::Context) {
+        if let Some(timer) = self
+            .replays
+            .remove(player_id)
+            .and_then(|replay| replay.timer)
+        {
+            ctx.cancel_future(timer);
+        }
+    }
+}
+
+impl<T: WsServer> Handler<StartReplay> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "start_replay", skip_all, fields(session_id = %msg.player_id, game_id = %msg.game_id))]
+    fn handle(&mut self, msg: StartReplay, ctx: &mut Self::Context) -> Self::Result {
+        let Some(game) = self.stored_game(&msg.game_id) else {
+            self.send_error(&msg.player_id, "Unable to replay the game: game not found");
+            return;
+        };
+        if msg.from_ply > game.moves.len() {
+            self.send_error(
+                &msg.player_id,
+                "Unable to replay the game: the game doesn't have that many moves",
+            );
+            return;
+        }
+        let positions = match game.positions() {
+            Ok(positions) => positions,
+            Err(e) => {
+                self.send_error(&msg.player_id, &format!("Unable to replay the game: {e}"));
+                return;
+            }
+        };
+
+        let speed = replay::speed(msg.speed);
+        let delays = game.move_delays(speed);
+        // the position the replay starts from comes right away
+        let frames = positions
+            .into_iter()
+            .skip(msg.from_ply)
+            .map(|position| match position.ply == msg.from_ply {
+                true => (Duration::ZERO, position),
+                false => (delays[position.ply - 1], position),
+            })
+            .collect();
+        info!(speed, from_ply = msg.from_ply, "replay started");
+
+        self.stop_replay(&msg.player_id, ctx);
+        self.replays.insert(
+            msg.player_id.clone(),
+            Replay {
+                game_id: msg.game_id,
+                live: game.live,
+                frames,
+                timer: None,
+            },
+        );
+        self.schedule_replay_frame(msg.player_id, ctx);
+    }
+}
+
+impl<T: WsServer> Handler<StopReplay> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "stop_replay", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: StopReplay, ctx: &mut Self::Context) -> Self::Result {
+        self.stop_replay(&msg.player_id, ctx);
+    }
+}
+
+impl<T: WsServer> Handler<GetStoredGame> for WsChessServer<T> {
+    type Result = Option<StoredGame>;
+
+    fn handle(&mut self, msg: GetStoredGame, _: &mut Self::Context) -> Self::Result {
+        self.stored_game(&msg.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Color, GameOptions, GameResult, Visibility};
+    use crate::websocket::server::tests::{client_move, connect, test_server};
+
+    #[actix::test]
+    async fn finished_private_games_stay_hidden() {
+        let mut server = test_server();
+        connect(&mut server, "a", None);
+        connect(&mut server, "b", None);
+
+        for visibility in [Visibility::Unlisted, Visibility::Private] {
+            let options = GameOptions {
+                visibility,
+                ..GameOptions::default()
+            };
+            let id = server
+                .inner_server
+                .create_game("game", "a", Color::White, options)
+                .unwrap();
+            server.inner_server.join_game(&id, "b");
+            server.play_move("a", client_move("e2e4")).unwrap();
+            let finished = server
+                .inner_server
+                .terminate_game(&id, GameResult::WhiteWins)
+                .unwrap();
+            server.game_over(finished);
+
+            assert!(server.archive.get(&id).is_some());
+            assert_eq!(
+                server.stored_game(&id).is_some(),
+                visibility != Visibility::Private
+            );
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//! Runs Swiss tournaments and arenas on top of the game server: players are seated in
+//! games the server creates itself and results flow back in through `game_over`.
+
+use actix::{AsyncContext, Handler};
+use std::time::Duration;
+use tracing::{debug, info, info_span, warn};
+
+use super::WsChessServer;
+use crate::{
+    rating::{Pool, Rating},
+    tournament::{
+        arena::ArenaTournament, swiss::SwissTournament, Participant, TournamentFormat,
+        TournamentStatus,
+    },
+    types::{Color, GameOptions, GameResult},
+    utils::now_secs,
+    websocket::{
+        messages::{
+            ArenaStandings, Berserk, Berserked, CreateTournament, JoinTournament, StartTournament,
+            TournamentPairing, TournamentStandings, Type,
+        },
+        servers::WsServer,
+    },
+};
+
+/// Keeps tournaments to a length that can be finished in one sitting
+const MAX_TOURNAMENT_ROUNDS: u32 = 15;
+const MAX_ARENA_MINUTES: u32 = 180;
+/// How often arena players get the standings pushed while the arena runs
+const ARENA_FEED_INTERVAL: Duration = Duration::from_secs(5);
+
+impl<T: WsServer> WsChessServer<T> {
+    /// Seat two tournament players in a new game and tell both who they play.
+    ///
+    /// Returns None if either player can't play right now.
+    fn seat_tournament_game(
+        &mut self,
+        tournament_id: &str,
+        round: Option<usize>,
+        name: &str,
+        white_id: &str,
+        black_id: &str,
+        options: GameOptions,
+    ) -> Option<String> {
+        let game_id = self
+            .inner_server
+            .create_paired_game(name, white_id, black_id, options)?;
+        info!(%tournament_id, %game_id, white = %white_id, black = %black_id, "paired");
+
+        let white_player = self.inner_server.get_player_one(&game_id);
+        let black_player = self.inner_server.get_player_two(&game_id);
+        for (id, color, opponent) in [
+            (white_id, Color::White, black_player),
+            (black_id, Color::Black, white_player),
+        ] {
+            let pairing = TournamentPairing {
+                player_id: id.to_owned(),
+                tournament_id: tournament_id.to_owned(),
+                round,
+                game_id: Some(game_id.clone()),
+                color: Some(color),
+                opponent: opponent.clone(),
+            };
+            self.send_to(
+                id,
+                Type::TournamentPairing,
+                serde_json::to_value(pairing).unwrap(),
+            );
+            // lets the client start the game the same way a joined game starts
+            self.send_to(
+                id,
+                Type::OpponentJoined,
+                serde_json::to_value(opponent).unwrap(),
+            );
+        }
+
+        Some(game_id)
+    }
+
+    /// Feed the result of a tournament game back into its tournament
+    pub(super) fn tournament_game_over(
+        &mut self,
+        tournament_id: &str,
+        game_id: &str,
+        result: GameResult,
+    ) {
+        let recorded = self
+            .tournaments
+            .get_mut(tournament_id)
+            .is_some_and(|t| t.record_result(game_id, result));
+        if recorded {
+            self.send_standings(tournament_id);
+            self.advance_tournament(tournament_id);
+            return;
+        }
+
+        // results of arena games that outlast the arena don't count
+        let recorded = self
+            .arenas
+            .get_mut(tournament_id)
+            .is_some_and(|arena| arena.record_result(game_id, result));
+        if recorded {
+            self.pair_arena(tournament_id);
+            self.send_arena_standings(tournament_id);
+        }
+    }
+
+    /// Withdraw a disconnected session from every tournament it plays in
+    pub(super) fn leave_tournaments(&mut self, id: &str) {
+        // tournaments that never started are dropped along with their creator
+        let mut cancelled: Vec<(String, Vec<String>)> = Vec::new();
+        self.tournaments.retain(|tournament_id, t| {
+            let cancel = t.status == TournamentStatus::Created && t.creator_id == id;
+            if cancel {
+                let players = t.players.iter().map(|p| p.id.clone()).collect();
+                cancelled.push((tournament_id.clone(), players));
+            }
+            !cancel
+        });
+        self.arenas.retain(|tournament_id, arena| {
+            let cancel = arena.status == TournamentStatus::Created && arena.creator_id == id;
+            if cancel {
+                let players = arena.players.iter().map(|p| p.id.clone()).collect();
+                cancelled.push((tournament_id.clone(), players));
+            }
+            !cancel
+        });
+
+        for (tournament_id, players) in cancelled {
+            info!(%tournament_id, "creator left, cancelling tournament");
+            for player_id in players.iter().filter(|p| p.as_str() != id) {
+                self.send_error(player_id, "The tournament was cancelled by its creator");
+            }
+        }
+
+        for tournament in self.tournaments.values_mut() {
+            tournament.withdraw(id);
+        }
+        for arena in self.arenas.values_mut() {
+            arena.withdraw(id);
+        }
+    }
+
+    /// Pair the next round of a tournament and seat every pairing in a new game
+    fn start_round(&mut self, tournament_id: &str) {
+        let Some(tournament) = self.tournaments.get_mut(tournament_id) else {
+            return;
+        };
+
+        // players who disconnected since the last round can't be paired anymore
+        for player in tournament.players.iter_mut() {
+            if self.inner_server.get_session(&player.id).is_none() {
+                player.withdrawn = true;
+            }
+        }
+
+        tournament.pair_next_round();
+
+        let round = tournament.current_round();
+        let name = format!("{} - round {}", tournament.name, round);
+        let mut options = tournament.options.clone();
+        options.tournament_id = Some(tournament_id.to_owned());
+
+        let pairings: Vec<(String, Option<String>)> = tournament.rounds[round - 1]
+            .iter()
+            .map(|pairing| {
+                let id_of = |index: usize| tournament.players[index].id.clone();
+                (id_of(pairing.white), pairing.black.map(id_of))
+            })
+            .collect();
+
+        let _tournament = info_span!("tournament", %tournament_id, round).entered();
+        info!("starting round");
+
+        for (index, (white_id, black_id)) in pairings.into_iter().enumerate() {
+            let Some(black_id) = black_id else {
+                debug!(player = %white_id, "bye");
+                self.send_unplayed(&white_id, tournament_id, round);
+                continue;
+            };
+
+            let game_id = self.seat_tournament_game(
+                tournament_id,
+                Some(round),
+                &name,
+                &white_id,
+                &black_id,
+                options.clone(),
+            );
+
+            // whoever can't be seated forfeits, a double forfeit scores nobody
+            let result = match game_id {
+                Some(_) => None,
+                None => Some(
+                    match (
+                        self.inner_server.is_available(&white_id),
+                        self.inner_server.is_available(&black_id),
+                    ) {
+                        (true, false) => GameResult::WhiteWins,
+                        (false, true) => GameResult::BlackWins,
+                        _ => GameResult::Aborted,
+                    },
+                ),
+            };
+
+            if let Some(pairing) = self
+                .tournaments
+                .get_mut(tournament_id)
+                .and_then(|t| t.pairing_mut(index))
+            {
+                pairing.game_id = game_id;
+                pairing.result = result;
+            }
+
+            if result.is_some() {
+                warn!(white = %white_id, black = %black_id, ?result, "forfeited pairing");
+                self.send_unplayed(&white_id, tournament_id, round);
+                self.send_unplayed(&black_id, tournament_id, round);
+            }
+        }
+    }
+
+    /// Start the next round once every game of the current one is over, or finish the tournament
+    fn advance_tournament(&mut self, tournament_id: &str) {
+        // new rounds would hold up the shutdown, the tournament ends with the last finished round
+        if !self.accepting_games {
+            return;
+        }
+
+        loop {
+            let Some(tournament) = self.tournaments.get_mut(tournament_id) else {
+                return;
+            };
+            if tournament.status != TournamentStatus::Running || !tournament.round_complete() {
+                return;
+            }
+
+            if tournament.is_over() {
+                info!(%tournament_id, "tournament finished");
+                tournament.status = TournamentStatus::Finished;
+                self.send_standings(tournament_id);
+                self.tournaments.remove(tournament_id);
+                return;
+            }
+
+            // rounds made up of byes and forfeits are complete right away, so keep going
+            self.start_round(tournament_id);
+        }
+    }
+
+    /// Seat every pair of waiting arena players in a new game
+    fn pair_arena(&mut self, tournament_id: &str) {
+        if !self.accepting_games {
+            return;
+        }
+        let Some(arena) = self.arenas.get_mut(tournament_id) else {
+            return;
+        };
+
+        // players who disconnected can't be paired anymore
+        for player in arena.players.iter_mut() {
+            if self.inner_server.get_session(&player.id).is_none() {
+                player.withdrawn = true;
+            }
+        }
+
+        let pairs: Vec<(usize, usize, String, String)> = arena
+            .pair_waiting()
+            .into_iter()
+            .map(|(white, black)| {
+                let id_of = |index: usize| arena.players[index].id.clone();
+                (white, black, id_of(white), id_of(black))
+            })
+            .collect();
+        let name = arena.name.clone();
+        let mut options = arena.options.clone();
+        options.tournament_id = Some(tournament_id.to_owned());
+
+        for (white, black, white_id, black_id) in pairs {
+            let game_id = self.seat_tournament_game(
+                tournament_id,
+                None,
+                &name,
+                &white_id,
+                &black_id,
+                options.clone(),
+            );
+
+            let Some(arena) = self.arenas.get_mut(tournament_id) else {
+                return;
+            };
+            match game_id {
+                Some(game_id) => arena.add_game(game_id, white, black),
+                None => {
+                    // players busy elsewhere are taken out until they join again
+                    for (index, id) in [(white, &white_id), (black, &black_id)] {
+                        match self.inner_server.is_available(id) {
+                            true => arena.requeue(index),
+                            false => arena.withdraw(id),
+                        }
+                    }
+                    warn!(white = %white_id, black = %black_id, "unable to seat arena pairing");
+                }
+            }
+        }
+    }
+
+    /// Stop pairing once the arena clock runs out and announce the final standings
+    fn end_arena(&mut self, tournament_id: &str) {
+        let Some(arena) = self.arenas.get_mut(tournament_id) else {
+            return;
+        };
+
+        info!(%tournament_id, games = arena.games.len(), "arena finished");
+        arena.finish();
+        self.send_arena_standings(tournament_id);
+        self.arenas.remove(tournament_id);
+    }
+
+    /// Push the standings every few seconds for as long as the arena runs
+    fn schedule_arena_feed(
+        &self,
+        tournament_id: String,
+        ctx: &mut <Self as actix::Actor>::Context,
+    ) {
+        ctx.run_later(ARENA_FEED_INTERVAL, move |act, ctx| {
+            if act.arenas.contains_key(&tournament_id) {
+                act.send_arena_standings(&tournament_id);
+                act.schedule_arena_feed(tournament_id, ctx);
+            }
+        });
+    }
+
+    /// The session as a tournament player, seeded by their rating for the tournament's time control
+    fn participant(&self, id: &str, options: &GameOptions) -> Option<Participant> {
+        let session = self
+            .inner_server
+            .session_summaries()
+            .into_iter()
+            .find(|session| session.id == id)?;
+
+        let rating = match (&session.user, &options.time_control) {
+            (Some(user), Some(time_control)) => {
+                self.ratings
+                    .get(user, Pool::for_time_control(time_control))
+                    .rating
+            }
+            _ => Rating::default().rating,
+        };
+
+        Some(Participant {
+            id: session.id,
+            name: match session.name.is_empty() {
+                true => "Anonymous".to_string(),
+                false => session.name,
+            },
+            user: session.user,
+            rating: rating.round() as i32,
+            withdrawn: false,
+        })
+    }
+
+    fn send_unplayed(&self, id: &str, tournament_id: &str, round: usize) {
+        let pairing = TournamentPairing::unplayed(id, tournament_id, round);
+
+        self.send_to(
+            id,
+            Type::TournamentPairing,
+            serde_json::to_value(pairing).unwrap(),
+        );
+    }
+
+    /// Send the current standings to every participant still connected
+    fn send_standings(&self, tournament_id: &str) {
+        let Some(tournament) = self.tournaments.get(tournament_id) else {
+            return;
+        };
+
+        let standings = TournamentStandings {
+            tournament_id: tournament.id.clone(),
+            name: tournament.name.clone(),
+            status: tournament.status,
+            round: tournament.current_round(),
+            total_rounds: tournament.total_rounds,
+            standings: tournament.standings(),
+        };
+        let payload = serde_json::to_value(standings).unwrap();
+
+        for player in &tournament.players {
+            self.send_to(&player.id, Type::TournamentStandings, payload.clone());
+        }
+    }
+
+    fn send_arena_standings(&self, tournament_id: &str) {
+        let Some(arena) = self.arenas.get(tournament_id) else {
+            return;
+        };
+
+        let standings = ArenaStandings {
+            tournament_id: arena.id.clone(),
+            name: arena.name.clone(),
+            status: arena.status,
+            seconds_left: arena.seconds_left(now_secs()),
+            standings: arena.standings(),
+        };
+        let payload = serde_json::to_value(standings).unwrap();
+
+        for player in &arena.players {
+            self.send_to(&player.id, Type::ArenaStandings, payload.clone());
+        }
+    }
+}
+
+impl<T: WsServer> Handler<CreateTournament> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "create_tournament", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, mut msg: CreateTournament, _: &mut Self::Context) -> Self::Result {
+        if !self.accepting_games {
+            self.send_error(
+                &msg.player_id,
+                "The server is shutting down, no new tournaments can be created",
+            );
+            return;
+        }
+
+        let invalid = match msg.format {
+            TournamentFormat::Swiss => (msg.rounds == 0 || msg.rounds > MAX_TOURNAMENT_ROUNDS)
+                .then(|| format!("A tournament has between 1 and {MAX_TOURNAMENT_ROUNDS} rounds")),
+            TournamentFormat::Arena => (msg.duration_mins == 0
+                || msg.duration_mins > MAX_ARENA_MINUTES)
+                .then(|| format!("An arena lasts between 1 and {MAX_ARENA_MINUTES} minutes")),
+        };
+        let invalid =
+            invalid.or_else(|| Self::check_time_control(msg.options.time_control.as_ref()).err());
+        if let Some(error) = invalid {
+            self.send_error(&msg.player_id, &error);
+            return;
+        }
+
+        let id = nanoid::nanoid!(10);
+        // the creator plays in their own tournament
+        let creator = self.participant(&msg.player_id, &msg.options);
+
+        match msg.format {
+            TournamentFormat::Swiss => {
+                let mut tournament = SwissTournament::new(
+                    id.clone(),
+                    msg.name.clone(),
+                    msg.player_id.clone(),
+                    msg.rounds,
+                    msg.options.clone(),
+                );
+                if let Some(creator) = creator {
+                    let _ = tournament.join(creator);
+                }
+                self.tournaments.insert(id.clone(), tournament);
+            }
+            TournamentFormat::Arena => {
+                let mut arena = ArenaTournament::new(
+                    id.clone(),
+                    msg.name.clone(),
+                    msg.player_id.clone(),
+                    u64::from(msg.duration_mins) * 60,
+                    msg.options.clone(),
+                );
+                if let Some(creator) = creator {
+                    let _ = arena.join(creator);
+                }
+                self.arenas.insert(id.clone(), arena);
+            }
+        }
+
+        info!(tournament_id = %id, name = %msg.name, format = ?msg.format, "tournament created");
+
+        msg.id = id.clone();
+        self.send_to(
+            &msg.player_id,
+            Type::CreateTournament,
+            serde_json::to_value(&msg).unwrap(),
+        );
+        self.send_standings(&id);
+        self.send_arena_standings(&id);
+    }
+}
+
+impl<T: WsServer> Handler<JoinTournament> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "join_tournament", skip_all, fields(session_id = %msg.player_id, tournament_id = %msg.tournament_id))]
+    fn handle(&mut self, msg: JoinTournament, _: &mut Self::Context) -> Self::Result {
+        let options = match (
+            self.tournaments.get(&msg.tournament_id),
+            self.arenas.get(&msg.tournament_id),
+        ) {
+            (Some(tournament), _) => tournament.options.clone(),
+            (_, Some(arena)) => arena.options.clone(),
+            (None, None) => {
+                self.send_error(&msg.player_id, "No such tournament");
+                return;
+            }
+        };
+        let Some(participant) = self.participant(&msg.player_id, &options) else {
+            return;
+        };
+
+        let joined = match self.tournaments.get_mut(&msg.tournament_id) {
+            Some(tournament) => tournament.join(participant),
+            None => match self.arenas.get_mut(&msg.tournament_id) {
+                Some(arena) => arena.join(participant),
+                None => return,
+            },
+        };
+        if let Err(e) = joined {
+            self.send_error(&msg.player_id, &format!("Unable to join: {e}"));
+            return;
+        }
+
+        info!("joined tournament");
+        // players joining a running arena are paired right away
+        self.pair_arena(&msg.tournament_id);
+        self.send_standings(&msg.tournament_id);
+        self.send_arena_standings(&msg.tournament_id);
+    }
+}
+
+impl<T: WsServer> Handler<StartTournament> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "start_tournament", skip_all, fields(session_id = %msg.player_id, tournament_id = %msg.tournament_id))]
+    fn handle(&mut self, msg: StartTournament, ctx: &mut Self::Context) -> Self::Result {
+        let state = match (
+            self.tournaments.get(&msg.tournament_id),
+            self.arenas.get(&msg.tournament_id),
+        ) {
+            (Some(t), _) => Some((t.creator_id.clone(), t.status, t.active_players())),
+            (_, Some(a)) => Some((a.creator_id.clone(), a.status, a.active_players())),
+            (None, None) => None,
+        };
+
+        let error = match state {
+            None => Some("No such tournament"),
+            Some((creator_id, _, _)) if creator_id != msg.player_id => {
+                Some("Only the creator can start the tournament")
+            }
+            Some((_, status, _)) if status != TournamentStatus::Created => {
+                Some("The tournament has already started")
+            }
+            Some((_, _, players)) if players < 2 => Some("A tournament needs at least two players"),
+            Some(_) => None,
+        };
+        if let Some(error) = error {
+            self.send_error(&msg.player_id, error);
+            return;
+        }
+
+        if let Some(tournament) = self.tournaments.get_mut(&msg.tournament_id) {
+            info!(players = tournament.players.len(), "starting tournament");
+            tournament.status = TournamentStatus::Running;
+
+            self.advance_tournament(&msg.tournament_id);
+            self.send_standings(&msg.tournament_id);
+        } else if let Some(arena) = self.arenas.get_mut(&msg.tournament_id) {
+            info!(
+                players = arena.players.len(),
+                duration_secs = arena.duration_secs,
+                "starting arena"
+            );
+            arena.start(now_secs());
+
+            let tournament_id = msg.tournament_id.clone();
+            ctx.run_later(Duration::from_secs(arena.duration_secs), move |act, _| {
+                act.end_arena(&tournament_id);
+            });
+            self.schedule_arena_feed(msg.tournament_id.clone(), ctx);
+
+            self.pair_arena(&msg.tournament_id);
+            self.send_arena_standings(&msg.tournament_id);
+        }
+    }
+}
+
+impl<T: WsServer> Handler<Berserk> for WsChessServer<T> {
+    type Result = ();
+
+    #[tracing::instrument(name = "berserk", skip_all, fields(session_id = %msg.player_id))]
+    fn handle(&mut self, msg: Berserk, _: &mut Self::Context) -> Self::Result {
+        // a session only ever plays one game, so at most one arena has it seated
+        let berserk = self.arenas.values_mut().find_map(|arena| {
+            let opponent_id = arena.current_opponent(&msg.player_id)?;
+            Some((arena.id.clone(), opponent_id, arena.berserk(&msg.player_id)))
+        });
+
+        match berserk {
+            Some((tournament_id, opponent_id, Ok(berserk))) => {
+                info!(%tournament_id, game_id = %berserk.game_id, "berserk");
+
+                let payload = serde_json::to_value(Berserked {
+                    tournament_id,
+                    game_id: berserk.game_id,
+                    color: berserk.color,
+                    time_control: berserk.time_control,
+                })
+                .unwrap();
+                self.send_to(&msg.player_id, Type::Berserk, payload.clone());
+                self.send_to(&opponent_id, Type::Berserk, payload);
+            }
+            Some((_, _, Err(e))) => {
+                self.send_error(&msg.player_id, &format!("Unable to berserk: {e}"))
+            }
+            None => self.send_error(&msg.player_id, "Not playing an arena game"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::server::tests::{connect, test_server};
+    use actix::Context;
+
+    #[actix::test]
+    async fn more_than_four_players_can_enter() {
+        let mut server = test_server();
+        let players: Vec<String> = (0..8).map(|number| format!("p{number}")).collect();
+        for id in &players {
+            connect(&mut server, id, None);
+        }
+
+        server.handle(
+            CreateTournament {
+                id: String::new(),
+                player_id: players[0].clone(),
+                name: "open".to_owned(),
+                format: TournamentFormat::Swiss,
+                rounds: 3,
+                duration_mins: 0,
+                options: GameOptions::default(),
+            },
+            &mut Context::new(),
+        );
+        let tournament_id = server.tournaments.keys().next().unwrap().clone();
+        for id in &players[1..] {
+            server.handle(
+                JoinTournament {
+                    tournament_id: tournament_id.clone(),
+                    player_id: id.clone(),
+                },
+                &mut Context::new(),
+            );
+        }
+        server.handle(
+            StartTournament {
+                tournament_id: tournament_id.clone(),
+                player_id: players[0].clone(),
+            },
+            &mut Context::new(),
+        );
+
+        assert_eq!(server.tournaments[&tournament_id].standings().len(), 8);
+        assert_eq!(server.inner_server.game_summaries().len(), 4);
+    }
+}
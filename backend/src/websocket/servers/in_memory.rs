@@ -1,41 +1,185 @@
 use super::WsServer;
-use crate::types::Color;
-use crate::websocket::session::{Message, Session};
+use crate::chess::{Board, Move, Outcome, Piece, VariantKind};
+use crate::openings::{self, Opening};
+use crate::replay::StoredGame;
+use crate::types::{ChessMove, Color, GameOptions, GameResult, TimeControl, Visibility};
+use crate::utils::{constant_time_eq, now_millis, now_secs};
+use crate::websocket::messages::{ClientMessage, Type};
+use crate::websocket::session::{Close, Message, Session};
 use serde::*;
 use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::{debug, info, info_span, warn};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Longest chain of premoves a player can queue
+const MAX_PREMOVES: usize = 10;
+const SECS_PER_DAY: u64 = 86_400;
+/// Returned by `record_move` for moves the rules don't allow, the Kriegspiel referee
+/// announces these
+pub const ILLEGAL_MOVE: &str = "illegal move";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DrawCondition {
     InsufficientMaterial,
     Stalemate,
     Repetition,
+    FiftyMoves,
+    /// Racing Kings: both kings reached the eighth rank
+    RaceTied,
+    /// Bughouse: the other board of the match was drawn
+    PartnerBoard,
+    /// Declared by an administrator
+    Arbiter,
+    /// Offered by one player and accepted by the other
+    Agreement,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WinLoseCondition {
     Checkmate,
     Resign,
     Overtime,
+    /// Three-check: the third check was given
+    ThreeChecks,
+    /// King of the Hill: a king reached the center
+    KingOfTheHill,
+    /// Atomic: a king was blown up
+    KingExploded,
+    /// Horde: every white pawn and piece was captured
+    HordeDestroyed,
+    /// Racing Kings: a king reached the eighth rank first
+    RaceWon,
+    /// Fog of War: a king was captured
+    KingCaptured,
+    /// Bughouse: the other board of the match decided it
+    PartnerBoard,
+    /// Declared by an administrator
+    Arbiter,
+    /// The opponent left a tournament game before it was over
+    Forfeit,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerStatus {
     win: Option<WinLoseCondition>,
     lose: Option<WinLoseCondition>,
+    /// Only sent by players, to offer a draw or to accept the opponent's offer
+    #[serde(default, skip_serializing)]
+    draw: Option<DrawCondition>,
+}
+
+impl PlayerStatus {
+    fn won_by(condition: WinLoseCondition) -> Self {
+        Self {
+            win: Some(condition),
+            lose: None,
+            draw: None,
+        }
+    }
+
+    fn lost_by(condition: WinLoseCondition) -> Self {
+        Self {
+            win: None,
+            lose: Some(condition),
+            draw: None,
+        }
+    }
+
+    fn forfeit_win() -> Self {
+        Self {
+            win: Some(WinLoseCondition::Forfeit),
+            lose: None,
+            draw: None,
+        }
+    }
+
+    fn overtime_loss() -> Self {
+        Self {
+            win: None,
+            lose: Some(WinLoseCondition::Overtime),
+            draw: None,
+        }
+    }
+
+    /// Whether the player is offering a draw, or accepting one
+    pub fn offers_draw(&self) -> bool {
+        self.draw.is_some()
+    }
+}
+
+impl GameState {
+    /// Record how the board ended the game, `player_one_won` is ignored for draws
+    fn end_on_board(&mut self, outcome: Outcome, player_one_won: bool) {
+        let condition = match outcome {
+            Outcome::Checkmate => WinLoseCondition::Checkmate,
+            Outcome::ThreeChecks => WinLoseCondition::ThreeChecks,
+            Outcome::KingOfTheHill => WinLoseCondition::KingOfTheHill,
+            Outcome::KingExploded => WinLoseCondition::KingExploded,
+            Outcome::HordeDestroyed => WinLoseCondition::HordeDestroyed,
+            Outcome::RaceWon => WinLoseCondition::RaceWon,
+            Outcome::KingCaptured => WinLoseCondition::KingCaptured,
+            Outcome::Stalemate
+            | Outcome::InsufficientMaterial
+            | Outcome::FiftyMoves
+            | Outcome::RaceTied => {
+                self.draw = Some(match outcome {
+                    Outcome::Stalemate => DrawCondition::Stalemate,
+                    Outcome::InsufficientMaterial => DrawCondition::InsufficientMaterial,
+                    Outcome::RaceTied => DrawCondition::RaceTied,
+                    _ => DrawCondition::FiftyMoves,
+                });
+                return;
+            }
+        };
+
+        let (winner, loser) = match player_one_won {
+            true => (&mut self.player_one, &mut self.player_two),
+            false => (&mut self.player_two, &mut self.player_one),
+        };
+        loser.lose = Some(condition.clone());
+        winner.win = Some(condition);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub draw: Option<DrawCondition>,
     pub player_one: PlayerStatus,
     pub player_two: PlayerStatus,
 }
 
+impl GameState {
+    /// Who won according to the statuses, None while nobody has won, lost or drawn
+    pub fn result(&self, player_one_color: Color) -> Option<GameResult> {
+        let player_one_won = self.player_one.win.is_some() || self.player_two.lose.is_some();
+        let player_two_won = self.player_two.win.is_some() || self.player_one.lose.is_some();
+
+        let winner = match (player_one_won, player_two_won) {
+            _ if self.draw.is_some() => return Some(GameResult::Draw),
+            (true, false) => player_one_color,
+            (false, true) => player_one_color.opposite(),
+            // contradicting reports
+            (true, true) => return Some(GameResult::Aborted),
+            (false, false) => return None,
+        };
+
+        match winner {
+            Color::White => Some(GameResult::WhiteWins),
+            Color::Black => Some(GameResult::BlackWins),
+            Color::None => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     pub id: String,
     pub name: String,
     pub color: Color,
+    /// Set when the player is logged in to an account
+    pub user: Option<String>,
 }
 
 impl From<&Session> for Player {
@@ -44,36 +188,395 @@ impl From<&Session> for Player {
             id: value.id.clone(),
             name: value.name.clone(),
             color: value.color,
+            user: value.user.clone(),
         }
     }
 }
 
-#[derive(Debug)]
+/// What the admin API reports about a connected session
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    /// Only set while the session is seated in a game
+    pub color: Option<Color>,
+    pub joined_game: Option<String>,
+}
+
+impl From<&Session> for SessionSummary {
+    fn from(value: &Session) -> Self {
+        Self {
+            id: value.id.clone(),
+            name: value.name.clone(),
+            user: value.user.clone(),
+            color: match value.color {
+                Color::None => None,
+                color => Some(color),
+            },
+            joined_game: value.joined_game.clone(),
+        }
+    }
+}
+
+/// What the admin API reports about a game
+#[derive(Debug, Clone, Serialize)]
+pub struct GameSummary {
+    pub id: String,
+    pub name: String,
+    pub player_one: Option<Player>,
+    pub player_two: Option<Player>,
+    pub game_state: GameState,
+    pub opening: Option<Opening>,
+    /// Set for correspondence games
+    pub days_per_move: Option<u32>,
+    pub move_deadline: Option<u64>,
+    pub visibility: Visibility,
+    pub variant: VariantKind,
+    /// The whole board, even in variants that hide it from the players
+    pub fen: String,
+}
+
+/// A game waiting for an opponent, as the lobby and invitations show it
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenGame {
+    pub id: String,
+    pub name: String,
+    /// The player who created the game
+    pub host: Option<Player>,
+    /// The color the joining player gets
+    pub color: Color,
+    pub rated: bool,
+    pub time_control: Option<TimeControl>,
+    pub days_per_move: Option<u32>,
+}
+
+/// A correspondence game from the point of view of one of its players
+#[derive(Debug, Clone, Serialize)]
+pub struct CorrespondenceGame {
+    pub id: String,
+    pub name: String,
+    pub color: Color,
+    /// The opponent's account, None while nobody has joined
+    pub opponent: Option<String>,
+    pub days_per_move: u32,
+    /// When the side to move loses on time, in seconds since the unix epoch
+    pub move_deadline: Option<u64>,
+    pub to_move: Color,
+    pub fen: String,
+    /// In UCI notation
+    pub moves: Vec<String>,
+}
+
+/// A move the server accepted, as it is relayed to the opponent
+#[derive(Debug, Clone)]
+pub struct PlayedMove {
+    pub san: String,
+    /// The position after the move
+    pub fen: String,
+    /// Set when the move changed the game's opening
+    pub opening: Option<Opening>,
+    /// Set when the move ended the game
+    pub finished: Option<FinishedGame>,
+    pub game_id: String,
+    /// The piece the move took, bughouse hands it to the mover's partner
+    pub captured: Option<Piece>,
+    pub variant: VariantKind,
+    /// The board after the move, for variants that show each player a different part of it
+    pub board: Board,
+}
+
+impl PlayedMove {
+    /// The move as the viewer gets to see it
+    pub fn seen_by(&self, chess_move: &ChessMove, viewer: Color) -> ChessMove {
+        move_seen_by(self.variant, &self.board, chess_move, viewer)
+    }
+}
+
+/// The move that led to the board as the viewer gets to see it. Where the variant hides
+/// the board, the squares and notation of the move are blanked and the position is
+/// the viewer's.
+fn move_seen_by(
+    variant: VariantKind,
+    board: &Board,
+    chess_move: &ChessMove,
+    viewer: Color,
+) -> ChessMove {
+    let rules = variant.rules();
+    let fen = Some(rules.fen_for(board, viewer));
+    match rules.hides_information() {
+        true => ChessMove {
+            from: String::new(),
+            to: String::new(),
+            promotion_piece: None,
+            drop_piece: None,
+            san: None,
+            fen,
+            premove: false,
+        },
+        false => ChessMove {
+            fen,
+            ..chess_move.clone()
+        },
+    }
+}
+
+/// Everything needed to wrap up a game once it is over
+#[derive(Debug, Clone)]
+pub struct FinishedGame {
+    pub game_id: String,
+    pub name: String,
+    pub result: GameResult,
+    pub game_state: GameState,
+    /// Sessions that were still seated when the game ended
+    pub player_ids: Vec<String>,
+    pub white_id: Option<String>,
+    pub black_id: Option<String>,
+    pub white_name: String,
+    pub black_name: String,
+    pub white_user: Option<String>,
+    pub black_user: Option<String>,
+    pub rated: bool,
+    pub time_control: Option<TimeControl>,
+    pub tournament_id: Option<String>,
+    pub days_per_move: Option<u32>,
+    pub variant: VariantKind,
+    pub visibility: Visibility,
+    pub password: Option<String>,
+    /// In UCI notation
+    pub moves: Vec<String>,
+    /// When each move was played, in milliseconds since the unix epoch
+    pub move_times: Vec<u64>,
+    pub opening: Option<Opening>,
+    /// The final position
+    pub fen: String,
+}
+
+impl FinishedGame {
+    /// The options to play the same game again with
+    pub fn options(&self) -> GameOptions {
+        GameOptions {
+            rated: self.rated,
+            time_control: self.time_control,
+            days_per_move: self.days_per_move,
+            variant: self.variant,
+            visibility: self.visibility,
+            password: self.password.clone(),
+            tournament_id: self.tournament_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Game {
     pub name: String,
     // The player who created the game will always be player_one
     pub player_one_id: String,
+    pub player_one_color: Color,
     pub player_two_id: Option<String>,
+    /// Accounts of the players, kept on the game so the result can be attributed
+    /// even after a session disconnects
+    pub player_one_user: Option<String>,
+    pub player_two_user: Option<String>,
+    pub rated: bool,
+    pub time_control: Option<TimeControl>,
+    /// Set for games paired by a tournament
+    #[serde(default)]
+    pub tournament_id: Option<String>,
     pub game_state: GameState,
+    /// Every move played so far, in UCI notation
+    #[serde(default)]
+    pub moves: Vec<String>,
+    /// When each move was played, in milliseconds since the unix epoch
+    #[serde(default)]
+    pub move_times: Vec<u64>,
+    #[serde(default)]
+    pub variant: VariantKind,
+    /// The position after the last move, along with what the variant keeps track of
+    #[serde(flatten)]
+    pub board: Board,
+    /// Moves each player queued to be played as soon as it is their turn
+    #[serde(default)]
+    pub player_one_premoves: Vec<Move>,
+    #[serde(default)]
+    pub player_two_premoves: Vec<Move>,
+    /// The most specific known opening the game went through
+    #[serde(default)]
+    pub opening: Option<Opening>,
+    /// Set for correspondence games, which are played by accounts rather than sessions
+    /// and keep their seats while the players are offline
+    #[serde(default)]
+    pub days_per_move: Option<u32>,
+    /// When the side to move loses on time in a correspondence game, in seconds since
+    /// the unix epoch
+    #[serde(default)]
+    pub move_deadline: Option<u64>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Shared by the creator of a private game to let someone join it
+    #[serde(default)]
+    pub invite_token: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// The color of the player offering a draw, the offer stands until the opponent
+    /// accepts it or makes a move
+    #[serde(default)]
+    pub draw_offer: Option<Color>,
+    /// Repetition keys of the positions since the last pawn move or capture, the only
+    /// ones the position on the board can still repeat. Only kept in standard games.
+    #[serde(default)]
+    pub repetitions: Vec<String>,
 }
 
 impl Game {
-    pub fn new(name: &str, player_one_id: String) -> Self {
-        Self {
+    pub fn new(
+        name: &str,
+        player_one_id: String,
+        player_one_user: Option<String>,
+        player_one_color: Color,
+        options: GameOptions,
+    ) -> Self {
+        // correspondence games have no clock
+        let time_control = options
+            .time_control
+            .filter(|_| options.days_per_move.is_none());
+        let mut game = Self {
             name: name.to_owned(),
             player_one_id,
+            player_one_color,
             player_two_id: None,
+            // unrated when the creator is anonymous, the game is untimed or isn't standard chess
+            rated: options.rated
+                && player_one_user.is_some()
+                && time_control.is_some()
+                && options.variant == VariantKind::Standard,
+            player_one_user,
+            player_two_user: None,
+            time_control,
+            tournament_id: options.tournament_id,
             game_state: GameState {
                 draw: None,
                 player_one: PlayerStatus {
                     win: None,
                     lose: None,
+                    draw: None,
                 },
                 player_two: PlayerStatus {
                     win: None,
                     lose: None,
+                    draw: None,
                 },
             },
+            moves: Vec::new(),
+            move_times: Vec::new(),
+            variant: options.variant,
+            board: options.variant.rules().starting_board(),
+            player_one_premoves: Vec::new(),
+            player_two_premoves: Vec::new(),
+            opening: None,
+            days_per_move: options.days_per_move,
+            move_deadline: None,
+            visibility: options.visibility,
+            invite_token: match options.visibility {
+                Visibility::Private => Some(nanoid::nanoid!(21)),
+                _ => None,
+            },
+            password: options.password.filter(|password| !password.is_empty()),
+            draw_offer: None,
+            repetitions: Vec::new(),
+        };
+        game.remember_position();
+
+        game
+    }
+
+    /// The color played by the seat the player sits in
+    fn color_of(&self, player_id: &str) -> Color {
+        match self.player_one_id == player_id {
+            true => self.player_one_color,
+            false => self.player_one_color.opposite(),
+        }
+    }
+
+    /// Give the side to move a fresh correspondence deadline
+    fn restart_deadline(&mut self) {
+        self.move_deadline = self
+            .days_per_move
+            .map(|days| now_secs() + u64::from(days) * SECS_PER_DAY);
+    }
+
+    /// The game as seen by the account, None if it isn't a correspondence game the
+    /// account plays in
+    fn correspondence_view(&self, id: &str, user: &str) -> Option<CorrespondenceGame> {
+        let days_per_move = self.days_per_move?;
+        let (color, opponent) = if self.player_one_user.as_deref() == Some(user) {
+            (self.player_one_color, self.player_two_user.clone())
+        } else if self.player_two_user.as_deref() == Some(user) {
+            (
+                self.player_one_color.opposite(),
+                self.player_one_user.clone(),
+            )
+        } else {
+            return None;
+        };
+
+        // where the board is hidden only the player's own moves are shown, white moves first
+        let rules = self.variant.rules();
+        let moves = match rules.hides_information() {
+            true => self
+                .moves
+                .iter()
+                .skip(usize::from(color == Color::Black))
+                .step_by(2)
+                .cloned()
+                .collect(),
+            false => self.moves.clone(),
+        };
+
+        Some(CorrespondenceGame {
+            id: id.to_owned(),
+            name: self.name.clone(),
+            color,
+            opponent,
+            days_per_move,
+            move_deadline: self.move_deadline,
+            to_move: self.board.position.side_to_move(),
+            fen: rules.fen_for(&self.board, color),
+            moves,
+        })
+    }
+
+    /// Keep track of the position on the board for repetitions, forgetting the positions
+    /// a pawn move or capture made unreachable
+    fn remember_position(&mut self) {
+        if self.variant != VariantKind::Standard {
+            return;
+        }
+
+        if self.board.position.halfmove_clock() == 0 {
+            self.repetitions.clear();
+        }
+        self.repetitions.push(self.board.position.repetition_key());
+    }
+
+    /// Whether the position on the board came up for the third time, which is only
+    /// tracked in standard games
+    fn repeated_three_times(&self) -> bool {
+        let Some(current) = self.repetitions.last() else {
+            return false;
+        };
+
+        self.repetitions
+            .iter()
+            .filter(|key| *key == current)
+            .count()
+            >= 3
+    }
+
+    fn premoves_mut(&mut self, player_id: &str) -> &mut Vec<Move> {
+        match self.player_one_id == player_id {
+            true => &mut self.player_one_premoves,
+            false => &mut self.player_two_premoves,
         }
     }
 }
@@ -84,6 +587,168 @@ pub struct InMemoryServer {
     sessions: HashMap<String, Session>,
 }
 
+impl InMemoryServer {
+    /// The game the player is seated in, which for correspondence games may outlive
+    /// the session the seat was taken with
+    fn seat_of(&self, player_id: &str) -> Option<String> {
+        if let Some(game_id) = self
+            .sessions
+            .get(player_id)
+            .and_then(|session| session.joined_game.clone())
+        {
+            return Some(game_id);
+        }
+
+        self.games.iter().find_map(|(id, game)| {
+            let seated =
+                game.player_one_id == player_id || game.player_two_id.as_deref() == Some(player_id);
+            (game.days_per_move.is_some() && seated).then(|| id.clone())
+        })
+    }
+
+    /// The account seated in a correspondence game, for when the player is offline
+    fn offline_player(game: &Game, player_id: &str, user: &Option<String>) -> Option<Player> {
+        let user = user.as_ref().filter(|_| game.days_per_move.is_some())?;
+        Some(Player {
+            id: player_id.to_owned(),
+            name: user.clone(),
+            color: game.color_of(player_id),
+            user: Some(user.clone()),
+        })
+    }
+
+    /// Load the games written by `save_snapshot`, a missing snapshot yields an empty server.
+    ///
+    /// The snapshot is removed once loaded so a later crash doesn't bring back stale games.
+    /// Only correspondence games are kept, the seats of other games belonged to sessions
+    /// that didn't survive the restart.
+    pub fn restore(path: &Path) -> io::Result<Self> {
+        let mut games: HashMap<String, Game> = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        fs::remove_file(path)?;
+
+        let total = games.len();
+        games.retain(|_, game| game.days_per_move.is_some());
+        info!(
+            games = games.len(),
+            dropped = total - games.len(),
+            "restored games from snapshot"
+        );
+
+        Ok(Self {
+            games,
+            ..Default::default()
+        })
+    }
+
+    /// End a game with a result decided outside of it, `win` is how it was won and
+    /// `draw` how it was drawn
+    fn impose_result(
+        &mut self,
+        game_id: &str,
+        result: GameResult,
+        win: WinLoseCondition,
+        draw: DrawCondition,
+    ) -> Option<FinishedGame> {
+        let game = self.games.get_mut(game_id)?;
+
+        let player_one_won = match result {
+            GameResult::WhiteWins => Some(game.player_one_color == Color::White),
+            GameResult::BlackWins => Some(game.player_one_color == Color::Black),
+            GameResult::Draw | GameResult::Aborted => None,
+        };
+        match player_one_won {
+            Some(true) => {
+                game.game_state.player_one = PlayerStatus::won_by(win.clone());
+                game.game_state.player_two = PlayerStatus::lost_by(win);
+            }
+            Some(false) => {
+                game.game_state.player_two = PlayerStatus::won_by(win.clone());
+                game.game_state.player_one = PlayerStatus::lost_by(win);
+            }
+            None if result == GameResult::Draw => {
+                game.game_state.draw = Some(draw);
+            }
+            None => {}
+        }
+
+        self.finish_game(game_id, result)
+    }
+
+    /// Remove a finished game and free both of its players
+    fn finish_game(&mut self, game_id: &str, result: GameResult) -> Option<FinishedGame> {
+        let game = self.games.remove(game_id)?;
+
+        // offline correspondence players go by their account
+        let name_of = |id: Option<&String>, user: &Option<String>| {
+            id.and_then(|id| self.sessions.get(id))
+                .map(|session| session.name.clone())
+                .filter(|name| !name.is_empty())
+                .or_else(|| user.clone())
+                .unwrap_or_else(|| "Anonymous".to_string())
+        };
+        let player_one_name = name_of(Some(&game.player_one_id), &game.player_one_user);
+        let player_two_name = name_of(game.player_two_id.as_ref(), &game.player_two_user);
+
+        let (white_id, black_id) = match game.player_one_color {
+            Color::Black => (game.player_two_id.clone(), Some(game.player_one_id.clone())),
+            _ => (Some(game.player_one_id.clone()), game.player_two_id.clone()),
+        };
+        let player_ids: Vec<String> = std::iter::once(game.player_one_id)
+            .chain(game.player_two_id)
+            .collect();
+        for player_id in &player_ids {
+            if let Some(session) = self.sessions.get_mut(player_id) {
+                session.joined_game = None;
+                session.color = Color::None;
+            }
+        }
+
+        let (white_user, black_user, white_name, black_name) = match game.player_one_color {
+            Color::Black => (
+                game.player_two_user,
+                game.player_one_user,
+                player_two_name,
+                player_one_name,
+            ),
+            _ => (
+                game.player_one_user,
+                game.player_two_user,
+                player_one_name,
+                player_two_name,
+            ),
+        };
+
+        Some(FinishedGame {
+            game_id: game_id.to_owned(),
+            name: game.name,
+            result,
+            game_state: game.game_state,
+            player_ids,
+            white_id,
+            black_id,
+            white_name,
+            black_name,
+            white_user,
+            black_user,
+            rated: game.rated,
+            time_control: game.time_control,
+            tournament_id: game.tournament_id,
+            days_per_move: game.days_per_move,
+            fen: game.variant.rules().fen(&game.board),
+            variant: game.variant,
+            visibility: game.visibility,
+            password: game.password,
+            moves: game.moves,
+            move_times: game.move_times,
+            opening: game.opening,
+        })
+    }
+}
+
 impl WsServer for InMemoryServer {
     type Session = Session;
     type Game = Game;
@@ -92,14 +757,32 @@ impl WsServer for InMemoryServer {
         self.games.get(id)
     }
 
-    fn create_game(&mut self, name: &str, player_one_id: &str, color: Color) -> Option<String> {
+    fn create_game(
+        &mut self,
+        name: &str,
+        player_one_id: &str,
+        color: Color,
+        options: GameOptions,
+    ) -> Option<String> {
         if player_one_id.is_empty() {
             return None;
         }
 
         let id = nanoid::nanoid!(10);
-        self.games
-            .insert(id.clone(), Game::new(name, player_one_id.to_owned()));
+        let player_one_user = self
+            .sessions
+            .get(player_one_id)
+            .and_then(|session| session.user.clone());
+        self.games.insert(
+            id.clone(),
+            Game::new(
+                name,
+                player_one_id.to_owned(),
+                player_one_user,
+                color,
+                options,
+            ),
+        );
 
         if let Some(session) = self.sessions.get_mut(player_one_id) {
             session.color = color;
@@ -109,83 +792,224 @@ impl WsServer for InMemoryServer {
         Some(id)
     }
 
-    fn join_game(&mut self, game_id: &str, player_id: &str) {
-        let mut player_two_color = Color::None;
-
-        if let Some(game) = self.games.get(game_id) {
-            if let Some(player_one) = self.sessions.get(&game.player_one_id) {
-                player_two_color = match player_one.color {
-                    Color::Black => Color::White,
-                    Color::White => Color::Black,
-                    _ => panic!("invalid color"),
-                }
-            }
+    fn create_paired_game(
+        &mut self,
+        name: &str,
+        white_id: &str,
+        black_id: &str,
+        options: GameOptions,
+    ) -> Option<String> {
+        if !self.is_available(white_id) || !self.is_available(black_id) {
+            return None;
         }
 
+        let game_id = self.create_game(name, white_id, Color::White, options)?;
+        self.join_game(&game_id, black_id);
+
+        Some(game_id)
+    }
+
+    fn join_game(&mut self, game_id: &str, player_id: &str) {
         if let Some(session) = self.sessions.get_mut(player_id) {
             if let Some(game) = self.games.get_mut(game_id) {
+                let player_two_color = game.player_one_color.opposite();
+
+                // correspondence players take their seat back from a new session
+                if game.days_per_move.is_some() && session.user.is_some() {
+                    let seat = if session.user == game.player_one_user {
+                        Some(&mut game.player_one_id)
+                    } else if session.user == game.player_two_user {
+                        game.player_two_id.as_mut()
+                    } else {
+                        None
+                    };
+                    if let Some(seat) = seat {
+                        *seat = player_id.to_owned();
+                        session.color = game.color_of(player_id);
+                        session.joined_game = Some(game_id.to_owned());
+                        info!(game_id, "correspondence player returned");
+                        return;
+                    }
+                }
+
                 if game.player_two_id.is_some() {
-                    println!("game is full, can't join");
+                    warn!(game_id, "game is full, can't join");
                 } else {
                     session.color = player_two_color;
                     session.joined_game = Some(game_id.to_owned());
                     game.player_two_id = Some(player_id.to_owned());
+                    game.player_two_user = session.user.clone();
+
+                    // a rated game needs two different accounts
+                    if game.player_two_user.is_none()
+                        || game.player_two_user == game.player_one_user
+                    {
+                        game.rated = false;
+                    }
+                    game.restart_deadline();
+                    info!(game_id, color = ?player_two_color, "player two joined");
                 }
             }
         }
     }
 
-    fn leave_game(&mut self, game_id: &str, player_id: &str) {
+    fn leave_game(&mut self, game_id: &str, player_id: &str) -> Option<FinishedGame> {
         if let Some(game) = self.games.get_mut(game_id) {
+            // correspondence games wait for the player to come back
+            if game.days_per_move.is_some() {
+                info!(game_id, "correspondence player went offline");
+                return None;
+            }
+
+            // leaving a rated, tournament or bughouse game hands the win to the opponent
+            if (game.rated || game.tournament_id.is_some() || game.variant == VariantKind::Bughouse)
+                && game.player_two_id.is_some()
+            {
+                let player_one_left = game.player_one_id == player_id;
+                let winner_color = match player_one_left {
+                    true => {
+                        game.game_state.player_two = PlayerStatus::forfeit_win();
+                        game.player_one_color.opposite()
+                    }
+                    false => {
+                        game.game_state.player_one = PlayerStatus::forfeit_win();
+                        game.player_one_color
+                    }
+                };
+                let result = match winner_color {
+                    Color::Black => GameResult::BlackWins,
+                    _ => GameResult::WhiteWins,
+                };
+                info!(
+                    game_id,
+                    ?result,
+                    "player left a rated, tournament or bughouse game, forfeiting"
+                );
+
+                return self.finish_game(game_id, result);
+            }
+
             // if player_one leaves, we delete the game
             if game.player_one_id == player_id {
+                info!(game_id, "player one left, deleting game");
                 self.delete_game(game_id);
-                return;
+                return None;
             }
 
             if let Some(player_two) = &game.player_two_id {
                 if player_two.as_str() == player_id {
+                    info!(game_id, "player two left");
                     game.player_two_id = None;
                 }
             }
         }
+
+        None
     }
 
     fn delete_game(&mut self, id: &str) {
         self.games.remove(id);
     }
 
-    fn update_game_state(&mut self, player_id: &str, player_status: PlayerStatus) {
-        if let Some(session) = self.sessions.get_mut(player_id) {
-            if let Some(game_id) = &session.joined_game {
-                if let Some(game) = self.games.get_mut(game_id) {
-                    if game.player_one_id == player_id {
-                        game.game_state.player_one = player_status;
-                    } else if let Some(player_two_id) = &game.player_two_id {
-                        if player_two_id == player_id {
-                            game.game_state.player_two = player_status;
-                        }
-                    }
-
-                    let game_id = game_id.clone();
-
-                    // game.game_state.is_finished = true;
-
-                    // This is temporary
-                    // Delete the game since it is finished
-                    session.joined_game = None;
-                    session.color = Color::None;
+    fn update_game_state(
+        &mut self,
+        player_id: &str,
+        player_status: PlayerStatus,
+    ) -> Result<Option<FinishedGame>, &'static str> {
+        let game_id = self.seat_of(player_id).ok_or("not in a game")?;
+        let game = self.games.get_mut(&game_id).ok_or("not in a game")?;
+        let color = game.color_of(player_id);
+        let (own, opponent) = match game.player_one_id == player_id {
+            true => (
+                &mut game.game_state.player_one,
+                &mut game.game_state.player_two,
+            ),
+            false => (
+                &mut game.game_state.player_two,
+                &mut game.game_state.player_one,
+            ),
+        };
 
-                    if let Some(player_two_id) = &game.player_two_id {
-                        if let Some(player_two_session) = self.sessions.get_mut(player_two_id) {
-                            player_two_session.joined_game = None;
-                            player_two_session.color = Color::None;
-                        }
-                    }
-                    self.games.remove(&game_id);
+        // players can only give the game away, everything else is decided on the board
+        match player_status {
+            PlayerStatus {
+                lose: Some(condition @ (WinLoseCondition::Resign | WinLoseCondition::Overtime)),
+                ..
+            } => {
+                *own = PlayerStatus::lost_by(condition.clone());
+                *opponent = PlayerStatus::won_by(condition);
+            }
+            PlayerStatus { win: Some(_), .. } | PlayerStatus { lose: Some(_), .. } => {
+                return Err("only resigning and losing on time can be reported");
+            }
+            PlayerStatus { draw: Some(_), .. } => {
+                if game.player_two_id.is_none() {
+                    return Err("the game hasn't started");
+                }
+                if game.draw_offer.is_none_or(|offer| offer == color) {
+                    info!(%game_id, ?color, "draw offered");
+                    game.draw_offer = Some(color);
+                    return Ok(None);
                 }
+                game.game_state.draw = Some(DrawCondition::Agreement);
             }
+            _ => return Ok(None),
         }
+
+        // resigning before anyone joined just cancels the game
+        let result = match game.player_two_id {
+            Some(_) => game
+                .game_state
+                .result(game.player_one_color)
+                .unwrap_or(GameResult::Aborted),
+            None => GameResult::Aborted,
+        };
+        info!(%game_id, state = ?game.game_state, ?result, "game finished");
+
+        // Delete the game since it is finished
+        Ok(self.finish_game(&game_id, result))
+    }
+
+    fn terminate_game(&mut self, game_id: &str, result: GameResult) -> Option<FinishedGame> {
+        info!(game_id, ?result, "game terminated by arbiter");
+        self.impose_result(
+            game_id,
+            result,
+            WinLoseCondition::Arbiter,
+            DrawCondition::Arbiter,
+        )
+    }
+
+    fn conclude_linked_game(&mut self, game_id: &str, result: GameResult) -> Option<FinishedGame> {
+        info!(game_id, ?result, "partner board decided the game");
+        self.impose_result(
+            game_id,
+            result,
+            WinLoseCondition::PartnerBoard,
+            DrawCondition::PartnerBoard,
+        )
+    }
+
+    fn board_view(&self, game_id: &str, viewer: Color) -> Option<String> {
+        let game = self.games.get(game_id)?;
+        Some(game.variant.rules().fen_for(&game.board, viewer))
+    }
+
+    fn add_to_pocket(&mut self, game_id: &str, piece: Piece) -> Option<String> {
+        let game = self.games.get_mut(game_id)?;
+        game.board
+            .variant_state
+            .add_to_pocket(piece.color, piece.kind);
+
+        Some(game.variant.rules().fen(&game.board))
+    }
+
+    fn active_game_count(&self) -> usize {
+        // correspondence games don't need both players online
+        self.games
+            .values()
+            .filter(|game| game.player_two_id.is_some() && game.days_per_move.is_none())
+            .count()
     }
 
     fn get_session(&self, id: &str) -> Option<&Self::Session> {
@@ -196,36 +1020,372 @@ impl WsServer for InMemoryServer {
         self.sessions.insert(id.to_owned(), session);
     }
 
-    fn delete_session(&mut self, id: &str) {
-        if let Some(session) = self.sessions.remove(id) {
-            if let Some(game_id) = session.joined_game {
-                self.leave_game(&game_id, id)
-            }
-        }
+    fn delete_session(&mut self, id: &str) -> Option<FinishedGame> {
+        let game_id = self.sessions.get(id)?.joined_game.clone();
+        // leave while the session is still around so its name ends up in the result
+        let finished = game_id.and_then(|game_id| self.leave_game(&game_id, id));
+        self.sessions.remove(id);
+
+        finished
+    }
+
+    fn is_available(&self, id: &str) -> bool {
+        self.sessions
+            .get(id)
+            .is_some_and(|session| session.joined_game.is_none())
     }
 
     fn update_session_name(&mut self, id: &str, name: &str) {
         if let Some(session) = self.sessions.get_mut(id) {
-            session.name = name.to_owned()
+            // the name of an authenticated player is their username
+            if session.user.is_none() {
+                session.name = name.to_owned()
+            }
         }
     }
 
-    fn make_move(&mut self, chess_move: &str, player_id: &str) {
-        if let Some(player) = self.get_session(player_id) {
-            if let Some(game_id) = &player.joined_game {
-                if let Some(game) = self.get_game(&game_id) {
-                    if game.player_one_id == player_id {
-                        if let Some(player_two_id) = &game.player_two_id {
-                            self.send(player_two_id, Message(chess_move.to_owned()));
-                        }
-                    } else {
-                        self.send(&game.player_one_id, Message(chess_move.to_owned()));
+    fn session_ids(&self) -> Vec<String> {
+        self.sessions.keys().cloned().collect()
+    }
+
+    fn session_summaries(&self) -> Vec<SessionSummary> {
+        self.sessions.values().map(SessionSummary::from).collect()
+    }
+
+    fn kick_session(&self, id: &str, reason: &str) -> bool {
+        match self.get_session(id) {
+            Some(session) => {
+                info!(session_id = id, reason, "kicking session");
+                session.close_addr.do_send(Close(reason.to_owned()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn session_user(&self, id: &str) -> Option<String> {
+        self.sessions
+            .get(id)
+            .and_then(|session| session.user.clone())
+    }
+
+    fn game_of(&self, player_id: &str) -> Option<String> {
+        self.seat_of(player_id)
+    }
+
+    fn seat_of_user(&self, game_id: &str, user: &str) -> Option<String> {
+        let game = self.games.get(game_id)?;
+        game.days_per_move?;
+
+        if game.player_one_user.as_deref() == Some(user) {
+            Some(game.player_one_id.clone())
+        } else if game.player_two_user.as_deref() == Some(user) {
+            game.player_two_id.clone()
+        } else {
+            None
+        }
+    }
+
+    fn correspondence_games(&self, user: &str) -> Vec<CorrespondenceGame> {
+        self.games
+            .iter()
+            .filter_map(|(id, game)| game.correspondence_view(id, user))
+            .collect()
+    }
+
+    fn correspondence_game(&self, game_id: &str, user: &str) -> Option<CorrespondenceGame> {
+        self.games.get(game_id)?.correspondence_view(game_id, user)
+    }
+
+    fn expire_correspondence_games(&mut self, now: u64) -> Vec<FinishedGame> {
+        let expired: Vec<String> = self
+            .games
+            .iter()
+            .filter(|(_, game)| game.move_deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|game_id| {
+                let game = self.games.get_mut(&game_id)?;
+                let to_move = game.board.position.side_to_move();
+
+                // nothing is at stake before both sides have moved
+                let result = if game.moves.len() < 2 {
+                    GameResult::Aborted
+                } else {
+                    match to_move == game.player_one_color {
+                        true => game.game_state.player_one = PlayerStatus::overtime_loss(),
+                        false => game.game_state.player_two = PlayerStatus::overtime_loss(),
                     }
+                    match to_move {
+                        Color::White => GameResult::BlackWins,
+                        _ => GameResult::WhiteWins,
+                    }
+                };
+                info!(%game_id, ?result, "correspondence deadline expired");
+
+                self.finish_game(&game_id, result)
+            })
+            .collect()
+    }
+
+    fn record_move(
+        &mut self,
+        player_id: &str,
+        chess_move: Move,
+    ) -> Result<PlayedMove, &'static str> {
+        let game_id = self.seat_of(player_id).ok_or("not in a game")?;
+        let game = self.games.get_mut(&game_id).ok_or("not in a game")?;
+
+        if game.player_two_id.is_none() {
+            return Err("the game hasn't started");
+        }
+        if game.color_of(player_id) != game.board.position.side_to_move() {
+            return Err("it isn't your turn");
+        }
+        let rules = game.variant.rules();
+        if !rules.is_legal(&game.board, chess_move) {
+            return Err(ILLEGAL_MOVE);
+        }
+
+        let san = rules.san(&game.board, chess_move);
+        let captured = rules
+            .captured(&game.board, chess_move)
+            .map(|kind| Piece::new(game.board.position.side_to_move().opposite(), kind));
+        // playing on turns the opponent's draw offer down
+        let mover = game.board.position.side_to_move();
+        game.draw_offer = game.draw_offer.filter(|&offer| offer == mover);
+        game.board = rules.play(&game.board, chess_move);
+        game.remember_position();
+        game.moves.push(chess_move.uci());
+        game.move_times.push(now_millis());
+        game.restart_deadline();
+        let fen = rules.fen(&game.board);
+        let (variant, board) = (game.variant, game.board.clone());
+
+        let mut opening = None;
+        if game.variant == VariantKind::Standard && openings::in_book_range(game.moves.len() - 1) {
+            let reached = openings::classify(&game.moves);
+            if reached.is_some() && reached != game.opening {
+                game.opening.clone_from(&reached);
+                opening = reached;
+            }
+        }
+
+        // mates, draws and the variants' own endings are decided on the server's board
+        let mut ended = true;
+        if let Some(outcome) = rules.outcome(&game.board) {
+            let player_one_won = rules.winner(&game.board, outcome) == Some(game.player_one_color);
+            game.game_state.end_on_board(outcome, player_one_won);
+            info!(%game_id, ?outcome, "game ended on the board");
+        } else if game.repeated_three_times() {
+            game.game_state.draw = Some(DrawCondition::Repetition);
+            info!(%game_id, "game drawn by repetition");
+        } else {
+            ended = false;
+        }
+        let mut finished = None;
+        if ended {
+            let result = game
+                .game_state
+                .result(game.player_one_color)
+                .unwrap_or(GameResult::Aborted);
+            finished = self.finish_game(&game_id, result);
+        }
+
+        Ok(PlayedMove {
+            san,
+            fen,
+            opening,
+            finished,
+            game_id,
+            captured,
+            variant,
+            board,
+        })
+    }
+
+    fn make_move(&mut self, chess_move: &ChessMove, player_id: &str) {
+        if let Some(game_id) = self.seat_of(player_id) {
+            if let Some(game) = self.get_game(&game_id) {
+                let _game = info_span!("game", %game_id).entered();
+                debug!("relaying move to opponent");
+
+                let opponent_id = match game.player_one_id == player_id {
+                    true => game.player_two_id.as_deref(),
+                    false => Some(game.player_one_id.as_str()),
+                };
+                if let Some(opponent_id) = opponent_id {
+                    let chess_move = move_seen_by(
+                        game.variant,
+                        &game.board,
+                        chess_move,
+                        game.color_of(opponent_id),
+                    );
+                    let client_msg = serde_json::to_string(&ClientMessage {
+                        m_type: Type::MakeMove,
+                        payload: serde_json::to_value(chess_move).unwrap(),
+                    })
+                    .expect("unable to parse move message");
+                    self.send(opponent_id, Message(client_msg));
                 }
             }
         }
     }
 
+    fn queue_premove(&mut self, player_id: &str, chess_move: Move) -> Result<usize, &'static str> {
+        let game = self
+            .seat_of(player_id)
+            .and_then(|id| self.games.get_mut(&id))
+            .ok_or("not in a game")?;
+
+        if game.player_two_id.is_none() {
+            return Err("the game hasn't started");
+        }
+        if game.color_of(player_id) == game.board.position.side_to_move() {
+            return Err("it is your turn, make a move instead");
+        }
+
+        let premoves = game.premoves_mut(player_id);
+        if premoves.len() >= MAX_PREMOVES {
+            return Err("too many premoves queued");
+        }
+        premoves.push(chess_move);
+
+        Ok(premoves.len())
+    }
+
+    fn cancel_premoves(&mut self, player_id: &str) {
+        let game_id = self.seat_of(player_id);
+
+        if let Some(game) = game_id.and_then(|id| self.games.get_mut(&id)) {
+            game.premoves_mut(player_id).clear();
+        }
+    }
+
+    fn next_premove(&mut self, game_id: &str) -> Option<(String, Move)> {
+        let game = self.games.get_mut(game_id)?;
+        let player_id = match game.board.position.side_to_move() == game.player_one_color {
+            true => game.player_one_id.clone(),
+            false => game.player_two_id.clone()?,
+        };
+
+        let premoves = game.premoves_mut(&player_id);
+        if premoves.is_empty() {
+            return None;
+        }
+
+        Some((player_id, premoves.remove(0)))
+    }
+
+    fn game_summaries(&self) -> Vec<GameSummary> {
+        self.games
+            .keys()
+            .filter_map(|id| self.game_summary(id))
+            .collect()
+    }
+
+    fn game_summary(&self, game_id: &str) -> Option<GameSummary> {
+        self.get_game(game_id).map(|game| GameSummary {
+            id: game_id.to_owned(),
+            name: game.name.clone(),
+            player_one: self.get_player_one(game_id),
+            player_two: self.get_player_two(game_id),
+            game_state: game.game_state.clone(),
+            opening: game.opening.clone(),
+            days_per_move: game.days_per_move,
+            move_deadline: game.move_deadline,
+            visibility: game.visibility,
+            variant: game.variant,
+            fen: game.variant.rules().fen(&game.board),
+        })
+    }
+
+    fn stored_game(&self, game_id: &str) -> Option<StoredGame> {
+        let game = self.get_game(game_id)?;
+        if game.visibility == Visibility::Private || game.variant.rules().hides_information() {
+            return None;
+        }
+
+        Some(StoredGame {
+            variant: game.variant,
+            moves: game.moves.clone(),
+            move_times: game.move_times.clone(),
+            live: true,
+        })
+    }
+
+    fn open_games(&self) -> Vec<OpenGame> {
+        self.games
+            .iter()
+            .filter(|(_, game)| game.visibility == Visibility::Public)
+            .filter_map(|(id, _)| self.open_game(id))
+            .collect()
+    }
+
+    fn open_game(&self, game_id: &str) -> Option<OpenGame> {
+        let game = self.get_game(game_id)?;
+        if game.player_two_id.is_some() || game.tournament_id.is_some() {
+            return None;
+        }
+
+        Some(OpenGame {
+            id: game_id.to_owned(),
+            name: game.name.clone(),
+            host: self.get_player_one(game_id),
+            color: game.player_one_color.opposite(),
+            rated: game.rated,
+            time_control: game.time_control,
+            days_per_move: game.days_per_move,
+        })
+    }
+
+    fn invite_token(&self, game_id: &str) -> Option<String> {
+        self.get_game(game_id)?.invite_token.clone()
+    }
+
+    fn invited_game(&self, token: &str) -> Option<String> {
+        self.games
+            .iter()
+            .find(|(_, game)| {
+                game.invite_token
+                    .as_deref()
+                    .is_some_and(|invite_token| constant_time_eq(invite_token, token))
+            })
+            .map(|(id, _)| id.clone())
+    }
+
+    fn check_admission(
+        &self,
+        game_id: &str,
+        invite_token: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<(), &'static str> {
+        let game = self.get_game(game_id).ok_or("no such game")?;
+        if game.visibility != Visibility::Private {
+            return Ok(());
+        }
+
+        let matches = |expected: &Option<String>, provided: Option<&str>| {
+            expected
+                .as_deref()
+                .zip(provided)
+                .is_some_and(|(expected, provided)| constant_time_eq(expected, provided))
+        };
+        if matches(&game.invite_token, invite_token) || matches(&game.password, password) {
+            return Ok(());
+        }
+
+        match (invite_token, password) {
+            (None, None) => {
+                Err("this game is private, join it with an invite link or its password")
+            }
+            _ => Err("the invite link or password is wrong"),
+        }
+    }
+
     fn get_player_one(&self, game_id: &str) -> Option<Player> {
         if let Some(game) = self.get_game(game_id) {
             if let Some(session) = self.get_session(&game.player_one_id) {
@@ -236,6 +1396,7 @@ impl WsServer for InMemoryServer {
                 //     color: session.color,
                 // });
             }
+            return Self::offline_player(game, &game.player_one_id, &game.player_one_user);
         }
 
         None
@@ -247,6 +1408,7 @@ impl WsServer for InMemoryServer {
                 if let Some(session) = self.get_session(player_two) {
                     return Some(session.into());
                 }
+                return Self::offline_player(game, player_two, &game.player_two_user);
             }
         }
 
@@ -255,7 +1417,161 @@ impl WsServer for InMemoryServer {
 
     fn send(&self, id: &str, msg: Message) {
         if let Some(session) = self.get_session(id) {
-            (&session.addr).do_send(msg);
+            session.addr.do_send(msg);
+        }
+    }
+
+    fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // only correspondence games can be taken back by their players after a restart
+        let games: HashMap<&String, &Game> = self
+            .games
+            .iter()
+            .filter(|(_, game)| game.days_per_move.is_some())
+            .collect();
+        fs::write(path, serde_json::to_string(&games)?)?;
+        info!(games = games.len(), path = %path.display(), "wrote game snapshot");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, moves: &str) -> bool {
+        let rules = game.variant.rules();
+        let mut repeated = false;
+        for uci in moves.split_whitespace() {
+            let mv = Move::from_uci(uci).unwrap();
+            assert!(rules.is_legal(&game.board, mv), "{uci}");
+            game.board = rules.play(&game.board, mv);
+            game.remember_position();
+            repeated = game.repeated_three_times();
         }
+
+        repeated
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut game = Game::new("", "p1".into(), None, Color::White, GameOptions::default());
+
+        // back to the starting position a second time, then a third
+        assert!(!play(&mut game, "g1f3 g8f6 f3g1 f6g8"));
+        assert!(play(&mut game, "g1f3 g8f6 f3g1 f6g8"));
+
+        // a pawn move leaves the earlier positions behind
+        assert!(!play(&mut game, "e2e4 e7e5 g1f3 g8f6 f3g1 f6g8"));
+        assert_eq!(game.repetitions.len(), 5);
+        assert!(!play(&mut game, "g1f3 g8f6 f3g1"));
+        assert!(play(&mut game, "f6g8"));
+    }
+
+    #[test]
+    fn correspondence_games_are_unrated() {
+        let options = |days_per_move| GameOptions {
+            rated: true,
+            time_control: Some(TimeControl {
+                initial_secs: 300,
+                increment_secs: 0,
+            }),
+            days_per_move,
+            ..GameOptions::default()
+        };
+        let user = Some("alice".to_owned());
+
+        let game = Game::new("", "p1".into(), user.clone(), Color::White, options(None));
+        assert!(game.rated);
+
+        let game = Game::new("", "p1".into(), user, Color::White, options(Some(3)));
+        assert!(game.time_control.is_none());
+        assert!(!game.rated);
+    }
+
+    #[test]
+    fn correspondence_deadlines_forfeit_the_side_to_move() {
+        let mut server = InMemoryServer::default();
+        let options = GameOptions {
+            days_per_move: Some(1),
+            ..GameOptions::default()
+        };
+        for (id, moves) in [("played", "e2e4 e7e5 g1f3"), ("barely_started", "e2e4")] {
+            let mut game = Game::new("", format!("{id}1"), None, Color::White, options.clone());
+            game.player_two_id = Some(format!("{id}2"));
+            play(&mut game, moves);
+            game.moves = moves.split_whitespace().map(str::to_owned).collect();
+            game.move_deadline = Some(1_000);
+            server.games.insert(id.to_owned(), game);
+        }
+
+        assert!(server.expire_correspondence_games(999).is_empty());
+        let mut finished = server.expire_correspondence_games(1_000);
+        finished.sort_by(|a, b| a.game_id.cmp(&b.game_id));
+        assert!(server.games.is_empty());
+
+        // black let the clock run out after both sides moved
+        assert_eq!(finished[1].game_id, "played");
+        assert_eq!(finished[1].result, GameResult::WhiteWins);
+        assert!(matches!(
+            finished[1].game_state.player_two.lose,
+            Some(WinLoseCondition::Overtime)
+        ));
+
+        // before that nothing was at stake
+        assert_eq!(finished[0].game_id, "barely_started");
+        assert_eq!(finished[0].result, GameResult::Aborted);
+        assert!(finished[0].game_state.player_one.lose.is_none());
+    }
+
+    #[test]
+    fn snapshots_keep_correspondence_games() {
+        let path = std::env::temp_dir()
+            .join(format!("snapshot-{}", nanoid::nanoid!(10)))
+            .join("games.json");
+        let mut server = InMemoryServer::default();
+        for (id, days_per_move) in [("correspondence", Some(3)), ("blitz", None)] {
+            let options = GameOptions {
+                days_per_move,
+                ..GameOptions::default()
+            };
+            let mut game = Game::new(
+                "",
+                format!("{id}1"),
+                Some("alice".to_owned()),
+                Color::White,
+                options,
+            );
+            game.player_two_id = Some(format!("{id}2"));
+            game.player_two_user = Some("bob".to_owned());
+            play(&mut game, "e2e4 e7e5");
+            game.moves = vec!["e2e4".to_owned(), "e7e5".to_owned()];
+            game.restart_deadline();
+            server.games.insert(id.to_owned(), game);
+        }
+
+        server.save_snapshot(&path).unwrap();
+        let restored = InMemoryServer::restore(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(restored.games.len(), 1);
+
+        let (saved, game) = (
+            &server.games["correspondence"],
+            &restored.games["correspondence"],
+        );
+        assert_eq!(game.moves, saved.moves);
+        assert_eq!(game.move_deadline, saved.move_deadline);
+        assert_eq!(game.player_two_user.as_deref(), Some("bob"));
+        assert_eq!(
+            game.variant.rules().fen(&game.board),
+            saved.variant.rules().fen(&saved.board)
+        );
+
+        // once loaded the snapshot is gone, nothing comes back a second time
+        assert!(InMemoryServer::restore(&path).unwrap().games.is_empty());
     }
 }
@@ -1,8 +1,15 @@
-use self::in_memory::{Player, PlayerStatus};
+use self::in_memory::{
+    CorrespondenceGame, FinishedGame, GameSummary, OpenGame, PlayedMove, Player, PlayerStatus,
+    SessionSummary,
+};
 
 use super::session::{Message, Session};
-use crate::types::Color;
+use crate::chess::{Move, Piece};
+use crate::replay::StoredGame;
+use crate::types::{ChessMove, Color, GameOptions, GameResult};
 use std::fmt::Debug;
+use std::io;
+use std::path::Path;
 
 pub mod in_memory;
 pub mod redis;
@@ -13,25 +20,127 @@ pub trait WsServer: Unpin + 'static + Debug {
 
     fn get_session(&self, id: &str) -> Option<&Self::Session>;
     fn create_session(&mut self, id: &str, session: Session);
-    fn delete_session(&mut self, id: &str);
+    /// Remove the session and take it out of its game
+    ///
+    /// Returns the game if leaving it ended it, which only happens in tournament games.
+    fn delete_session(&mut self, id: &str) -> Option<FinishedGame>;
+    /// Whether the session is connected and not seated in a game
+    fn is_available(&self, id: &str) -> bool;
     fn update_session_name(&mut self, id: &str, name: &str);
+    /// The account the session is logged in as
+    fn session_user(&self, id: &str) -> Option<String>;
+    fn session_ids(&self) -> Vec<String>;
+    fn session_summaries(&self) -> Vec<SessionSummary>;
+    /// Close the session's websocket, returns false if there is no such session
+    fn kick_session(&self, id: &str, reason: &str) -> bool;
 
     fn get_game(&self, id: &str) -> Option<&Self::Game>;
 
     /// Create a game and join player one to the game
     ///
     /// Returns the ID of the game if created successfully, None if not.
-    fn create_game(&mut self, name: &str, player_one: &str, color: Color) -> Option<String>;
+    fn create_game(
+        &mut self,
+        name: &str,
+        player_one: &str,
+        color: Color,
+        options: GameOptions,
+    ) -> Option<String>;
+    /// Create a game and seat both players, used when the server pairs players itself
+    ///
+    /// Returns None if either player is disconnected or already playing.
+    fn create_paired_game(
+        &mut self,
+        name: &str,
+        white_id: &str,
+        black_id: &str,
+        options: GameOptions,
+    ) -> Option<String>;
     fn join_game(&mut self, game_id: &str, player_id: &str);
-    fn leave_game(&mut self, game_id: &str, player_id: &str);
+    fn leave_game(&mut self, game_id: &str, player_id: &str) -> Option<FinishedGame>;
     fn delete_game(&mut self, id: &str);
-    fn update_game_state(&mut self, player_id: &str, player_status: PlayerStatus);
+    /// Record the status a player reported for their game. Resigning or losing on time
+    /// ends it, so does a draw both players offered. Returns None while the game goes on.
+    ///
+    /// Fails when the player isn't seated in a game or claims what only the board decides.
+    fn update_game_state(
+        &mut self,
+        player_id: &str,
+        player_status: PlayerStatus,
+    ) -> Result<Option<FinishedGame>, &'static str>;
+    /// End a game with a result decided by an administrator
+    ///
+    /// Returns None if the game doesn't exist.
+    fn terminate_game(&mut self, game_id: &str, result: GameResult) -> Option<FinishedGame>;
+    /// End a bughouse game with the result its partner board decided
+    fn conclude_linked_game(&mut self, game_id: &str, result: GameResult) -> Option<FinishedGame>;
+    /// The FEN of what the viewer is allowed to see of the game's board
+    fn board_view(&self, game_id: &str, viewer: Color) -> Option<String>;
+    /// Put a piece in a pocket of the game, returns the game's FEN afterwards
+    fn add_to_pocket(&mut self, game_id: &str, piece: Piece) -> Option<String>;
+    /// Number of games where both players have joined, correspondence games aside
+    fn active_game_count(&self) -> usize;
 
-    fn make_move(&mut self, chess_move: &str, player_id: &str);
+    /// The game the player is seated in, offline correspondence players included
+    fn game_of(&self, player_id: &str) -> Option<String>;
+    /// The player ID of the account's seat in a correspondence game, which is the
+    /// session it last played from
+    fn seat_of_user(&self, game_id: &str, user: &str) -> Option<String>;
+    /// Every correspondence game the account is seated in
+    fn correspondence_games(&self, user: &str) -> Vec<CorrespondenceGame>;
+    fn correspondence_game(&self, game_id: &str, user: &str) -> Option<CorrespondenceGame>;
+    /// End the correspondence games whose side to move let the deadline pass, that side
+    /// loses on time unless the game never really started
+    fn expire_correspondence_games(&mut self, now: u64) -> Vec<FinishedGame>;
+    /// Play a move in the player's game
+    ///
+    /// Fails when the player isn't seated in a started game, it isn't their turn or
+    /// the move is illegal.
+    fn record_move(
+        &mut self,
+        player_id: &str,
+        chess_move: Move,
+    ) -> Result<PlayedMove, &'static str>;
+    /// Relay a played move to the player's opponent, rendered for what they can see
+    fn make_move(&mut self, chess_move: &ChessMove, player_id: &str);
+    /// Queue a move to be played as soon as it is the player's turn
+    ///
+    /// Returns how many moves the player has queued.
+    fn queue_premove(&mut self, player_id: &str, chess_move: Move) -> Result<usize, &'static str>;
+    /// Forget every move the player queued
+    fn cancel_premoves(&mut self, player_id: &str);
+    /// Take the first move queued by the player whose turn it is, along with their ID
+    fn next_premove(&mut self, game_id: &str) -> Option<(String, Move)>;
+
+    fn game_summaries(&self) -> Vec<GameSummary>;
+    fn game_summary(&self, game_id: &str) -> Option<GameSummary>;
+    /// The moves of a game being played, None for private games and for variants that
+    /// keep the board from the players
+    fn stored_game(&self, game_id: &str) -> Option<StoredGame>;
+    /// Public games waiting for an opponent
+    fn open_games(&self) -> Vec<OpenGame>;
+    /// The game if it is still waiting for an opponent, whatever its visibility
+    fn open_game(&self, game_id: &str) -> Option<OpenGame>;
+    /// The token that lets players into a private game
+    fn invite_token(&self, game_id: &str) -> Option<String>;
+    /// The ID of the game the invite token belongs to
+    fn invited_game(&self, token: &str) -> Option<String>;
+    /// Whether a player who brought the invite token or password may join the game,
+    /// only private games ask for either
+    fn check_admission(
+        &self,
+        game_id: &str,
+        invite_token: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<(), &'static str>;
 
     fn get_player_one(&self, game_id: &str) -> Option<Player>;
     fn get_player_two(&self, game_id: &str) -> Option<Player>;
 
     /// Send a message to a session actor
     fn send(&self, id: &str, msg: Message);
+
+    /// Persist the games their players can take back after a restart, which are the
+    /// correspondence games since those are seated by account
+    fn save_snapshot(&self, path: &Path) -> io::Result<()>;
 }
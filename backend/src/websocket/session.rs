@@ -2,8 +2,13 @@ use actix::prelude::*;
 use actix::{Actor, Addr, Handler, Recipient, StreamHandler};
 use actix_web_actors::ws::{self, CloseReason};
 use nanoid::nanoid;
+use tracing::{debug, info_span, warn, Span};
 
-use super::messages::{MakeMove, UpdateGameState};
+use super::messages::{
+    AcceptRematch, Berserk, CancelPremoves, CreateBughouse, CreateTournament, DeclineRematch,
+    JoinBughouse, JoinTournament, MakeMove, OfferRematch, Premove, PuzzleMove, RequestPuzzle,
+    StartReplay, StartTournament, StopReplay, TeamChat, UpdateGameState,
+};
 use super::{
     messages::{ClientMessage, Connect, CreateGame, Disconnect, JoinGame, Type, UpdateName},
     server::WsChessServer,
@@ -15,13 +20,22 @@ use crate::types::Color;
 #[rtype(result = "()")]
 pub struct Message(pub String);
 
+/// Close the websocket with the given reason
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Close(pub String);
+
 #[derive(Debug)]
 pub struct Session {
     pub id: String,
     /// The address of the session actor
     pub addr: Recipient<Message>,
+    /// Used to disconnect the session from the server side
+    pub close_addr: Recipient<Close>,
     /// The name of the player
     pub name: String,
+    /// The account this websocket logged in with, guests don't have one
+    pub user: Option<String>,
     pub joined_game: Option<String>,
     pub color: Color,
 }
@@ -29,13 +43,21 @@ pub struct Session {
 pub struct SessionActor<T: WsServer> {
     pub id: String,
     pub server_addr: Addr<WsChessServer<T>>,
+    /// Username of the authenticated player, None for guests
+    pub user: Option<String>,
+    /// Every event logged on behalf of this websocket is recorded inside this span
+    span: Span,
 }
 
 impl<T: WsServer> SessionActor<T> {
-    pub fn new(server_addr: Addr<WsChessServer<T>>) -> Self {
+    pub fn new(server_addr: Addr<WsChessServer<T>>, user: Option<String>) -> Self {
+        let id = nanoid!(10);
+
         Self {
-            id: nanoid!(10),
+            span: info_span!("session", session_id = %id, user = user.as_deref()),
+            id,
             server_addr,
+            user,
         }
     }
 }
@@ -46,10 +68,18 @@ impl<T: WsServer> Actor for SessionActor<T> {
     // Whenever the actor is started, we send a Connect message with
     // the current actor's address to the WsChessServer actor to register a new session
     fn started(&mut self, ctx: &mut Self::Context) {
+        let _span = self.span.enter();
+        debug!("websocket opened");
+
         let addr = ctx.address();
 
         self.server_addr
-            .send(Connect::new(self.id.clone(), addr.recipient()))
+            .send(Connect::new(
+                self.id.clone(),
+                self.user.clone(),
+                addr.clone().recipient(),
+                addr.recipient(),
+            ))
             .into_actor(self)
             .then(|res, _, ctx| {
                 match res {
@@ -62,6 +92,9 @@ impl<T: WsServer> Actor for SessionActor<T> {
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        let _span = self.span.enter();
+        debug!("websocket closing");
+
         self.server_addr.do_send(Disconnect::new(self.id.clone()));
         Running::Stop
     }
@@ -76,18 +109,35 @@ impl<T: WsServer> Handler<Message> for SessionActor<T> {
     }
 }
 
+impl<T: WsServer> Handler<Close> for SessionActor<T> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Close, ctx: &mut Self::Context) -> Self::Result {
+        let _span = self.span.enter();
+        debug!(reason = %msg.0, "closing websocket from the server");
+
+        ctx.close(Some(CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.0),
+        }));
+        ctx.stop();
+    }
+}
+
 impl<T: WsServer> StreamHandler<Result<ws::Message, ws::ProtocolError>> for SessionActor<T> {
     fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         let msg = item.expect("Unable to handle Websocket message");
 
         match msg {
             ws::Message::Text(text) => {
                 match parse_text(text.into(), &self.id, &self.server_addr) {
                     Ok(_) => {}
-                    Err(_) => {
+                    Err(e) => {
+                        warn!(error = %e, "unable to parse client message, closing websocket");
                         ctx.close(Some(CloseReason {
                             code: ws::CloseCode::Normal,
-                            description: Some(format!("Error")),
+                            description: Some("Error".to_string()),
                         }));
                         ctx.stop();
                     }
@@ -136,12 +186,104 @@ fn parse_text<T: WsServer>(
             server_addr.do_send(msg);
         }
 
+        Type::Premove => {
+            let mut msg = serde_json::from_value::<Premove>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::CancelPremoves => {
+            let mut msg = serde_json::from_value::<CancelPremoves>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
         Type::UpdateGameState => {
             let mut msg = serde_json::from_value::<UpdateGameState>(msg.payload)?;
             msg.player_id = id.to_owned();
             server_addr.do_send(msg);
         }
 
+        Type::OfferRematch => {
+            let mut msg = serde_json::from_value::<OfferRematch>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::AcceptRematch => {
+            let mut msg = serde_json::from_value::<AcceptRematch>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::DeclineRematch => {
+            let mut msg = serde_json::from_value::<DeclineRematch>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::CreateTournament => {
+            let mut msg = serde_json::from_value::<CreateTournament>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::JoinTournament => {
+            let mut msg = serde_json::from_value::<JoinTournament>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::StartTournament => {
+            let mut msg = serde_json::from_value::<StartTournament>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::Berserk => {
+            let mut msg = serde_json::from_value::<Berserk>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::CreateBughouse => {
+            let mut msg = serde_json::from_value::<CreateBughouse>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::JoinBughouse => {
+            let mut msg = serde_json::from_value::<JoinBughouse>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
+        Type::TeamChat => {
+            let mut msg = serde_json::from_value::<TeamChat>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+        Type::RequestPuzzle => {
+            let mut msg = serde_json::from_value::<RequestPuzzle>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+        Type::PuzzleMove => {
+            let mut msg = serde_json::from_value::<PuzzleMove>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+        Type::StartReplay => {
+            let mut msg = serde_json::from_value::<StartReplay>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+        Type::StopReplay => {
+            let mut msg = serde_json::from_value::<StopReplay>(msg.payload)?;
+            msg.player_id = id.to_owned();
+            server_addr.do_send(msg);
+        }
+
         _ => {}
     }
 
@@ -0,0 +1,45 @@
+#!/bin/sh
+# Stands in for a UCI engine in the engine tests, answering with canned lines.
+#
+# What a search does depends on the depth it is asked for:
+#   go depth 1   two lines of analysis, then bestmove e2e4
+#   go depth 2   no legal move, bestmove (none)
+#   go depth 3   never answers
+#   go depth 4   exits in the middle of the search
+
+while read -r command args; do
+    case "$command" in
+    uci)
+        echo "id name Mock Engine 1.0"
+        echo "id author nobody"
+        echo "option name MultiPV type spin default 1 min 1 max 500"
+        echo "uciok"
+        ;;
+    isready)
+        echo "readyok"
+        ;;
+    go)
+        case "$args" in
+        "depth 1")
+            echo "info depth 1 seldepth 1 multipv 1 score cp 12 nodes 20 pv e2e4"
+            echo "info depth 5 seldepth 7 multipv 2 score mate -3 nodes 400 pv f2f3 e7e5 g2g4 d8h4"
+            echo "info depth 5 seldepth 7 multipv 1 score cp 35 nodes 400 pv e2e4 e7e5 g1f3"
+            echo "info string searched 400 nodes"
+            echo "bestmove e2e4 ponder e7e5"
+            ;;
+        "depth 2")
+            echo "info depth 0 score mate 0"
+            echo "bestmove (none)"
+            ;;
+        "depth 3")
+            ;;
+        *)
+            exit 1
+            ;;
+        esac
+        ;;
+    quit)
+        exit 0
+        ;;
+    esac
+done